@@ -0,0 +1,71 @@
+use criterion::*;
+use mio::net::UdpSocket;
+use rust_ipv8::networking::address::Address;
+use rust_ipv8::networking::batch::{send_batch, RecvBatch};
+use rust_ipv8::serialization::Packet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// The number of packets sent and received per iteration
+const PACKETS: usize = 32;
+
+fn localhost() -> Address {
+    Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+}
+
+/// Builds the packets sent every iteration, all to the same address
+fn packets(to: Address) -> Vec<(Address, Packet)> {
+    (0..PACKETS).map(|_| (to, Packet(vec![42; 200]))).collect()
+}
+
+/// Sends a batch of packets over localhost and receives them again, once with one system call per packet and once with
+/// one system call per batch. Both send the same packets from a plain socket, so they only differ in the system calls.
+/// Where the kernel supports it the batch is segmented by the kernel, as the packets are equal.
+fn throughput(c: &mut Criterion) {
+    c.bench(
+        "udp",
+        Benchmark::new("one-by-one", |b| {
+            let sender = UdpSocket::bind(&localhost().0).unwrap();
+            let receiver = UdpSocket::bind(&localhost().0).unwrap();
+            let packets = packets(Address(receiver.local_addr().unwrap()));
+            let mut buffer = vec![0; 2048];
+
+            b.iter(|| {
+                for (to, packet) in &packets {
+                    sender.send_to(packet.raw(), &to.0).unwrap();
+                }
+                let mut received = 0;
+                while received < PACKETS {
+                    if receiver.recv_from(&mut buffer).is_ok() {
+                        received += 1;
+                    }
+                }
+            })
+        })
+        .throughput(Throughput::Elements(PACKETS as u32)),
+    );
+
+    c.bench(
+        "udp",
+        Benchmark::new("batched", |b| {
+            let sender = UdpSocket::bind(&localhost().0).unwrap();
+            let receiver = UdpSocket::bind(&localhost().0).unwrap();
+            let packets = packets(Address(receiver.local_addr().unwrap()));
+            let mut batch = RecvBatch::new(PACKETS, 2048);
+
+            b.iter(|| {
+                let mut sent = 0;
+                while sent < PACKETS {
+                    sent += send_batch(&sender, &packets[sent..]).unwrap();
+                }
+                let mut received = 0;
+                while received < PACKETS {
+                    received += batch.recv(&receiver).unwrap();
+                }
+            })
+        })
+        .throughput(Throughput::Elements(PACKETS as u32)),
+    );
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,561 @@
+//! Communities which handle one message at a time with mutable access to their state.
+//!
+//! A [Community] handles messages on many threads at once, so all of its state has to be behind locks. An
+//! [ActorCommunity] is a single-threaded actor instead: the [CommunityActor] it is wrapped in queues its messages in a
+//! mailbox, and a single job hands them to the community one at a time, in the order they were received. Its handlers
+//! take `&mut self`. Different communities still handle their messages in parallel.
+//!
+//! The job runs on the rayon pool, or on the blocking threads of the tokio runtime for a node created
+//! [with_tokio](crate::IPv8::with_tokio), so such a node does not start the rayon pool for its actors.
+//!
+//! ```ignore
+//! let actor = CommunityActor::<MyCommunity>::new(&ipv8.endpoint, &ipv8.task_manager)?;
+//! ipv8.communities.add_community(Box::new(actor))?;
+//! ```
+
+use crate::community::peer::{MasterPeer, MyPeer};
+use crate::community::{Community, SignaturePolicy};
+use crate::crypto::signature::Ed25519PublicKey;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::networking::endpoint::Endpoint;
+use crate::serialization::header::Header;
+use crate::serialization::PacketDeserializer;
+use crate::taskmanager::TaskManager;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The number of messages waiting in a mailbox before new ones are dropped, unless set with
+/// [with_mailbox_size](CommunityActor::with_mailbox_size)
+pub const DEFAULT_MAILBOX_SIZE: usize = 1024;
+
+/// A community which handles one message at a time, see the [module documentation](crate::community::actor).
+///
+/// The methods are the same as those of [Community], except that handlers get mutable access. The registry never
+/// waits for the community: the [signature_policy](ActorCommunity::signature_policy) of every message type is asked
+/// once, and messages signed by a [known peer](ActorCommunity::known_peer_key) are checked by the actor itself, right
+/// before they are handled.
+pub trait ActorCommunity: Send + 'static {
+    /// Every community should have a constructor, see [Community::new]
+    fn new(
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        Self: Sized;
+
+    /// Constructs the community with the identity of the node, see [Community::with_identity]
+    fn with_identity(
+        _my_peer: MyPeer,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        Self: Sized,
+    {
+        Self::new(endpoint, task_manager)
+    }
+
+    /// Returns the identity this community signs its messages with, see [Community::my_peer]. It is asked once, when
+    /// the community is wrapped in its [CommunityActor], like the [master_peer](ActorCommunity::master_peer) and the
+    /// [mid](ActorCommunity::get_mid).
+    fn my_peer(&self) -> Option<&MyPeer> {
+        None
+    }
+
+    /// Returns the master peer of this community, see [Community::master_peer]
+    fn master_peer(&self) -> Option<&MasterPeer> {
+        None
+    }
+
+    /// Returns a unique sequence identifying the community, see [Community::get_mid]
+    fn get_mid(&self) -> Vec<u8>;
+
+    /// Returns how messages of the given type have to be signed, see [Community::signature_policy]. It is asked for
+    /// every message type once, when the community is wrapped in its [CommunityActor].
+    fn signature_policy(&self, _message_type: u8) -> SignaturePolicy {
+        SignaturePolicy::Signed
+    }
+
+    /// Returns the public key of the peer this community knows at the given address, see [Community::known_peer_key].
+    /// It is asked between two messages, to check a message with the
+    /// [SignedByKnownPeer](SignaturePolicy::SignedByKnownPeer) policy before it is handled.
+    fn known_peer_key(&self, _address: &Address) -> Option<Ed25519PublicKey> {
+        None
+    }
+
+    /// Called when the community is removed from the registry, after the message being handled at that moment.
+    /// Messages still in the mailbox are dropped.
+    fn unload(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called for every message directed at this community, in the order they were received
+    fn on_receive(
+        &mut self,
+        header: Header,
+        deserializer: PacketDeserializer,
+        address: Address,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// The messages waiting for a community
+struct Mailbox {
+    /// The waiting messages, oldest first
+    messages: VecDeque<(Header, PacketDeserializer, Address)>,
+    /// True while a job handling the messages is running or about to run
+    scheduled: bool,
+    /// Set once the community is unloaded, no new messages are accepted after that
+    unloaded: bool,
+}
+
+/// The part of a [CommunityActor] the job handling its messages holds on to
+struct Actor<C> {
+    /// The community. Locked by the job for every message it handles, and when the community is unloaded.
+    community: Mutex<C>,
+    /// The messages waiting to be handled
+    mailbox: Mutex<Mailbox>,
+    /// The max number of waiting messages
+    mailbox_size: usize,
+    /// The signature policy of every message type, which does not change
+    policies: Vec<SignaturePolicy>,
+    /// The number of messages the community failed to handle
+    failed_messages: AtomicUsize,
+}
+
+impl<C: ActorCommunity> Actor<C> {
+    /// Handles messages until the mailbox is empty
+    fn run(&self) {
+        loop {
+            // The community is locked before a message is taken from the mailbox, like unload does, so no message is
+            // handled once it is unloaded
+            let mut community = match self.community.lock() {
+                Ok(community) => community,
+                Err(_) => {
+                    error!(
+                        "A community actor stopped: {}",
+                        Ipv8Error::LockPoisoned("the community actor")
+                    );
+                    return;
+                }
+            };
+            let message = match self.mailbox.lock() {
+                Ok(mut mailbox) => match mailbox.messages.pop_front() {
+                    Some(message) => message,
+                    None => {
+                        mailbox.scheduled = false;
+                        return;
+                    }
+                },
+                Err(_) => {
+                    error!(
+                        "A community actor stopped: {}",
+                        Ipv8Error::LockPoisoned("the community actor")
+                    );
+                    return;
+                }
+            };
+
+            let (header, mut deserializer, address) = message;
+            if self.policies[header.message_type.unwrap_or(0) as usize]
+                == SignaturePolicy::SignedByKnownPeer
+                && !verify_known_peer(&*community, &header, &mut deserializer, &address)
+            {
+                self.failed_messages.fetch_add(1, Ordering::SeqCst);
+                debug!(
+                    "Dropped a message from {:?} which is not signed by the peer known there",
+                    address
+                );
+                continue;
+            }
+            if let Err(e) = community.on_receive(header, deserializer, address) {
+                self.failed_messages.fetch_add(1, Ordering::SeqCst);
+                warn!(
+                    "A community failed to handle a message from {:?}: {}",
+                    address, e
+                );
+            }
+        }
+    }
+}
+
+/// Runs an [ActorCommunity] as a [Community], so it can be added to the
+/// [CommunityRegistry](crate::community::CommunityRegistry).
+pub struct CommunityActor<C> {
+    /// The mid of the community, which does not change
+    mid: Vec<u8>,
+    /// The identity of the community, which does not change
+    my_peer: Option<MyPeer>,
+    /// The master peer of the community, which does not change
+    master_peer: Option<MasterPeer>,
+    /// The community and its mailbox
+    actor: Arc<Actor<C>>,
+}
+
+impl<C: ActorCommunity> CommunityActor<C> {
+    /// Wraps an existing community with a mailbox of [DEFAULT_MAILBOX_SIZE]
+    pub fn from_community(community: C) -> Self {
+        Self::with_mailbox_size(community, DEFAULT_MAILBOX_SIZE)
+    }
+
+    /// Wraps an existing community with a mailbox holding at most `mailbox_size` messages
+    pub fn with_mailbox_size(community: C, mailbox_size: usize) -> Self {
+        Self {
+            mid: community.get_mid(),
+            my_peer: community.my_peer().cloned(),
+            master_peer: community.master_peer().cloned(),
+            actor: Arc::new(Actor {
+                policies: (0..=u8::MAX)
+                    .map(|message_type| community.signature_policy(message_type))
+                    .collect(),
+                community: Mutex::new(community),
+                mailbox: Mutex::new(Mailbox {
+                    messages: VecDeque::new(),
+                    scheduled: false,
+                    unloaded: false,
+                }),
+                mailbox_size: mailbox_size.max(1),
+                failed_messages: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Returns the number of messages the community failed to handle. As they are handled later, these failures can
+    /// not be reported to the registry.
+    pub fn failed_messages(&self) -> usize {
+        self.actor.failed_messages.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of messages waiting to be handled
+    pub fn waiting_messages(&self) -> Result<usize, Ipv8Error> {
+        Ok(self
+            .actor
+            .mailbox
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the community actor")))?
+            .messages
+            .len())
+    }
+}
+
+impl<C: ActorCommunity> Community for CommunityActor<C> {
+    fn new(
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_community(C::new(endpoint, task_manager)?))
+    }
+
+    fn with_identity(
+        my_peer: MyPeer,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_community(C::with_identity(
+            my_peer,
+            endpoint,
+            task_manager,
+        )?))
+    }
+
+    fn my_peer(&self) -> Option<&MyPeer> {
+        self.my_peer.as_ref()
+    }
+
+    fn master_peer(&self) -> Option<&MasterPeer> {
+        self.master_peer.as_ref()
+    }
+
+    fn get_mid(&self) -> Vec<u8> {
+        self.mid.clone()
+    }
+
+    /// Messages signed by a known peer are checked by the actor, as only it can ask the community for the peer
+    fn signature_policy(&self, message_type: u8) -> SignaturePolicy {
+        match self.actor.policies[message_type as usize] {
+            SignaturePolicy::SignedByKnownPeer => SignaturePolicy::Unsigned,
+            policy => policy,
+        }
+    }
+
+    /// The known peers are only asked by the actor itself, this never waits for the community
+    fn known_peer_key(&self, _address: &Address) -> Option<Ed25519PublicKey> {
+        None
+    }
+
+    fn unload(&self) -> Result<(), Box<dyn Error>> {
+        let mut community = self
+            .actor
+            .community
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the community actor")))?;
+        {
+            let mut mailbox = self
+                .actor
+                .mailbox
+                .lock()
+                .or(Err(Ipv8Error::LockPoisoned("the community actor")))?;
+            mailbox.unloaded = true;
+            mailbox.messages.clear();
+        }
+        community.unload()
+    }
+
+    /// Puts the message in the mailbox, and starts a job handling the mailbox if there is none
+    fn on_receive(
+        &self,
+        header: Header,
+        deserializer: PacketDeserializer,
+        address: Address,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut mailbox = self
+            .actor
+            .mailbox
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the community actor")))?;
+        if mailbox.unloaded || mailbox.messages.len() >= self.actor.mailbox_size {
+            return Err(Box::new(Ipv8Error::MailboxFull {
+                mid: self.mid.clone(),
+            }));
+        }
+        mailbox.messages.push_back((header, deserializer, address));
+
+        if !mailbox.scheduled {
+            mailbox.scheduled = true;
+            spawn(Arc::clone(&self.actor));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the message is signed by the peer the community knows at the address it came from. The deserializer is
+/// left positioned at the header.
+fn verify_known_peer<C: ActorCommunity>(
+    community: &C,
+    header: &Header,
+    deserializer: &mut PacketDeserializer,
+    address: &Address,
+) -> bool {
+    let key = match community.known_peer_key(address) {
+        Some(key) => key,
+        None => return false,
+    };
+    let start = deserializer.index;
+    deserializer.index += header.size;
+    let valid = deserializer.verify_with(key);
+    deserializer.index = start;
+    valid
+}
+
+/// Starts a job handling the mailbox of the actor. It runs on the blocking threads of the tokio runtime the message was
+/// received on, and on the rayon pool when there is none.
+fn spawn<C: ActorCommunity>(actor: Arc<Actor<C>>) {
+    #[cfg(feature = "tokio")]
+    {
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn_blocking(move || actor.run());
+            return;
+        }
+    }
+    rayon::spawn(move || actor.run());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::community::CommunityRegistry;
+    use crate::crypto::signature::KeyPair;
+    use crate::networking::test_helper::{localhost, localhost_endpoint, wait_for};
+    use crate::serialization::Packet;
+
+    /// Remembers the order of its messages without any locks of its own
+    struct OrderedCommunity {
+        received: Vec<u8>,
+        shared: Arc<Mutex<Vec<u8>>>,
+        unloaded: Arc<AtomicUsize>,
+        thread: Arc<Mutex<Option<String>>>,
+        known_peer: Option<Ed25519PublicKey>,
+    }
+
+    impl ActorCommunity for OrderedCommunity {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
+            Ok(OrderedCommunity {
+                received: vec![],
+                shared: Arc::new(Mutex::new(vec![])),
+                unloaded: Arc::new(AtomicUsize::new(0)),
+                thread: Arc::new(Mutex::new(None)),
+                known_peer: None,
+            })
+        }
+
+        fn get_mid(&self) -> Vec<u8> {
+            vec![3; 20]
+        }
+
+        fn signature_policy(&self, message_type: u8) -> SignaturePolicy {
+            if message_type >= 200 {
+                SignaturePolicy::SignedByKnownPeer
+            } else {
+                SignaturePolicy::Unsigned
+            }
+        }
+
+        fn known_peer_key(&self, _address: &Address) -> Option<Ed25519PublicKey> {
+            self.known_peer
+        }
+
+        fn unload(&mut self) -> Result<(), Box<dyn Error>> {
+            self.unloaded.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_receive(
+            &mut self,
+            header: Header,
+            _deserializer: PacketDeserializer,
+            _address: Address,
+        ) -> Result<(), Box<dyn Error>> {
+            self.received.push(header.message_type.unwrap() as u8);
+            *self.shared.lock().unwrap() = self.received.clone();
+            *self.thread.lock().unwrap() = std::thread::current().name().map(String::from);
+            if header.message_type == Some(13) {
+                return Err("The test community fails on message 13".into());
+            }
+            Ok(())
+        }
+    }
+
+    /// Waits until the actor handled all of its messages
+    fn wait_idle<C: ActorCommunity>(actor: &CommunityActor<C>) {
+        assert!(
+            wait_for(|| !actor.actor.mailbox.lock().unwrap().scheduled),
+            "the actor did not finish"
+        );
+    }
+
+    #[test]
+    fn test_ordered_messages() {
+        let community = OrderedCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let shared = Arc::clone(&community.shared);
+        let actor = Arc::new(CommunityActor::from_community(community));
+        let registry = CommunityRegistry::default();
+        registry
+            .add_community(Box::new(Arc::clone(&actor)))
+            .unwrap();
+
+        let address = localhost();
+        for message_type in 0..100 {
+            registry
+                .forward_message(
+                    Packet::new(Header::py_ipv8_header([3; 20], message_type)).unwrap(),
+                    address,
+                )
+                .unwrap();
+        }
+        wait_idle(&actor);
+
+        assert_eq!(*shared.lock().unwrap(), (0..100).collect::<Vec<u8>>());
+        assert_eq!(actor.failed_messages(), 1);
+        assert_eq!(actor.waiting_messages().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_signed_by_known_peer() {
+        let keypair = KeyPair::new().unwrap();
+        let mut community = OrderedCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        community.known_peer = Some(keypair.public_key().unwrap());
+        let shared = Arc::clone(&community.shared);
+        let actor = Arc::new(CommunityActor::from_community(community));
+        let registry = CommunityRegistry::default();
+        registry
+            .add_community(Box::new(Arc::clone(&actor)))
+            .unwrap();
+
+        // the registry does not wait for the community, even while it is handling a message
+        let guard = actor.actor.community.lock().unwrap();
+        let signed = |message_type, keypair: &KeyPair| {
+            Packet::new(Header::py_ipv8_header([3; 20], message_type))
+                .unwrap()
+                .sign(keypair)
+                .unwrap()
+        };
+        registry
+            .forward_message(signed(200, &keypair), localhost())
+            .unwrap();
+        registry
+            .forward_message(signed(201, &KeyPair::new().unwrap()), localhost())
+            .unwrap();
+        drop(guard);
+        wait_idle(&actor);
+
+        // the actor checks the signatures before handling the messages
+        assert_eq!(*shared.lock().unwrap(), vec![200]);
+        assert_eq!(actor.failed_messages(), 1);
+    }
+
+    /// Returns a message as the registry passes it to a community
+    fn message() -> (Header, PacketDeserializer, Address) {
+        let packet = Packet::new(Header::py_ipv8_header([3; 20], 1)).unwrap();
+        let deserializer = packet.start_deserialize();
+        (
+            deserializer.peek_header().unwrap(),
+            deserializer,
+            localhost(),
+        )
+    }
+
+    #[test]
+    fn test_mailbox_size() {
+        let community = OrderedCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let actor = CommunityActor::with_mailbox_size(community, 2);
+
+        // hold the community so nothing is handled
+        let guard = actor.actor.community.lock().unwrap();
+        for _ in 0..2 {
+            let (header, deserializer, address) = message();
+            actor.on_receive(header, deserializer, address).unwrap();
+        }
+        let (header, deserializer, address) = message();
+        assert!(actor.on_receive(header, deserializer, address).is_err());
+        drop(guard);
+        wait_idle(&actor);
+    }
+
+    #[test]
+    fn test_unload() {
+        let community = OrderedCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let unloaded = Arc::clone(&community.unloaded);
+        let actor = CommunityActor::from_community(community);
+
+        actor.unload().unwrap();
+        assert_eq!(unloaded.load(Ordering::SeqCst), 1);
+
+        let (header, deserializer, address) = message();
+        assert!(actor.on_receive(header, deserializer, address).is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .thread_name("actor-test")
+            .build()
+            .unwrap();
+        let community = OrderedCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let thread = Arc::clone(&community.thread);
+        let actor = CommunityActor::from_community(community);
+
+        // messages received on a runtime are handled on its blocking threads instead of on the rayon pool
+        runtime.block_on(async {
+            let (header, deserializer, address) = message();
+            actor.on_receive(header, deserializer, address).unwrap();
+        });
+        wait_idle(&actor);
+        assert_eq!(thread.lock().unwrap().as_deref(), Some("actor-test"));
+    }
+}
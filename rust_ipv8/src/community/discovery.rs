@@ -0,0 +1,1212 @@
+//! The built-in DiscoveryCommunity. Every py-ipv8 node is part of this community and uses it to find other peers.
+//!
+//! This is a port of the introduction and NAT puncturing logic of py-ipv8's
+//! [DiscoveryCommunity](https://github.com/Tribler/py-ipv8/blob/master/ipv8/peerdiscovery/community.py).
+//! The message flow is as follows:
+//!
+//! 1. Peer A sends an introduction request (246) to us.
+//! 2. We answer with a signed introduction response (245) which, if we know any other peer B, contains the addresses of B.
+//! 3. At the same time we send a puncture request (250) to B, asking it to contact A.
+//! 4. B sends a puncture (249) to A which punches a hole in the NAT of B, after which A can contact B.
+
+use crate::community::globaltime::GlobalTime;
+use crate::community::handlers::MessageHandlers;
+use crate::community::messagebuilder::{MessageBuilder, SigningCommunity};
+use crate::community::peer::{MyPeer, Peer};
+use crate::community::{Community, SignaturePolicy};
+use crate::configuration::Config;
+use crate::crypto::signature::{Ed25519PublicKey, KeyPair};
+use crate::error::Ipv8Error;
+use crate::networking::address::{Address, Ipv6Address};
+use crate::networking::endpoint::Endpoint;
+use crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
+use crate::payloads::connectiontype::ConnectionType;
+use crate::payloads::introductionrequestpayload::IntroductionRequestPayload;
+use crate::payloads::introductionresponsepayload::IntroductionResponsePayload;
+use crate::payloads::pingpayload::PingPayload;
+use crate::payloads::puncturepayload::PuncturePayload;
+use crate::payloads::puncturerequestpayload::PunctureRequestPayload;
+use crate::payloads::timedistributionpayload::TimeDistributionPayload;
+use crate::peerdiscovery::network::Network;
+use crate::peerdiscovery::Overlay;
+use crate::requestcache::{NumberCache, RequestCache};
+use crate::serialization::header::Header;
+use crate::serialization::rawend::RawEnd;
+use crate::serialization::Packet;
+use crate::taskmanager::TaskManager;
+use rand::seq::IteratorRandom;
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// The mid of the py-ipv8 DiscoveryCommunity. This is the sha1 hash of its (OpenSSL) master peer key
+/// and is hardcoded to be compatible with py-ipv8.
+pub const DISCOVERY_MID: [u8; 20] = [
+    0x7e, 0x31, 0x36, 0x85, 0xc1, 0x91, 0x2a, 0x14, 0x12, 0x79, 0xf8, 0x24, 0x8f, 0xc8, 0xdb, 0x58,
+    0x99, 0xc5, 0xdf, 0x5a,
+];
+
+/// Message type of an [IntroductionRequestPayload] message
+pub const INTRODUCTION_REQUEST: u8 = 246;
+/// Message type of an [IntroductionResponsePayload] message
+pub const INTRODUCTION_RESPONSE: u8 = 245;
+/// Message type of a [PunctureRequestPayload] message
+pub const PUNCTURE_REQUEST: u8 = 250;
+/// Message type of a [PuncturePayload] message
+pub const PUNCTURE: u8 = 249;
+/// Message type of a [PingPayload] message sent as a ping
+pub const PING: u8 = 3;
+/// Message type of a [PingPayload] message sent as a pong
+pub const PONG: u8 = 4;
+
+/// The tag in front of an IPv6 introduction in the extra bytes of an introduction response. The extra bytes of other
+/// implementations are not mistaken for an introduction this way.
+const IPV6_INTRODUCTION_TAG: &[u8] = b"rust-ipv8/ipv6/1";
+
+/// The size of a serialized [Ipv6Address]
+const IPV6_ADDRESS_SIZE: usize = 18;
+
+/// Returns the extra bytes introducing a peer at an IPv6 address, which does not fit in the introduction addresses
+fn ipv6_introduction(address: &Address) -> Result<Vec<u8>, Ipv8Error> {
+    let mut extra_bytes = IPV6_INTRODUCTION_TAG.to_vec();
+    extra_bytes.extend(
+        bincode::config()
+            .big_endian()
+            .serialize(&Ipv6Address(*address))?,
+    );
+    Ok(extra_bytes)
+}
+
+/// Returns the IPv6 address introduced in the extra bytes of an introduction response, if they hold a valid one
+fn parse_ipv6_introduction(extra_bytes: &[u8]) -> Option<Address> {
+    if extra_bytes.len() != IPV6_INTRODUCTION_TAG.len() + IPV6_ADDRESS_SIZE
+        || !extra_bytes.starts_with(IPV6_INTRODUCTION_TAG)
+    {
+        return None;
+    }
+    let introduced: Ipv6Address = bincode::config()
+        .big_endian()
+        .deserialize(&extra_bytes[IPV6_INTRODUCTION_TAG.len()..])
+        .ok()?;
+    let ip = introduced.0 .0.ip();
+    if !introduced.0.is_ipv6()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || introduced.0 .0.port() == 0
+    {
+        return None;
+    }
+    Some(introduced.0)
+}
+
+/// The address py-ipv8 uses to signal "no address"
+fn empty_address() -> Address {
+    Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0))
+}
+
+/// Returns true if the address is not reachable from outside of the local network
+fn address_is_lan(address: &Address) -> bool {
+    match address.normalized().0.ip() {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
+
+/// Returns the address of the interface we can be reached at inside the local network, like py-ipv8's
+/// `get_lan_address`. When the socket is bound to the unspecified address, that is the interface the route to a public
+/// address leaves from. Connecting a UDP socket only looks up that route, nothing is sent. Without such a route only
+/// this host can reach us.
+fn lan_address(bound: &Address) -> Address {
+    if !bound.0.ip().is_unspecified() {
+        return bound.normalized();
+    }
+    let ip = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80))?;
+            socket.local_addr()
+        })
+        .map(|local| local.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    Address(SocketAddr::new(ip, bound.0.port()))
+}
+
+/// Returns the address as it can be put in the IPv4 address fields of a payload.
+/// IPv6 addresses don't fit and become the [empty address](empty_address), except for the unspecified address of a
+/// dual-stack socket which becomes the unspecified IPv4 address.
+fn payload_address(address: &Address) -> Address {
+    match address.normalized().0.ip() {
+        IpAddr::V6(ip) if ip.is_unspecified() => Address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            address.0.port(),
+        )),
+        IpAddr::V6(_) => empty_address(),
+        IpAddr::V4(_) => address.normalized(),
+    }
+}
+
+/// The connection type we report to a peer. When we talk to it over IPv6, our addresses don't fit in the payload.
+fn connection_type(peer: &Address) -> ConnectionType {
+    if peer.is_ipv6() {
+        ConnectionType::IPV6
+    } else {
+        ConnectionType::UNKNOWN
+    }
+}
+
+/// The prefix of the [PingCache] in the [RequestCache] of the DiscoveryCommunity
+const PING_PREFIX: &str = "discovery-ping";
+
+/// A ping which was not answered yet
+struct PingCache {
+    /// The address the ping was sent to
+    address: Address,
+    /// The time the ping was sent, to measure the round trip time
+    sent: Instant,
+}
+
+impl NumberCache for PingCache {
+    fn prefix(&self) -> &'static str {
+        PING_PREFIX
+    }
+
+    /// Unanswered pings are counted as failures by the [RandomChurn](crate::peerdiscovery::churn::RandomChurn), so
+    /// there is nothing left to do
+    fn on_timeout(&mut self) {
+        debug!("Ping to {:?} timed out", self.address);
+    }
+}
+
+/// The DiscoveryCommunity answers introduction requests and handles NAT puncturing, exactly like py-ipv8 does.
+/// It is registered by default by [IPv8::new](crate::IPv8::new), which also adds a
+/// [RandomWalk](crate::peerdiscovery::discovery::RandomWalk) over it.
+pub struct DiscoveryCommunity {
+    /// The identity all outgoing messages are signed with
+    my_peer: MyPeer,
+    /// Used to send responses to other peers
+    endpoint: Arc<dyn Endpoint>,
+    /// Our address inside our local network
+    lan_address: Address,
+    /// The lamport clock of this community
+    global_time: Arc<GlobalTime>,
+    /// All peers we know of. The verified ones are introduced to others.
+    network: RwLock<Network>,
+    /// The addresses to contact when we don't know any peers yet
+    bootstrap_addresses: Vec<Address>,
+    /// The pings which were not answered yet, by identifier
+    pings: RequestCache,
+}
+
+impl DiscoveryCommunity {
+    /// Creates a DiscoveryCommunity with the given identity which bootstraps from the given addresses.
+    /// [Community::with_identity](crate::community::Community::with_identity) uses the default hosts of the default
+    /// [Config], and [Community::new](crate::community::Community::new) generates an identity as well.
+    pub fn with_bootstrap_addresses(
+        my_peer: MyPeer,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+        bootstrap_addresses: Vec<Address>,
+    ) -> Result<Self, Ipv8Error> {
+        let endpoint = Arc::clone(endpoint);
+        let lan_address = lan_address(&endpoint.local_address()?);
+
+        Ok(DiscoveryCommunity {
+            my_peer,
+            endpoint,
+            lan_address,
+            global_time: Arc::new(GlobalTime::default()),
+            network: RwLock::new(Network::with_clock(task_manager.clock())),
+            bootstrap_addresses,
+            pings: RequestCache::new(Arc::clone(task_manager)),
+        })
+    }
+
+    /// Returns the public key this community signs its messages with
+    pub fn public_key(&self) -> Result<Ed25519PublicKey, Ipv8Error> {
+        Ok(*self.my_peer.public_key())
+    }
+
+    /// Returns our best guess of the address other peers can reach us at: the address most peers which sent us an
+    /// introduction response see us at, or our lan address before any did
+    pub fn wan_address(&self) -> Result<Address, Ipv8Error> {
+        Ok(self
+            .network
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .wan_address()
+            .unwrap_or(self.lan_address))
+    }
+
+    /// Creates an introduction request directed at the given address.
+    pub fn create_introduction_request(&self, destination: &Address) -> Result<Packet, Ipv8Error> {
+        let global_time = self.global_time.claim()?;
+        let payload = IntroductionRequestPayload {
+            destination_address: payload_address(destination),
+            source_lan_address: payload_address(&self.lan_address),
+            source_wan_address: payload_address(&self.wan_address()?),
+            advice: true,
+            connection_type: connection_type(destination),
+            // py-ipv8 uses the global time as identifier
+            identifier: (global_time % 65536) as u16,
+            extra_bytes: RawEnd(vec![]),
+        };
+        MessageBuilder::new(
+            DISCOVERY_MID,
+            INTRODUCTION_REQUEST,
+            self.my_peer.keypair(),
+            global_time,
+        )?
+        .with_payload(&payload)?
+        .build()
+    }
+
+    /// Picks a random peer we know of to introduce to `exclude`
+    fn get_peer_for_introduction(
+        &self,
+        exclude: &Address,
+    ) -> Result<Option<Address>, Box<dyn Error>> {
+        Ok(self
+            .network
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .get_verified_addresses()
+            .into_iter()
+            .filter(|address| address != exclude)
+            .choose(&mut rand::thread_rng()))
+    }
+
+    /// Creates an introduction response for the peer at `socket_address`.
+    ///
+    /// If a peer to introduce could be found, a puncture request is sent to that peer as well. An IPv6 peer can't be put
+    /// in the introduction address fields, so it is sent as a tagged [Ipv6Address] in the extra bytes instead. py-ipv8
+    /// ignores these, other rust-ipv8 nodes walk to the peer.
+    fn create_introduction_response(
+        &self,
+        lan_socket_address: &Address,
+        socket_address: &Address,
+        identifier: u16,
+    ) -> Result<Packet, Ipv8Error> {
+        let wan_address = self.wan_address()?;
+        let mut lan_introduction_address = empty_address();
+        let mut wan_introduction_address = empty_address();
+        let mut extra_bytes = vec![];
+
+        if let Some(introduction) = self.get_peer_for_introduction(socket_address)? {
+            if introduction.is_ipv6() {
+                extra_bytes = ipv6_introduction(&introduction)?;
+            } else if address_is_lan(&introduction) {
+                lan_introduction_address = introduction;
+                wan_introduction_address = payload_address(&Address(SocketAddr::new(
+                    wan_address.0.ip(),
+                    introduction.0.port(),
+                )));
+            } else {
+                wan_introduction_address = introduction;
+            }
+
+            let puncture_request =
+                self.create_puncture_request(lan_socket_address, socket_address, identifier)?;
+            let target = if lan_introduction_address != empty_address() {
+                lan_introduction_address
+            } else if wan_introduction_address != empty_address() {
+                wan_introduction_address
+            } else {
+                introduction
+            };
+            self.endpoint.send(&target, puncture_request)?;
+        }
+
+        let payload = IntroductionResponsePayload {
+            destination_address: payload_address(socket_address),
+            source_lan_address: payload_address(&self.lan_address),
+            source_wan_address: payload_address(&wan_address),
+            lan_introduction_address,
+            wan_introduction_address,
+            tunnel: false,
+            connection_type: connection_type(socket_address),
+            identifier,
+            extra_bytes: RawEnd(extra_bytes),
+        };
+        self.create_message(INTRODUCTION_RESPONSE, &payload)
+    }
+
+    /// Creates a puncture request. Unlike all other messages this one is not signed.
+    fn create_puncture_request(
+        &self,
+        lan_walker_address: &Address,
+        wan_walker_address: &Address,
+        identifier: u16,
+    ) -> Result<Packet, Ipv8Error> {
+        let mut packet = Packet::new(Header::py_ipv8_header(DISCOVERY_MID, PUNCTURE_REQUEST))?;
+        packet.add(&TimeDistributionPayload {
+            global_time: self.global_time.claim()?,
+        })?;
+        packet.add(&PunctureRequestPayload {
+            lan_walker_address: payload_address(lan_walker_address),
+            wan_walker_address: payload_address(wan_walker_address),
+            identifier,
+        })?;
+        Ok(packet)
+    }
+
+    /// Creates a puncture. The puncture itself is the hole in the NAT, its contents tell the receiver how to reach us.
+    fn create_puncture(&self, identifier: u16) -> Result<Packet, Ipv8Error> {
+        let payload = PuncturePayload {
+            lan_walker_address: payload_address(&self.lan_address),
+            wan_walker_address: payload_address(&self.wan_address()?),
+            identifier,
+        };
+        self.create_message(PUNCTURE, &payload)
+    }
+
+    /// Remembers a peer which sent us a valid signed message
+    fn add_verified_peer(
+        &self,
+        address: Address,
+        public_key: Ed25519PublicKey,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut network = self
+            .network
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+        let now = network.now();
+        network.add_verified_peer(Peer::new(public_key, address, true, now));
+        network.discover_services(&public_key, &[DISCOVERY_MID.to_vec()]);
+        Ok(())
+    }
+
+    /// Marks that we just sent a message to the peer at the given address, if it is verified
+    fn update_last_request(&self, address: &Address) -> Result<(), Box<dyn Error>> {
+        let mut network = self
+            .network
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+        let now = network.now();
+        if let Some(peer) = network.get_verified_by_address_mut(address) {
+            peer.update_last_request(now);
+        }
+        Ok(())
+    }
+
+    /// Handles an incoming introduction request by sending back an introduction response
+    fn on_introduction_request(
+        &self,
+        address: Address,
+        (auth, _time, payload): (
+            BinMemberAuthenticationPayload,
+            TimeDistributionPayload,
+            IntroductionRequestPayload,
+        ),
+    ) -> Result<(), Box<dyn Error>> {
+        let response = self.create_introduction_response(
+            &payload.source_lan_address,
+            &address,
+            payload.identifier,
+        )?;
+        // Add the peer after creating the response so it does not get introduced to itself
+        self.add_verified_peer(address, auth.public_key_bin)?;
+        self.endpoint.send(&address, response)?;
+        Ok(())
+    }
+
+    /// Handles an incoming introduction response. The destination address is the vote of the peer for our wan
+    /// address, the introduction addresses are added to our network so they can be walked to.
+    fn on_introduction_response(
+        &self,
+        address: Address,
+        (auth, _time, payload): (
+            BinMemberAuthenticationPayload,
+            TimeDistributionPayload,
+            IntroductionResponsePayload,
+        ),
+    ) -> Result<(), Box<dyn Error>> {
+        // Over IPv6 the peer could not tell us how it sees us, and a peer in our own network sees our lan address
+        let destination = payload.destination_address;
+        if !destination.0.ip().is_unspecified() && !address_is_lan(&destination) {
+            self.network
+                .write()
+                .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+                .wan_address_vote(destination, address);
+        }
+        let wan_address = self.wan_address()?;
+        self.add_verified_peer(address, auth.public_key_bin)?;
+
+        // Peers behind the same NAT as us should be contacted on their lan address
+        let introduced = if payload.wan_introduction_address != empty_address()
+            && payload.wan_introduction_address.0.ip() != wan_address.0.ip()
+        {
+            Some(payload.wan_introduction_address)
+        } else if payload.lan_introduction_address != empty_address()
+            && payload.wan_introduction_address.0.ip() == wan_address.0.ip()
+        {
+            Some(payload.lan_introduction_address)
+        } else {
+            parse_ipv6_introduction(&payload.extra_bytes.0)
+        };
+
+        if let Some(introduced) = introduced {
+            self.network
+                .write()
+                .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+                .discover_address(address, introduced, Some(&DISCOVERY_MID));
+        }
+        Ok(())
+    }
+
+    /// Handles an incoming puncture request by sending a puncture to the peer that wants to contact us
+    fn on_puncture_request(
+        &self,
+        _address: Address,
+        (_time, payload): (TimeDistributionPayload, PunctureRequestPayload),
+    ) -> Result<(), Box<dyn Error>> {
+        // When the walker is behind the same NAT as we are, we can reach it directly on its lan address
+        let target = if payload.wan_walker_address.0.ip() == self.wan_address()?.0.ip() {
+            payload.lan_walker_address
+        } else {
+            payload.wan_walker_address
+        };
+
+        // The walker is an IPv6 peer we can't puncture for
+        if target == empty_address() {
+            return Ok(());
+        }
+
+        let puncture = self.create_puncture(payload.identifier)?;
+        self.endpoint.send(&target, puncture)?;
+        Ok(())
+    }
+
+    /// Handles an incoming puncture. The hole is punched by receiving it, so only the signature is checked.
+    fn on_puncture(
+        &self,
+        address: Address,
+        (auth, _time, _payload): (
+            BinMemberAuthenticationPayload,
+            TimeDistributionPayload,
+            PuncturePayload,
+        ),
+    ) -> Result<(), Box<dyn Error>> {
+        self.add_verified_peer(address, auth.public_key_bin)
+    }
+
+    /// Handles an incoming ping by sending back a pong with the same identifier
+    fn on_ping(
+        &self,
+        address: Address,
+        (auth, _time, payload): (
+            BinMemberAuthenticationPayload,
+            TimeDistributionPayload,
+            PingPayload,
+        ),
+    ) -> Result<(), Box<dyn Error>> {
+        self.add_verified_peer(address, auth.public_key_bin)?;
+
+        let pong = self.create_message(PONG, &payload)?;
+        self.endpoint.send(&address, pong)?;
+        Ok(())
+    }
+
+    /// Handles an incoming pong. If it answers one of our pings, the round trip time is added to the peer.
+    fn on_pong(
+        &self,
+        address: Address,
+        (auth, _time, payload): (
+            BinMemberAuthenticationPayload,
+            TimeDistributionPayload,
+            PingPayload,
+        ),
+    ) -> Result<(), Box<dyn Error>> {
+        let public_key = auth.public_key_bin;
+        self.add_verified_peer(address, public_key)?;
+
+        let ping = self
+            .pings
+            .pop::<PingCache>(PING_PREFIX, payload.identifier)?;
+        if let Some(PingCache {
+            address: pinged,
+            sent,
+        }) = ping
+        {
+            if pinged == address {
+                let mut network = self
+                    .network
+                    .write()
+                    .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+                let now = network.now();
+                if let Some(peer) = network.get_verified_by_public_key_mut(&public_key) {
+                    peer.add_ping(now.saturating_duration_since(sent));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Community for DiscoveryCommunity {
+    fn new(
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::with_identity(
+            MyPeer::generate(endpoint.local_address()?)?,
+            endpoint,
+            task_manager,
+        )
+    }
+
+    fn with_identity(
+        my_peer: MyPeer,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::with_bootstrap_addresses(
+            my_peer,
+            endpoint,
+            task_manager,
+            Config::default().default_hosts,
+        )?)
+    }
+
+    fn my_peer(&self) -> Option<&MyPeer> {
+        Some(&self.my_peer)
+    }
+
+    fn get_mid(&self) -> Vec<u8> {
+        DISCOVERY_MID.to_vec()
+    }
+
+    fn signature_policy(&self, message_type: u8) -> SignaturePolicy {
+        match message_type {
+            // Puncture requests are forwarded on behalf of another peer, py-ipv8 does not sign them
+            PUNCTURE_REQUEST => SignaturePolicy::Unsigned,
+            _ => SignaturePolicy::Signed,
+        }
+    }
+
+    fn known_peer_key(&self, address: &Address) -> Option<Ed25519PublicKey> {
+        self.network
+            .read()
+            .ok()?
+            .get_verified_by_address(address)
+            .map(|peer| *peer.key())
+    }
+
+    /// Cancels the timeouts of the pings which were not answered yet
+    fn unload(&self) -> Result<(), Box<dyn Error>> {
+        Ok(self.pings.clear()?)
+    }
+
+    fn register_handlers(handlers: &mut MessageHandlers<Self>) {
+        handlers.register(INTRODUCTION_REQUEST, Self::on_introduction_request);
+        handlers.register(INTRODUCTION_RESPONSE, Self::on_introduction_response);
+        handlers.register(PUNCTURE_REQUEST, Self::on_puncture_request);
+        handlers.register(PUNCTURE, Self::on_puncture);
+        handlers.register(PING, Self::on_ping);
+        handlers.register(PONG, Self::on_pong);
+    }
+
+    fn global_time(&self) -> Option<&GlobalTime> {
+        Some(&self.global_time)
+    }
+}
+
+impl SigningCommunity for DiscoveryCommunity {
+    fn keypair(&self) -> &KeyPair {
+        self.my_peer.keypair()
+    }
+}
+
+impl Overlay for DiscoveryCommunity {
+    fn network(&self) -> &RwLock<Network> {
+        &self.network
+    }
+
+    fn walk_to(&self, address: &Address) -> Result<(), Box<dyn Error>> {
+        let packet = self.create_introduction_request(address)?;
+        self.endpoint.send(address, packet)?;
+        self.update_last_request(address)
+    }
+
+    fn send_ping(&self, address: &Address) -> Result<(), Box<dyn Error>> {
+        let identifier = self.pings.add(PingCache {
+            address: *address,
+            sent: self.now()?,
+        })?;
+        let packet = self.create_message(PING, &PingPayload { identifier })?;
+        self.endpoint.send(address, packet)?;
+        self.update_last_request(address)
+    }
+
+    fn bootstrap_addresses(&self) -> Vec<Address> {
+        self.bootstrap_addresses.clone()
+    }
+
+    /// Removes the peer from the network, and its vote from the global time
+    fn remove_peer(&self, peer: &Peer) -> Result<(), Box<dyn Error>> {
+        self.network
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .remove_by_address(peer.address());
+        self.global_time.remove_neighbour(peer.key())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::endpoint::UdpEndpoint;
+    use crate::networking::test_helper::{localhost_endpoint, localhost_socket};
+    use crate::payloads::Ipv8Payload;
+    use crate::serialization::PacketDeserializer;
+    use serde::Serialize;
+    use std::net::{Ipv6Addr, UdpSocket};
+    use std::time::Duration;
+
+    /// A py-ipv8 peer as far as the DiscoveryCommunity is concerned
+    struct RemotePeer {
+        keypair: KeyPair,
+        socket: UdpSocket,
+    }
+
+    impl RemotePeer {
+        fn new() -> Self {
+            Self::bind(localhost_socket())
+        }
+
+        fn ipv6() -> Self {
+            Self::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 0))
+        }
+
+        fn bind(address: SocketAddr) -> Self {
+            let socket = UdpSocket::bind(address).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            RemotePeer {
+                keypair: KeyPair::new().unwrap(),
+                socket,
+            }
+        }
+
+        fn address(&self) -> Address {
+            Address(self.socket.local_addr().unwrap())
+        }
+
+        fn introduction_request(&self, destination: &Address, identifier: u16) -> Packet {
+            let mut packet =
+                Packet::new(Header::py_ipv8_header(DISCOVERY_MID, INTRODUCTION_REQUEST)).unwrap();
+            packet
+                .add(&BinMemberAuthenticationPayload {
+                    public_key_bin: self.keypair.public_key().unwrap(),
+                    encryption_key_bin: [0; 32],
+                })
+                .unwrap();
+            packet
+                .add(&TimeDistributionPayload { global_time: 1 })
+                .unwrap();
+            packet
+                .add(&IntroductionRequestPayload {
+                    destination_address: payload_address(destination),
+                    source_lan_address: payload_address(&self.address()),
+                    source_wan_address: payload_address(&self.address()),
+                    advice: true,
+                    connection_type: connection_type(destination),
+                    identifier,
+                    extra_bytes: RawEnd(vec![]),
+                })
+                .unwrap();
+            packet.sign(&self.keypair).unwrap()
+        }
+
+        fn introduction_response(&self, destination: &Address, introduced: &Address) -> Packet {
+            let mut packet =
+                Packet::new(Header::py_ipv8_header(DISCOVERY_MID, INTRODUCTION_RESPONSE)).unwrap();
+            packet
+                .add(&BinMemberAuthenticationPayload {
+                    public_key_bin: self.keypair.public_key().unwrap(),
+                    encryption_key_bin: [0; 32],
+                })
+                .unwrap();
+            packet
+                .add(&TimeDistributionPayload { global_time: 1 })
+                .unwrap();
+            packet
+                .add(&IntroductionResponsePayload {
+                    destination_address: *destination,
+                    source_lan_address: self.address(),
+                    source_wan_address: self.address(),
+                    lan_introduction_address: empty_address(),
+                    wan_introduction_address: *introduced,
+                    tunnel: false,
+                    connection_type: ConnectionType::UNKNOWN,
+                    identifier: 1,
+                    extra_bytes: RawEnd(vec![]),
+                })
+                .unwrap();
+            packet.sign(&self.keypair).unwrap()
+        }
+
+        fn signed<T: Ipv8Payload + Serialize>(&self, message_type: u8, payload: &T) -> Packet {
+            let mut packet =
+                Packet::new(Header::py_ipv8_header(DISCOVERY_MID, message_type)).unwrap();
+            packet
+                .add(&BinMemberAuthenticationPayload {
+                    public_key_bin: self.keypair.public_key().unwrap(),
+                    encryption_key_bin: [0; 32],
+                })
+                .unwrap();
+            packet
+                .add(&TimeDistributionPayload { global_time: 1 })
+                .unwrap();
+            packet.add(payload).unwrap();
+            packet.sign(&self.keypair).unwrap()
+        }
+
+        fn receive(&self) -> (Header, PacketDeserializer) {
+            let mut buffer = [0; 2048];
+            let (size, _) = self.socket.recv_from(&mut buffer).unwrap();
+            let mut deserializer = Packet(buffer[..size].to_vec()).start_deserialize();
+            let header = deserializer.pop_header().unwrap();
+            (header, deserializer)
+        }
+    }
+
+    fn deliver(community: &DiscoveryCommunity, packet: Packet, from: Address) {
+        let deserializer = packet.start_deserialize();
+        let header = deserializer.peek_header().unwrap();
+        community.receive(header, deserializer, from).unwrap();
+    }
+
+    fn create_community() -> DiscoveryCommunity {
+        let sender = localhost_endpoint();
+        DiscoveryCommunity::new(&sender, &Arc::default()).unwrap()
+    }
+
+    #[test]
+    fn test_introduction_request() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+
+        deliver(
+            &community,
+            remote.introduction_request(&community.lan_address, 42),
+            remote.address(),
+        );
+
+        let (header, mut deserializer) = remote.receive();
+        assert_eq!(
+            header,
+            Header::py_ipv8_header(DISCOVERY_MID, INTRODUCTION_RESPONSE)
+        );
+        let auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        assert_eq!(auth.public_key_bin, community.public_key().unwrap());
+        assert!(deserializer.verify_with(auth.public_key_bin));
+
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let response: IntroductionResponsePayload = deserializer.next_payload().unwrap();
+        assert_eq!(response.destination_address, remote.address());
+        assert_eq!(response.source_lan_address, community.lan_address);
+        assert_eq!(response.wan_introduction_address, empty_address());
+        assert_eq!(response.identifier, 42);
+
+        assert_eq!(community.get_peers().unwrap(), vec![remote.address()]);
+    }
+
+    #[test]
+    fn test_introduction_with_puncture_request() {
+        let community = create_community();
+        let first = RemotePeer::new();
+        let second = RemotePeer::new();
+
+        deliver(
+            &community,
+            first.introduction_request(&community.lan_address, 1),
+            first.address(),
+        );
+        first.receive();
+
+        deliver(
+            &community,
+            second.introduction_request(&community.lan_address, 2),
+            second.address(),
+        );
+
+        // the second peer gets introduced to the first
+        let (_, mut deserializer) = second.receive();
+        assert!(deserializer.verify());
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let response: IntroductionResponsePayload = deserializer.next_payload().unwrap();
+        assert_eq!(response.lan_introduction_address, first.address());
+        assert_eq!(response.identifier, 2);
+
+        // and the first peer is asked to puncture the second
+        let (header, mut deserializer) = first.receive();
+        assert_eq!(
+            header,
+            Header::py_ipv8_header(DISCOVERY_MID, PUNCTURE_REQUEST)
+        );
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let request: PunctureRequestPayload = deserializer.next_payload().unwrap();
+        assert_eq!(request.wan_walker_address, second.address());
+        assert_eq!(request.lan_walker_address, second.address());
+        assert_eq!(request.identifier, 2);
+    }
+
+    #[test]
+    fn test_puncture_request() {
+        let community = create_community();
+        let requester = RemotePeer::new();
+        let walker = RemotePeer::new();
+
+        let mut packet =
+            Packet::new(Header::py_ipv8_header(DISCOVERY_MID, PUNCTURE_REQUEST)).unwrap();
+        packet
+            .add(&TimeDistributionPayload { global_time: 1 })
+            .unwrap();
+        packet
+            .add(&PunctureRequestPayload {
+                lan_walker_address: walker.address(),
+                wan_walker_address: walker.address(),
+                identifier: 7,
+            })
+            .unwrap();
+        deliver(&community, packet, requester.address());
+
+        let (header, mut deserializer) = walker.receive();
+        assert_eq!(header, Header::py_ipv8_header(DISCOVERY_MID, PUNCTURE));
+        assert!(deserializer.verify());
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let puncture: PuncturePayload = deserializer.next_payload().unwrap();
+        assert_eq!(puncture.lan_walker_address, community.lan_address);
+        assert_eq!(puncture.identifier, 7);
+    }
+
+    #[test]
+    fn test_walk_to() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+
+        community.walk_to(&remote.address()).unwrap();
+
+        let (header, mut deserializer) = remote.receive();
+        assert_eq!(
+            header,
+            Header::py_ipv8_header(DISCOVERY_MID, INTRODUCTION_REQUEST)
+        );
+        assert!(deserializer.verify());
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let request: IntroductionRequestPayload = deserializer.next_payload().unwrap();
+        assert_eq!(request.destination_address, remote.address());
+        assert_eq!(request.source_lan_address, community.lan_address);
+        assert!(request.advice);
+    }
+
+    #[test]
+    fn test_introduction_response() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+        let wan = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 5));
+        let introduced = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 9));
+
+        deliver(
+            &community,
+            remote.introduction_response(&wan, &introduced),
+            remote.address(),
+        );
+
+        assert_eq!(community.wan_address().unwrap(), wan);
+        assert_eq!(community.get_peers().unwrap(), vec![remote.address()]);
+        assert_eq!(
+            community.get_walkable_addresses().unwrap(),
+            vec![introduced]
+        );
+    }
+
+    #[test]
+    fn test_wan_address_vote() {
+        let community = create_community();
+        let first = RemotePeer::new();
+        let second = RemotePeer::new();
+        let wan = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 5));
+        let other = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(4, 3, 2, 1)), 5));
+        let lan = Address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+            5,
+        ));
+
+        deliver(
+            &community,
+            first.introduction_response(&wan, &empty_address()),
+            first.address(),
+        );
+        // a peer in our own network sees our lan address, which is no vote
+        deliver(
+            &community,
+            second.introduction_response(&lan, &empty_address()),
+            second.address(),
+        );
+        assert_eq!(community.wan_address().unwrap(), wan);
+
+        // the first vote is kept on a tie
+        deliver(
+            &community,
+            second.introduction_response(&other, &empty_address()),
+            second.address(),
+        );
+        assert_eq!(community.wan_address().unwrap(), wan);
+    }
+
+    #[test]
+    fn test_unspecified_lan_address() {
+        let unspecified = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+        let config = Config {
+            sending_address: unspecified,
+            receiving_address: unspecified,
+            ..Config::default()
+        };
+        let endpoint: Arc<dyn Endpoint> =
+            Arc::new(UdpEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap());
+        let community = DiscoveryCommunity::new(&endpoint, &Arc::default()).unwrap();
+
+        // the address of an interface is advertised instead of 0.0.0.0
+        let port = endpoint.local_address().unwrap().0.port();
+        assert!(!community.lan_address.0.ip().is_unspecified());
+        assert_eq!(community.lan_address.0.port(), port);
+        assert_eq!(community.wan_address().unwrap(), community.lan_address);
+
+        let dual_stack = Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port));
+        assert_eq!(lan_address(&dual_stack), community.lan_address);
+    }
+
+    #[test]
+    fn test_forged_introduction_request() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+        let mut packet = remote.introduction_request(&community.lan_address, 42);
+        // flip a bit in the payload
+        let index = packet.0.len() - 70;
+        packet.0[index] ^= 1;
+
+        let deserializer = packet.start_deserialize();
+        let header = deserializer.peek_header().unwrap();
+        assert!(community
+            .receive(header, deserializer, remote.address())
+            .is_err());
+        assert!(community.get_peers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+
+        // a ping is answered with a pong carrying the same identifier
+        deliver(
+            &community,
+            remote.signed(PING, &PingPayload { identifier: 7 }),
+            remote.address(),
+        );
+        let (header, mut deserializer) = remote.receive();
+        assert_eq!(header, Header::py_ipv8_header(DISCOVERY_MID, PONG));
+        let auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        assert!(deserializer.verify_with(auth.public_key_bin));
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let pong: PingPayload = deserializer.next_payload().unwrap();
+        assert_eq!(pong.identifier, 7);
+
+        // our own ping is answered, which gives us the round trip time
+        community.send_ping(&remote.address()).unwrap();
+        let (_, mut deserializer) = remote.receive();
+        let _auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let ping: PingPayload = deserializer.next_payload().unwrap();
+        assert!(community.pings.has(PING_PREFIX, ping.identifier).unwrap());
+        deliver(&community, remote.signed(PONG, &ping), remote.address());
+        assert!(!community.pings.has(PING_PREFIX, ping.identifier).unwrap());
+
+        {
+            let network = community.network.read().unwrap();
+            let peer = network.get_verified_by_address(&remote.address()).unwrap();
+            assert_eq!(peer.pings().len(), 1);
+            assert!(peer.last_request().is_some());
+        }
+
+        // unanswered pings are forgotten when the community is unloaded
+        community.send_ping(&remote.address()).unwrap();
+        let (_, mut deserializer) = remote.receive();
+        let _auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let ping: PingPayload = deserializer.next_payload().unwrap();
+        community.unload().unwrap();
+        assert!(!community.pings.has(PING_PREFIX, ping.identifier).unwrap());
+    }
+
+    #[test]
+    fn test_global_time() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+
+        deliver(
+            &community,
+            remote.signed(PING, &PingPayload { identifier: 1 }),
+            remote.address(),
+        );
+        // the clock moved to the time of the ping, and once more for the pong
+        assert_eq!(community.global_time.current().unwrap(), 2);
+
+        // a message from far in the future is dropped
+        let mut packet = Packet::new(Header::py_ipv8_header(DISCOVERY_MID, PING)).unwrap();
+        packet
+            .add(&BinMemberAuthenticationPayload {
+                public_key_bin: remote.keypair.public_key().unwrap(),
+                encryption_key_bin: [0; 32],
+            })
+            .unwrap();
+        packet
+            .add(&TimeDistributionPayload {
+                global_time: u64::MAX,
+            })
+            .unwrap();
+        packet.add(&PingPayload { identifier: 2 }).unwrap();
+        let packet = packet.sign(&remote.keypair).unwrap();
+
+        let deserializer = packet.start_deserialize();
+        let header = deserializer.peek_header().unwrap();
+        assert!(community
+            .receive(header, deserializer, remote.address())
+            .is_err());
+        assert_eq!(community.global_time.current().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_ipv6_introduction() {
+        let config = Config {
+            sending_address: Address(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                localhost_socket().port(),
+            )),
+            receiving_address: Address(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                localhost_socket().port(),
+            )),
+            ..Config::default()
+        };
+        let sender: Arc<dyn Endpoint> =
+            Arc::new(UdpEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap());
+        let community = DiscoveryCommunity::new(&sender, &Arc::default()).unwrap();
+        let ipv6 = RemotePeer::ipv6();
+        let ipv4 = RemotePeer::new();
+
+        deliver(
+            &community,
+            ipv6.introduction_request(&community.lan_address, 1),
+            ipv6.address(),
+        );
+        let (_, mut deserializer) = ipv6.receive();
+        let _auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let response: IntroductionResponsePayload = deserializer.next_payload().unwrap();
+        assert_eq!(response.connection_type, ConnectionType::IPV6);
+        assert_eq!(response.destination_address, empty_address());
+
+        // the IPv6 peer is introduced to the IPv4 peer in the extra bytes of the response
+        deliver(
+            &community,
+            ipv4.introduction_request(&community.lan_address, 2),
+            ipv4.address(),
+        );
+        let (_, mut deserializer) = ipv4.receive();
+        let auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        // the signature is no part of the extra bytes once verified
+        assert!(deserializer.verify_with(auth.public_key_bin));
+        let _time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        let response: IntroductionResponsePayload = deserializer.next_payload().unwrap();
+        assert_eq!(response.connection_type, ConnectionType::UNKNOWN);
+        assert_eq!(response.destination_address, ipv4.address());
+        assert_eq!(response.wan_introduction_address, empty_address());
+        assert_eq!(
+            parse_ipv6_introduction(&response.extra_bytes.0),
+            Some(ipv6.address())
+        );
+
+        // and the IPv6 peer got a puncture request over IPv6
+        let (header, _) = ipv6.receive();
+        assert_eq!(
+            header,
+            Header::py_ipv8_header(DISCOVERY_MID, PUNCTURE_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_ipv6_introduction_response() {
+        let community = create_community();
+        let remote = RemotePeer::new();
+        let introduced = Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 1234));
+
+        let mut packet =
+            Packet::new(Header::py_ipv8_header(DISCOVERY_MID, INTRODUCTION_RESPONSE)).unwrap();
+        packet
+            .add(&BinMemberAuthenticationPayload {
+                public_key_bin: remote.keypair.public_key().unwrap(),
+                encryption_key_bin: [0; 32],
+            })
+            .unwrap();
+        packet
+            .add(&TimeDistributionPayload { global_time: 1 })
+            .unwrap();
+        packet
+            .add(&IntroductionResponsePayload {
+                destination_address: empty_address(),
+                source_lan_address: remote.address(),
+                source_wan_address: remote.address(),
+                lan_introduction_address: empty_address(),
+                wan_introduction_address: empty_address(),
+                tunnel: false,
+                connection_type: ConnectionType::IPV6,
+                identifier: 1,
+                extra_bytes: RawEnd(ipv6_introduction(&introduced).unwrap()),
+            })
+            .unwrap();
+        deliver(
+            &community,
+            packet.sign(&remote.keypair).unwrap(),
+            remote.address(),
+        );
+
+        // an empty destination is no vote for our wan address
+        assert_eq!(community.wan_address().unwrap(), community.lan_address);
+        assert_eq!(
+            community
+                .network
+                .read()
+                .unwrap()
+                .get_walkable_addresses(None),
+            vec![introduced]
+        );
+    }
+
+    #[test]
+    fn test_parse_ipv6_introduction() {
+        let ipv6 = Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 1234));
+        let tagged = ipv6_introduction(&ipv6).unwrap();
+        let raw = &tagged[IPV6_INTRODUCTION_TAG.len()..];
+
+        assert_eq!(parse_ipv6_introduction(&tagged), Some(ipv6));
+        // the address without the tag is not an introduction, nor are other extra bytes of the same size
+        assert_eq!(parse_ipv6_introduction(raw), None);
+        assert_eq!(parse_ipv6_introduction(&[0; 34]), None);
+
+        // neither are addresses nobody can be reached at
+        let unspecified = Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 1234));
+        assert_eq!(
+            parse_ipv6_introduction(&ipv6_introduction(&unspecified).unwrap()),
+            None
+        );
+        let no_port = Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 0));
+        assert_eq!(
+            parse_ipv6_introduction(&ipv6_introduction(&no_port).unwrap()),
+            None
+        );
+    }
+}
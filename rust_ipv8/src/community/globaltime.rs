@@ -0,0 +1,269 @@
+//! The [Lamport clock](https://en.wikipedia.org/wiki/Lamport_timestamps) of a community, distributed with every
+//! [TimeDistributionPayload](crate::payloads::timedistributionpayload::TimeDistributionPayload).
+//!
+//! Global time values are limited to 64 bits. To keep malicious peers from quickly pushing the global time to the
+//! point where none are left, messages are only accepted when their global time is at most the median of the global
+//! times of our neighbours plus a margin, like Dispersy did.
+//!
+//! Neighbours are known by their public key, so only the global times of signed messages count. A peer changing its
+//! address does not get a second vote, but it still takes only a fresh key to get one. The number of neighbours is
+//! limited to keep such peers from filling our memory.
+
+use crate::crypto::signature::Ed25519PublicKey;
+use crate::networking::address::Address;
+use std::collections::{HashMap, VecDeque};
+use crate::error::Ipv8Error;
+use std::sync::Mutex;
+
+/// The margin Dispersy used on top of the median global time of the neighbours
+pub const DEFAULT_GLOBAL_TIME_MARGIN: u64 = 10000;
+
+/// The number of neighbours whose global time is remembered. When a new neighbour comes along, the one seen first
+/// is forgotten.
+pub const MAX_GLOBAL_TIME_NEIGHBOURS: usize = 100;
+
+/// The mutable state of a [GlobalTime]
+#[derive(Default)]
+struct Clock {
+    /// Our current global time
+    time: u64,
+    /// The last accepted global time of every neighbour
+    neighbours: HashMap<Ed25519PublicKey, u64>,
+    /// The neighbours in the order they were first seen in, to forget the oldest one when there are too many
+    order: VecDeque<Ed25519PublicKey>,
+}
+
+/// A Lamport clock with a median-of-neighbours acceptance limit.
+pub struct GlobalTime {
+    /// The clock itself
+    clock: Mutex<Clock>,
+    /// How far the global time of a message may be ahead of the median of our neighbours
+    margin: u64,
+}
+
+impl GlobalTime {
+    /// Creates a clock at time 0, accepting messages up to `margin` ahead of the median of the neighbours
+    pub fn new(margin: u64) -> Self {
+        Self {
+            clock: Mutex::new(Clock::default()),
+            margin,
+        }
+    }
+
+    /// Returns our current global time
+    pub fn current(&self) -> Result<u64, Ipv8Error> {
+        Ok(self
+            .clock
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the global time")))?
+            .time)
+    }
+
+    /// Increments the clock and returns the new time. Called for every message we send.
+    pub fn claim(&self) -> Result<u64, Ipv8Error> {
+        let mut clock = self
+            .clock
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the global time")))?;
+        clock.time = clock.time.saturating_add(1);
+        Ok(clock.time)
+    }
+
+    /// Returns the highest global time we accept. When we know no neighbours yet, our own time is used
+    /// instead of their median.
+    pub fn limit(&self) -> Result<u64, Ipv8Error> {
+        let clock = self
+            .clock
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the global time")))?;
+        Ok(Self::median(&clock).saturating_add(self.margin))
+    }
+
+    /// Checks the global time of an unsigned message received from `address` against the [limit](GlobalTime::limit),
+    /// without updating the clock. Anyone can forge those, so they don't get a say in our global time.
+    pub fn check(&self, address: Address, global_time: u64) -> Result<(), Ipv8Error> {
+        let clock = self
+            .clock
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the global time")))?;
+        self.check_limit(&clock, address, global_time)
+    }
+
+    /// Checks the global time of a message signed by `key` and received from `address`, and updates the clock with
+    /// it. Only call this for messages whose signature is verified.
+    ///
+    /// Fails with [GlobalTimeAhead](Ipv8Error::GlobalTimeAhead) when it is beyond the [limit](GlobalTime::limit), in which case the message
+    /// should be dropped and the clock is left untouched.
+    pub fn receive(
+        &self,
+        key: Ed25519PublicKey,
+        address: Address,
+        global_time: u64,
+    ) -> Result<(), Ipv8Error> {
+        let mut clock = self
+            .clock
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the global time")))?;
+        self.check_limit(&clock, address, global_time)?;
+
+        clock.time = clock.time.max(global_time);
+        if clock.neighbours.insert(key, global_time).is_none() {
+            clock.order.push_back(key);
+            if clock.order.len() > MAX_GLOBAL_TIME_NEIGHBOURS {
+                if let Some(oldest) = clock.order.pop_front() {
+                    clock.neighbours.remove(&oldest);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the global time of a neighbour, for example when it left the network
+    pub fn remove_neighbour(&self, key: &Ed25519PublicKey) -> Result<(), Ipv8Error> {
+        let mut clock = self
+            .clock
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the global time")))?;
+        if clock.neighbours.remove(key).is_some() {
+            clock.order.retain(|neighbour| neighbour != key);
+        }
+        Ok(())
+    }
+
+    /// Fails when the global time is beyond the limit of the clock
+    fn check_limit(
+        &self,
+        clock: &Clock,
+        address: Address,
+        global_time: u64,
+    ) -> Result<(), Ipv8Error> {
+        let limit = Self::median(clock).saturating_add(self.margin);
+        if global_time > limit {
+            return Err(Ipv8Error::GlobalTimeAhead {
+                address,
+                global_time,
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// The median of the global times of the neighbours, or our own time if there are none
+    fn median(clock: &Clock) -> u64 {
+        if clock.neighbours.is_empty() {
+            return clock.time;
+        }
+        let mut times: Vec<u64> = clock.neighbours.values().cloned().collect();
+        times.sort();
+        times[times.len() / 2]
+    }
+}
+
+impl Default for GlobalTime {
+    /// Creates a clock with the [DEFAULT_GLOBAL_TIME_MARGIN]
+    fn default() -> Self {
+        Self::new(DEFAULT_GLOBAL_TIME_MARGIN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    fn address(port: u16) -> Address {
+        Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), port))
+    }
+
+    /// The key of neighbour `n`
+    fn key(n: u16) -> Ed25519PublicKey {
+        let mut key = [0; 32];
+        key[..2].copy_from_slice(&n.to_be_bytes());
+        key
+    }
+
+    /// Receives a global time from neighbour `n`
+    fn receive(clock: &GlobalTime, n: u16, global_time: u64) -> Result<(), Ipv8Error> {
+        clock.receive(key(n), address(n), global_time)
+    }
+
+    #[test]
+    fn test_claim() {
+        let clock = GlobalTime::default();
+        assert_eq!(clock.current().unwrap(), 0);
+        assert_eq!(clock.claim().unwrap(), 1);
+        assert_eq!(clock.claim().unwrap(), 2);
+        assert_eq!(clock.current().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_receive() {
+        let clock = GlobalTime::new(10);
+        receive(&clock, 1, 5).unwrap();
+        assert_eq!(clock.current().unwrap(), 5);
+
+        // an older message does not move the clock back
+        receive(&clock, 2, 3).unwrap();
+        assert_eq!(clock.current().unwrap(), 5);
+
+        // sending continues from the highest time seen
+        assert_eq!(clock.claim().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_median_limit() {
+        let clock = GlobalTime::new(10);
+        assert!(receive(&clock, 1, 11).is_err());
+        receive(&clock, 1, 10).unwrap();
+        receive(&clock, 2, 12).unwrap();
+        receive(&clock, 3, 20).unwrap();
+
+        // the median of 10, 12 and 20 is 12
+        assert_eq!(clock.limit().unwrap(), 22);
+        assert!(receive(&clock, 4, 23).is_err());
+        assert_eq!(clock.current().unwrap(), 20);
+
+        // a single neighbour can't drag the median along
+        assert!(receive(&clock, 3, 1000).is_err());
+        clock.remove_neighbour(&key(3)).unwrap();
+        assert_eq!(clock.limit().unwrap(), 22);
+
+        // neither can it by moving to another address
+        clock.receive(key(2), address(5), 22).unwrap();
+        assert_eq!(clock.clock.lock().unwrap().neighbours.len(), 2);
+    }
+
+    #[test]
+    fn test_check() {
+        let clock = GlobalTime::new(10);
+        receive(&clock, 1, 5).unwrap();
+
+        // unsigned messages are checked, but don't move the clock
+        clock.check(address(2), 15).unwrap();
+        assert!(clock.check(address(2), 16).is_err());
+        assert_eq!(clock.current().unwrap(), 5);
+        assert_eq!(clock.limit().unwrap(), 15);
+    }
+
+    #[test]
+    fn test_max_neighbours() {
+        let clock = GlobalTime::new(10);
+        receive(&clock, 0, 10).unwrap();
+        for n in 1..MAX_GLOBAL_TIME_NEIGHBOURS as u16 {
+            receive(&clock, n, 0).unwrap();
+        }
+        // an update does not count as a new neighbour
+        receive(&clock, 0, 10).unwrap();
+        assert_eq!(
+            clock.clock.lock().unwrap().neighbours.len(),
+            MAX_GLOBAL_TIME_NEIGHBOURS
+        );
+
+        // the neighbour seen first is forgotten
+        receive(&clock, 1000, 0).unwrap();
+        let inner = clock.clock.lock().unwrap();
+        assert_eq!(inner.neighbours.len(), MAX_GLOBAL_TIME_NEIGHBOURS);
+        assert!(!inner.neighbours.contains_key(&key(0)));
+        assert_eq!(inner.order.len(), MAX_GLOBAL_TIME_NEIGHBOURS);
+    }
+}
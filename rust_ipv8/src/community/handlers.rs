@@ -0,0 +1,527 @@
+//! A dispatch table for the messages of a [Community], like py-ipv8's `lazy_wrapper` decorators.
+//!
+//! A community registers a handler per message type in [register_handlers](Community::register_handlers),
+//! together with the payloads that message consists of. The payloads are decoded in order and passed to the handler
+//! as a tuple. When the first payload is a [BinMemberAuthenticationPayload], the signature at the end of the packet
+//! is verified with its key before anything else is decoded, so handlers of signed messages never see forged
+//! packets. When the community has a [global time](Community::global_time), every [TimeDistributionPayload] is
+//! checked against it before the handler is called. Only those of signed messages are added to it. Messages without a handler are passed to
+//! [on_receive](Community::on_receive).
+//!
+//! The table is built once per type of community, the first time one of them receives a message.
+//!
+//! With the `tokio` feature, a handler can also return a future, see [register_async](MessageHandlers::register_async).
+//!
+//! ```
+//! use rust_ipv8::community::handlers::MessageHandlers;
+//! use rust_ipv8::community::Community;
+//! use rust_ipv8::networking::address::Address;
+//! use rust_ipv8::networking::endpoint::Endpoint;
+//! use rust_ipv8::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
+//! use rust_ipv8::payloads::timedistributionpayload::TimeDistributionPayload;
+//! use rust_ipv8::taskmanager::TaskManager;
+//! use std::error::Error;
+//! use std::sync::Arc;
+//!
+//! struct MyCommunity;
+//!
+//! impl MyCommunity {
+//!     fn on_time(
+//!         &self,
+//!         _address: Address,
+//!         (auth, time): (BinMemberAuthenticationPayload, TimeDistributionPayload),
+//!     ) -> Result<(), Box<dyn Error>> {
+//!         println!("{:?} says it is {}", auth.public_key_bin, time.global_time);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl Community for MyCommunity {
+//!     fn new(
+//!         _endpoint: &Arc<dyn Endpoint>,
+//!         _task_manager: &Arc<TaskManager>,
+//!     ) -> Result<Self, Box<dyn Error>> {
+//!         Ok(MyCommunity)
+//!     }
+//!
+//!     fn get_mid(&self) -> Vec<u8> {
+//!         vec![1; 20]
+//!     }
+//!
+//!     fn register_handlers(handlers: &mut MessageHandlers<Self>) {
+//!         handlers.register(1, MyCommunity::on_time);
+//!     }
+//! }
+//! ```
+
+use crate::community::globaltime::GlobalTime;
+use crate::community::Community;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
+use crate::payloads::timedistributionpayload::TimeDistributionPayload;
+use crate::payloads::Ipv8Payload;
+use crate::serialization::header::Header;
+use crate::serialization::PacketDeserializer;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle;
+
+/// A list of payloads which are decoded from a packet in order.
+///
+/// Implemented for tuples of up to six [Ipv8Payloads](Ipv8Payload).
+pub trait Payloads: Sized {
+    /// Decodes all payloads from the deserializer, which must be positioned right after the header.
+    ///
+    /// If the first payload is a [BinMemberAuthenticationPayload], the signature of the packet is verified first.
+    fn decode(deserializer: &mut PacketDeserializer) -> Result<Self, Box<dyn Error>>;
+
+    /// Returns the global time of the first [TimeDistributionPayload] in the list, if there is one
+    fn global_time(&self) -> Option<u64>;
+}
+
+/// Returns the global time of the payload if it is a [TimeDistributionPayload]
+fn global_time_of(payload: &dyn Any) -> Option<u64> {
+    payload
+        .downcast_ref::<TimeDistributionPayload>()
+        .map(|time| time.global_time)
+}
+
+/// Decodes the first payload of a message and verifies the signature of the packet if it is a
+/// [BinMemberAuthenticationPayload].
+fn decode_first<T>(deserializer: &mut PacketDeserializer) -> Result<T, Box<dyn Error>>
+where
+    for<'de> T: Deserialize<'de> + Ipv8Payload + Serialize + 'static,
+{
+    let first: T = deserializer.next_payload()?;
+    if let Some(auth) = (&first as &dyn Any).downcast_ref::<BinMemberAuthenticationPayload>() {
+        if !deserializer.verify_with(auth.public_key_bin) {
+            return Err(Box::new(Ipv8Error::InvalidSignature {
+                key: auth.public_key_bin,
+            }));
+        }
+    }
+    Ok(first)
+}
+
+/// Implements [Payloads] for a tuple of the given payload types
+macro_rules! impl_payloads {
+    ($first:ident $(, $rest:ident)*) => {
+        impl<$first $(, $rest)*> Payloads for ($first, $($rest,)*)
+        where
+            for<'de> $first: Deserialize<'de> + Ipv8Payload + Serialize + 'static,
+            $(for<'de> $rest: Deserialize<'de> + Ipv8Payload + Serialize + 'static,)*
+        {
+            fn decode(deserializer: &mut PacketDeserializer) -> Result<Self, Box<dyn Error>> {
+                Ok((
+                    decode_first::<$first>(deserializer)?,
+                    $(deserializer.next_payload::<$rest>()?,)*
+                ))
+            }
+
+            #[allow(non_snake_case)]
+            fn global_time(&self) -> Option<u64> {
+                let ($first, $($rest,)*) = self;
+                global_time_of($first)$(.or_else(|| global_time_of($rest)))*
+            }
+        }
+    };
+}
+
+impl_payloads!(A);
+impl_payloads!(A, B);
+impl_payloads!(A, B, C);
+impl_payloads!(A, B, C, D);
+impl_payloads!(A, B, C, D, E);
+impl_payloads!(A, B, C, D, E, F);
+
+/// A handler with its payload types erased
+type Handler<C> = Box<
+    dyn Fn(&C, &mut PacketDeserializer, Address, Option<&GlobalTime>) -> Result<(), Box<dyn Error>>
+        + Send
+        + Sync,
+>;
+
+/// The handlers of a community of type `C`, by message type.
+///
+/// A community fills it in [register_handlers](Community::register_handlers).
+pub struct MessageHandlers<C> {
+    /// All registered handlers
+    handlers: HashMap<u8, Handler<C>>,
+}
+
+impl<C> MessageHandlers<C> {
+    /// Creates a dispatch table without any handlers
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Registers the handler for a message type. The payload types of the message are given by the tuple the
+    /// handler takes. Registering a message type twice replaces the earlier handler.
+    pub fn register<P, F>(&mut self, message_type: u8, handler: F)
+    where
+        P: Payloads + 'static,
+        F: Fn(&C, Address, P) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.handlers.insert(
+            message_type,
+            Box::new(move |community, deserializer, address, global_time| {
+                let payloads = P::decode(deserializer)?;
+                // Only signed messages count, otherwise anyone could pose as any number of neighbours
+                if let (Some(clock), Some(time)) = (global_time, payloads.global_time()) {
+                    match deserializer.verified_with() {
+                        Some(key) => clock.receive(key, address, time)?,
+                        None => clock.check(address, time)?,
+                    }
+                }
+                handler(community, address, payloads)
+            }),
+        );
+    }
+
+    /// Registers a handler for a message type which returns a future, like [register](MessageHandlers::register).
+    /// The signature and global time of the message are checked before the handler is called. The future it returns
+    /// is spawned on the tokio runtime the message is received on, so it can wait for other peers without holding up
+    /// the messages after it. A failing future is logged.
+    ///
+    /// The handler only borrows the community, the future has to own what it needs, like a clone of the endpoint.
+    /// Messages received outside of a tokio runtime, so not on a
+    /// [TokioEndpoint](crate::networking::tokioendpoint::TokioEndpoint), fail with a
+    /// [NoRuntime](Ipv8Error::NoRuntime) error.
+    #[cfg(feature = "tokio")]
+    pub fn register_async<P, F, T>(&mut self, message_type: u8, handler: F)
+    where
+        P: Payloads + 'static,
+        F: Fn(&C, Address, P) -> T + Send + Sync + 'static,
+        T: Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'static,
+    {
+        self.register(message_type, move |community, address, payloads: P| {
+            let runtime = Handle::try_current().or(Err(Ipv8Error::NoRuntime))?;
+            let future = handler(community, address, payloads);
+            runtime.spawn(async move {
+                if let Err(e) = future.await {
+                    warn!(
+                        "The async handler of message {} from {:?} failed: {}",
+                        message_type, address, e
+                    );
+                }
+            });
+            Ok(())
+        });
+    }
+
+    /// Returns true if a handler is registered for the message type
+    pub fn has_handler(&self, message_type: u8) -> bool {
+        self.handlers.contains_key(&message_type)
+    }
+}
+
+impl<C> Default for MessageHandlers<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static! {
+    /// The dispatch table of every type of community which received a message so far, by the type of the community
+    static ref TABLES: RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>> = RwLock::new(HashMap::new());
+}
+
+/// Returns the dispatch table of the community type, registering its handlers the first time
+fn handlers_of<C: Community + 'static>() -> Result<Arc<MessageHandlers<C>>, Ipv8Error> {
+    let cached = TABLES
+        .read()
+        .or(Err(Ipv8Error::LockPoisoned("the dispatch tables")))?
+        .get(&TypeId::of::<C>())
+        .cloned();
+    let table = match cached {
+        Some(table) => table,
+        None => {
+            let mut handlers = MessageHandlers::<C>::new();
+            C::register_handlers(&mut handlers);
+            let table: Arc<dyn Any + Send + Sync> = Arc::new(handlers);
+            // Another thread may have registered them in the meantime, which table is kept does not matter
+            Arc::clone(
+                TABLES
+                    .write()
+                    .or(Err(Ipv8Error::LockPoisoned("the dispatch tables")))?
+                    .entry(TypeId::of::<C>())
+                    .or_insert(table),
+            )
+        }
+    };
+    Ok(table
+        .downcast()
+        .expect("the dispatch tables are stored by the type of their community"))
+}
+
+/// Passes the messages of a [Community] to the handler registered for their type, see the
+/// [module documentation](crate::community::handlers).
+///
+/// Implemented for every community, there is no need to implement it yourself.
+pub trait Dispatch {
+    /// Decodes the message and calls the handler registered for its message type. Messages without a handler are
+    /// passed to [on_receive](Community::on_receive).
+    ///
+    /// The deserializer must still contain the header, like the one passed to
+    /// [on_receive](Community::on_receive).
+    fn dispatch(
+        &self,
+        header: Header,
+        deserializer: PacketDeserializer,
+        address: Address,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+impl<C: Community + 'static> Dispatch for C {
+    fn dispatch(
+        &self,
+        header: Header,
+        mut deserializer: PacketDeserializer,
+        address: Address,
+    ) -> Result<(), Box<dyn Error>> {
+        let message_type = header.message_type.ok_or(Ipv8Error::MissingMessageType)? as u8;
+        let handlers = handlers_of::<C>()?;
+        match handlers.handlers.get(&message_type) {
+            Some(handler) => {
+                deserializer.pop_header()?;
+                handler(self, &mut deserializer, address, self.global_time())
+            }
+            None => self.on_receive(header, deserializer, address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::signature::KeyPair;
+    use crate::networking::endpoint::Endpoint;
+    use crate::networking::test_helper::localhost;
+    use crate::payloads::timedistributionpayload::TimeDistributionPayload;
+    use crate::serialization::Packet;
+    use crate::taskmanager::TaskManager;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct TestCommunity {
+        times: Mutex<Vec<u64>>,
+        unhandled: Mutex<Vec<u8>>,
+        global_time: Option<GlobalTime>,
+    }
+
+    impl TestCommunity {
+        fn on_signed(
+            &self,
+            _address: Address,
+            (_auth, time): (BinMemberAuthenticationPayload, TimeDistributionPayload),
+        ) -> Result<(), Box<dyn Error>> {
+            self.times.lock().unwrap().push(time.global_time);
+            Ok(())
+        }
+    }
+
+    impl Community for TestCommunity {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
+            Ok(Self::default())
+        }
+
+        fn get_mid(&self) -> Vec<u8> {
+            vec![0; 20]
+        }
+
+        fn register_handlers(handlers: &mut MessageHandlers<Self>) {
+            handlers.register(1, TestCommunity::on_signed);
+            handlers.register(
+                2,
+                |community: &TestCommunity, _address, (time,): (TimeDistributionPayload,)| {
+                    community.times.lock().unwrap().push(time.global_time);
+                    Ok(())
+                },
+            );
+        }
+
+        fn global_time(&self) -> Option<&GlobalTime> {
+            self.global_time.as_ref()
+        }
+
+        fn on_receive(
+            &self,
+            header: Header,
+            _deserializer: PacketDeserializer,
+            _address: Address,
+        ) -> Result<(), Box<dyn Error>> {
+            self.unhandled
+                .lock()
+                .unwrap()
+                .push(header.message_type.unwrap() as u8);
+            Ok(())
+        }
+    }
+
+    /// Hands the global times of its messages to the test from an async handler
+    #[cfg(feature = "tokio")]
+    struct AsyncCommunity {
+        times: tokio::sync::mpsc::UnboundedSender<u64>,
+    }
+
+    #[cfg(feature = "tokio")]
+    impl Community for AsyncCommunity {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
+            Err("created by the test".into())
+        }
+
+        fn get_mid(&self) -> Vec<u8> {
+            vec![0; 20]
+        }
+
+        fn register_handlers(handlers: &mut MessageHandlers<Self>) {
+            handlers.register_async(
+                1,
+                |community: &AsyncCommunity,
+                 _address,
+                 (_auth, time): (BinMemberAuthenticationPayload, TimeDistributionPayload)| {
+                    let times = community.times.clone();
+                    async move {
+                        // the handler is a real future, other messages are handled while it waits
+                        tokio::task::yield_now().await;
+                        times.send(time.global_time)?;
+                        Ok(())
+                    }
+                },
+            );
+        }
+    }
+
+    fn signed_packet(message_type: u8, global_time: u64) -> Packet {
+        let keypair = KeyPair::new().unwrap();
+        let mut packet = Packet::new(Header::py_ipv8_header([0; 20], message_type)).unwrap();
+        packet
+            .add(&BinMemberAuthenticationPayload {
+                public_key_bin: keypair.public_key().unwrap(),
+                encryption_key_bin: [0; 32],
+            })
+            .unwrap();
+        packet
+            .add(&TimeDistributionPayload { global_time })
+            .unwrap();
+        packet.sign(&keypair).unwrap()
+    }
+
+    fn handle(community: &dyn Community, packet: Packet) -> Result<(), Box<dyn Error>> {
+        let deserializer = packet.start_deserialize();
+        let header = deserializer.peek_header().unwrap();
+        community.receive(header, deserializer, localhost())
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let community = TestCommunity::default();
+
+        handle(&community, signed_packet(1, 42)).unwrap();
+
+        let mut unsigned = Packet::new(Header::py_ipv8_header([0; 20], 2)).unwrap();
+        unsigned
+            .add(&TimeDistributionPayload { global_time: 43 })
+            .unwrap();
+        handle(&community, unsigned).unwrap();
+
+        // messages without a handler go to on_receive
+        handle(&community, signed_packet(3, 44)).unwrap();
+
+        assert_eq!(*community.times.lock().unwrap(), vec![42, 43]);
+        assert_eq!(*community.unhandled.lock().unwrap(), vec![3]);
+        let handlers = handlers_of::<TestCommunity>().unwrap();
+        assert!(handlers.has_handler(1));
+        assert!(!handlers.has_handler(3));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_handler() {
+        let (sender, mut times) = tokio::sync::mpsc::unbounded_channel();
+        let community = AsyncCommunity { times: sender };
+
+        handle(&community, signed_packet(1, 42)).unwrap();
+        handle(&community, signed_packet(1, 43)).unwrap();
+        let mut forged = signed_packet(1, 44).0;
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(handle(&community, Packet(forged)).is_err());
+
+        // the futures run concurrently, so in any order
+        let mut received = vec![times.recv().await.unwrap(), times.recv().await.unwrap()];
+        received.sort();
+        assert_eq!(received, vec![42, 43]);
+        drop(community);
+        assert_eq!(times.recv().await, None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_handler_without_runtime() {
+        let (sender, _times) = tokio::sync::mpsc::unbounded_channel();
+        let community = AsyncCommunity { times: sender };
+        assert!(handle(&community, signed_packet(1, 42)).is_err());
+    }
+
+    #[test]
+    fn test_shared_community() {
+        // a community behind an Arc dispatches to its own handlers
+        let community = Arc::new(TestCommunity::default());
+        handle(&community, signed_packet(1, 42)).unwrap();
+        assert_eq!(*community.times.lock().unwrap(), vec![42]);
+    }
+
+    #[test]
+    fn test_global_time() {
+        let community = TestCommunity {
+            global_time: Some(GlobalTime::new(100)),
+            ..TestCommunity::default()
+        };
+        let clock = community.global_time.as_ref().unwrap();
+
+        handle(&community, signed_packet(1, 42)).unwrap();
+        assert_eq!(clock.current().unwrap(), 42);
+
+        // too far in the future, compared to the only neighbour
+        assert!(handle(&community, signed_packet(1, 1000)).is_err());
+        assert_eq!(*community.times.lock().unwrap(), vec![42]);
+        assert_eq!(clock.current().unwrap(), 42);
+
+        // unsigned messages are checked, but anyone could have sent them so they don't count
+        let mut unsigned = Packet::new(Header::py_ipv8_header([0; 20], 2)).unwrap();
+        unsigned
+            .add(&TimeDistributionPayload { global_time: 100 })
+            .unwrap();
+        handle(&community, unsigned).unwrap();
+        assert_eq!(clock.current().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_forged() {
+        let community = TestCommunity::default();
+
+        let mut packet = signed_packet(1, 42);
+        let index = packet.0.len() - 70;
+        packet.0[index] ^= 1;
+
+        assert!(handle(&community, packet).is_err());
+        assert!(community.times.lock().unwrap().is_empty());
+    }
+}
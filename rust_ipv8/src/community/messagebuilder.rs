@@ -0,0 +1,236 @@
+//! Builds signed py-ipv8 messages.
+//!
+//! A signed message consists of a py-ipv8 [Header], a [BinMemberAuthenticationPayload], a [TimeDistributionPayload],
+//! the payloads of the message itself and finally the signature over all of that. The [MessageBuilder] adds
+//! everything but the payloads of the message, using the key and the clock of the community sending it.
+//!
+//! ```
+//! use rust_ipv8::community::globaltime::GlobalTime;
+//! use rust_ipv8::community::messagebuilder::MessageBuilder;
+//! use rust_ipv8::crypto::signature::KeyPair;
+//! use rust_ipv8::payloads::pingpayload::PingPayload;
+//!
+//! let keypair = KeyPair::new().unwrap();
+//! let global_time = GlobalTime::default();
+//!
+//! let packet = MessageBuilder::new([0; 20], 3, &keypair, global_time.claim().unwrap())
+//!     .unwrap()
+//!     .with_payload(&PingPayload { identifier: 42 })
+//!     .unwrap()
+//!     .build()
+//!     .unwrap();
+//! ```
+
+use crate::community::Community;
+use crate::crypto::signature::KeyPair;
+use crate::error::Ipv8Error;
+use crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
+use crate::payloads::timedistributionpayload::TimeDistributionPayload;
+use crate::payloads::Ipv8Payload;
+use crate::serialization::header::Header;
+use crate::serialization::Packet;
+use serde::Serialize;
+use std::convert::TryInto;
+
+/// A [Community] which signs its messages with its own key. The messages carry a time claimed from its
+/// [global time](Community::global_time), which it has to have.
+pub trait SigningCommunity: Community {
+    /// The key messages of this community are signed with
+    fn keypair(&self) -> &KeyPair;
+
+    /// Creates a signed message of the given type consisting of a single payload.
+    /// Use a [MessageBuilder] for messages with more than one payload.
+    fn create_message<T>(&self, message_type: u8, payload: &T) -> Result<Packet, Ipv8Error>
+    where
+        T: Ipv8Payload + Serialize,
+        Self: Sized,
+    {
+        MessageBuilder::for_community(self, message_type)?
+            .with_payload(payload)?
+            .build()
+    }
+}
+
+/// Assembles a signed message, see the [module documentation](crate::community::messagebuilder).
+pub struct MessageBuilder<'a> {
+    /// The key the message is signed with
+    keypair: &'a KeyPair,
+    /// The message so far
+    packet: Packet,
+}
+
+impl<'a> MessageBuilder<'a> {
+    /// Starts a message of the given type for the community with the given mid, with the authentication and time
+    /// payloads already in place.
+    pub fn new(
+        mid: [u8; 20],
+        message_type: u8,
+        keypair: &'a KeyPair,
+        global_time: u64,
+    ) -> Result<Self, Ipv8Error> {
+        let mut packet = Packet::new(Header::py_ipv8_header(mid, message_type))?;
+        packet.add(&BinMemberAuthenticationPayload {
+            public_key_bin: keypair.public_key()?,
+            // We don't have a Curve25519 encryption key (yet). py-ipv8 only uses it for encryption so zeroes suffice.
+            encryption_key_bin: [0; 32],
+        })?;
+        packet.add(&TimeDistributionPayload { global_time })?;
+        Ok(Self { keypair, packet })
+    }
+
+    /// Starts a message of the given type sent by the community. Claims a new global time from its clock.
+    pub fn for_community<C>(community: &'a C, message_type: u8) -> Result<Self, Ipv8Error>
+    where
+        C: SigningCommunity + ?Sized,
+    {
+        let mid = community.get_mid();
+        let global_time = community
+            .global_time()
+            .ok_or_else(|| Ipv8Error::NoGlobalTime { mid: mid.clone() })?
+            .claim()?;
+        let mid: [u8; 20] = mid
+            .as_slice()
+            .try_into()
+            .map_err(|_| Ipv8Error::InvalidMid { mid: mid.clone() })?;
+        Self::new(mid, message_type, community.keypair(), global_time)
+    }
+
+    /// Adds the next payload of the message
+    pub fn with_payload<T>(mut self, payload: &T) -> Result<Self, Ipv8Error>
+    where
+        T: Ipv8Payload + Serialize,
+    {
+        self.packet.add(payload)?;
+        Ok(self)
+    }
+
+    /// Signs the message. The returned packet can be sent as is.
+    pub fn build(self) -> Result<Packet, Ipv8Error> {
+        self.packet.sign(self.keypair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use crate::networking::endpoint::Endpoint;
+    use crate::taskmanager::TaskManager;
+    use std::sync::Arc;
+    use crate::payloads::pingpayload::PingPayload;
+    use crate::community::globaltime::GlobalTime;
+
+    struct TestCommunity {
+        keypair: KeyPair,
+        global_time: GlobalTime,
+    }
+
+    impl Community for TestCommunity {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
+            Ok(Self {
+                keypair: KeyPair::new()?,
+                global_time: GlobalTime::default(),
+            })
+        }
+
+        fn get_mid(&self) -> Vec<u8> {
+            vec![7; 20]
+        }
+
+        fn global_time(&self) -> Option<&GlobalTime> {
+            Some(&self.global_time)
+        }
+    }
+
+    impl SigningCommunity for TestCommunity {
+        fn keypair(&self) -> &KeyPair {
+            &self.keypair
+        }
+    }
+
+    fn community() -> TestCommunity {
+        TestCommunity {
+            keypair: KeyPair::new().unwrap(),
+            global_time: GlobalTime::default(),
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let community = community();
+        let packet = MessageBuilder::for_community(&community, 3)
+            .unwrap()
+            .with_payload(&PingPayload { identifier: 1 })
+            .unwrap()
+            .with_payload(&PingPayload { identifier: 2 })
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut deserializer = packet.start_deserialize();
+        let header = deserializer.pop_header().unwrap();
+        assert_eq!(header.mid_hash, Some(vec![7; 20]));
+        assert_eq!(header.message_type, Some(3));
+
+        let auth: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        assert_eq!(auth.public_key_bin, community.keypair.public_key().unwrap());
+        assert!(deserializer.verify_with(auth.public_key_bin));
+
+        let time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        assert_eq!(time.global_time, 1);
+        let first: PingPayload = deserializer.next_payload().unwrap();
+        let second: PingPayload = deserializer.next_payload().unwrap();
+        assert_eq!((first.identifier, second.identifier), (1, 2));
+    }
+
+    #[test]
+    fn test_create_message_claims_time() {
+        let community = community();
+        community
+            .create_message(3, &PingPayload { identifier: 1 })
+            .unwrap();
+        let packet = community
+            .create_message(3, &PingPayload { identifier: 1 })
+            .unwrap();
+
+        let mut deserializer = packet.start_deserialize();
+        deserializer.pop_header().unwrap();
+        let _: BinMemberAuthenticationPayload = deserializer.next_payload().unwrap();
+        let time: TimeDistributionPayload = deserializer.next_payload().unwrap();
+        assert_eq!(time.global_time, 2);
+        assert_eq!(community.global_time.current().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_invalid_mid() {
+        struct ShortMid(TestCommunity);
+
+        impl Community for ShortMid {
+            fn new(
+                endpoint: &Arc<dyn Endpoint>,
+                task_manager: &Arc<TaskManager>,
+            ) -> Result<Self, Box<dyn Error>> {
+                Ok(Self(TestCommunity::new(endpoint, task_manager)?))
+            }
+
+            fn get_mid(&self) -> Vec<u8> {
+                vec![1, 2, 3]
+            }
+
+            fn global_time(&self) -> Option<&GlobalTime> {
+                Some(&self.0.global_time)
+            }
+        }
+
+        impl SigningCommunity for ShortMid {
+            fn keypair(&self) -> &KeyPair {
+                &self.0.keypair
+            }
+        }
+
+        assert!(MessageBuilder::for_community(&ShortMid(community()), 3).is_err());
+    }
+}
@@ -1,28 +1,72 @@
-//! In this module, all communities are defined. Currently none as
+//! In this module, all communities are defined. Currently only the built-in
+//! [DiscoveryCommunity](crate::community::discovery::DiscoveryCommunity), other
 //! communities are still handled by py-ipv8 but the tools to move them soon
 //! are already in place.
 
+use crate::error::Ipv8Error;
 use crate::serialization::{Packet, PacketDeserializer};
 use crate::serialization::header::Header;
 use std::error::Error;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use crate::networking::address::Address;
-use crate::networking::NetworkSender;
-
-#[cfg(test)]
-use std::sync::atomic::AtomicUsize;
+use crate::networking::endpoint::Endpoint;
+use crate::networking::Receiver;
+use crate::crypto::signature::Ed25519PublicKey;
+use crate::community::globaltime::GlobalTime;
+use crate::community::handlers::{Dispatch, MessageHandlers};
+use crate::community::peer::{MasterPeer, MyPeer};
+use crate::community::ratelimit::RateLimiter;
+use crate::statistics::Statistics;
+use crate::taskmanager::TaskManager;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+pub mod actor;
+pub mod discovery;
+pub mod globaltime;
+pub mod handlers;
+pub mod messagebuilder;
 pub mod peer;
+pub mod ratelimit;
+
+/// How the [CommunityRegistry] checks the signature of a message before the community gets to see it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SignaturePolicy {
+    /// The message starts with a [BinMemberAuthenticationPayload](crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload)
+    /// and is signed with the key in it.
+    Signed,
+    /// The message is signed with the key of the peer we know at the address it came from. It has no
+    /// [BinMemberAuthenticationPayload](crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload).
+    SignedByKnownPeer,
+    /// The message is not signed at all. Anyone can forge these.
+    Unsigned,
+}
 
-create_error!(
-    HeaderUnwrapError,
-    "The community experienced an error trying to deserialize the header of a packet"
-);
-create_error!(MidError, "Failed to get the mid");
-create_error!(
-    UnknownCommunityError,
-    "No community with matching mid found"
-);
+/// Returns the name of a deprecated message type. Messages of these types are captured before they reach
+/// [on_receive](Community::on_receive), and before their signature is checked.
+fn deprecated_message(message_type: u8) -> Option<&'static str> {
+    Some(match message_type {
+        255 => "reserved-255",
+        254 => "on-missing-sequence",
+        253 => "missing-proof",
+        252 => "signature-request",
+        251 => "signature-response",
+        248 => "on-identity",
+        247 => "on-missing-identity",
+        244 => "destroy-community",
+        243 => "authorize",
+        242 => "revoke",
+        241 => "subjective-set",
+        240 => "missing-subjective-set",
+        239 => "on-missing-message",
+        238 => "undo-own",
+        237 => "undo-other",
+        236 => "dynamic-settings",
+        235 => "missing-last-message",
+        _ => return None,
+    })
+}
 
 #[cfg(test)]
 static WARN_DEPRECATED_CALLS: AtomicUsize = AtomicUsize::new(0);
@@ -35,15 +79,50 @@ static WARN_DEPRECATED_CALLS: AtomicUsize = AtomicUsize::new(0);
 ///
 /// _**Note:** Try to avoid the use of .unwrap() in actual production code, this is just an example_
 ///
-pub trait Community {
+/// Packets are handled on many threads at once, so a community has to be [Send] and [Sync]. State which changes when
+/// a packet is handled goes behind a lock or in an atomic.
+///
+/// A community [registers](Community::register_handlers) a handler for each of its messages, which gets the
+/// payloads of the message decoded and verified, see [handlers](crate::community::handlers).
+pub trait Community: Send + Sync + Dispatch {
     /// Every community should have a constructor.
-    /// It will receive an endpoint from the [NetworkSender](crate::networking::NetworkSender) which constructs
-    /// the community. An endpoint is used to send messages over the network to other
-    /// communities.
-    fn new(endpoint: &NetworkSender) -> Result<Self, Box<dyn Error>>
+    /// It will receive the [Endpoint] of the IPv8 instance which constructs the community. An endpoint is used to
+    /// send messages over the network to other communities, the community can keep its own handle to it.
+    /// The [TaskManager] of the instance runs the tasks of the community, for example those of a
+    /// [RequestCache](crate::requestcache::RequestCache).
+    fn new(
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>>
     where
         Self: Sized;
 
+    /// Constructs the community with the identity of the node, which it should sign its messages with. This is how
+    /// the [CommunityRegistry] [loads](CommunityRegistry::load_community) communities.
+    ///
+    /// By default the identity is ignored and the community is constructed with [new](Community::new).
+    fn with_identity(
+        _my_peer: MyPeer,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        Self: Sized,
+    {
+        Self::new(endpoint, task_manager)
+    }
+
+    /// Returns the identity this community signs its messages with, if it signs any.
+    fn my_peer(&self) -> Option<&MyPeer> {
+        None
+    }
+
+    /// Returns the master peer of this community, whose sha1 hash should be the [mid](Community::get_mid). None for
+    /// communities with a hardcoded mid, like the ones with an OpenSSL master peer.
+    fn master_peer(&self) -> Option<&MasterPeer> {
+        None
+    }
+
     /// Returns a unique (currently 20 byte) sequence identifying a community.
     ///
     /// This is used to be the SHA1 hash of its public key. You are free to choose whatever.
@@ -57,6 +136,46 @@ pub trait Community {
     /// unique 20 byte sequence.
     fn get_mid(&self) -> Vec<u8>;
 
+    /// Returns how messages of the given type have to be signed. The [CommunityRegistry] drops every packet which
+    /// does not meet this policy before it reaches the community.
+    ///
+    /// By default all messages have to be [Signed](SignaturePolicy::Signed).
+    fn signature_policy(&self, _message_type: u8) -> SignaturePolicy {
+        SignaturePolicy::Signed
+    }
+
+    /// Returns the public key of the peer this community knows at the given address.
+    /// Used to verify messages with the [SignedByKnownPeer](SignaturePolicy::SignedByKnownPeer) policy, and to keep the
+    /// [RateLimiter] from blacklisting the address of a known peer.
+    fn known_peer_key(&self, _address: &Address) -> Option<Ed25519PublicKey> {
+        None
+    }
+
+    /// Registers the handler of every message type of this community. The framework decodes the payloads a handler
+    /// takes, verifies the signature of the message and calls the handler with them. Messages without a handler are
+    /// passed to [on_receive](Community::on_receive).
+    ///
+    /// Called once per type of community, the first time one of them receives a message.
+    fn register_handlers(_handlers: &mut MessageHandlers<Self>)
+    where
+        Self: Sized,
+    {
+    }
+
+    /// Returns the lamport clock of this community, if it has one. Every global time in a message with a
+    /// [registered](Community::register_handlers) handler is checked against it, those of signed messages are added
+    /// to it. Messages with a global time beyond its limit are dropped before they reach their handler.
+    fn global_time(&self) -> Option<&GlobalTime> {
+        None
+    }
+
+    /// Called when the community is removed from the [CommunityRegistry], for example when IPv8 is
+    /// [stopped](crate::IPv8Handle::stop). The endpoint is still open, so a community can say goodbye to its peers
+    /// here. It should cancel its own tasks, as it will not receive any packets anymore.
+    fn unload(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
     /// Gets called whenever a packet is received directed at this community
     /// DO NOT OVERRIDE
     #[doc(hidden)]
@@ -70,107 +189,376 @@ pub trait Community {
         //! used to pre-decode the header and filter out messages
         //!
 
-        // Used in on_receive. Has to be outside to be testable.
-        #[doc(hidden)]
-        fn warn_deprecated(message: &str, address: Address) -> Result<(), Box<dyn Error>> {
-            warn!(
-                "Received deprecated message {} from ({:?})",
-                message, address
-            );
+        match deprecated_message(header.message_type.ok_or(Ipv8Error::MissingMessageType)? as u8) {
+            Some(message) => {
+                warn!(
+                    "Received deprecated message {} from ({:?})",
+                    message, address
+                );
 
-            #[cfg(test)]
-            {
-                WARN_DEPRECATED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-            }
+                #[cfg(test)]
+                {
+                    WARN_DEPRECATED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
 
-            Ok(())
-        }
-
-        match header.message_type.ok_or(HeaderUnwrapError)? {
-            255 => warn_deprecated("reserved-255", address),
-            254 => warn_deprecated("on-missing-sequence", address),
-            253 => warn_deprecated("missing-proof", address),
-            252 => warn_deprecated("signature-request", address),
-            251 => warn_deprecated("signature-response", address),
-            248 => warn_deprecated("on-identity", address),
-            247 => warn_deprecated("on-missing-identity", address),
-            244 => warn_deprecated("destroy-community", address),
-            243 => warn_deprecated("authorize", address),
-            242 => warn_deprecated("revoke", address),
-            241 => warn_deprecated("subjective-set", address),
-            240 => warn_deprecated("missing-subjective-set", address),
-            239 => warn_deprecated("on-missing-message", address),
-            238 => warn_deprecated("undo-own", address),
-            237 => warn_deprecated("undo-other", address),
-            236 => warn_deprecated("dynamic-settings", address),
-            235 => warn_deprecated("missing-last-message", address),
-            _ => self.on_receive(header, deserializer, address),
+                Ok(())
+            }
+            None => self.dispatch(header, deserializer, address),
         }
     }
 
-    /// This method called for every incoming message, directed at this community, which is not captured.
+    /// This method called for every incoming message, directed at this community, which is not captured and has no
+    /// [registered](Community::register_handlers) handler.
     ///
     /// Messages are captured whenever they have a reserved message_type (235 ~ 255). These are used for legacy support
     /// and some default responses which every community should give.
+    ///
+    /// By default the message is logged and ignored.
+    fn on_receive(
+        &self,
+        header: Header,
+        _deserializer: PacketDeserializer,
+        address: Address,
+    ) -> Result<(), Box<dyn Error>> {
+        debug!(
+            "Received unsupported message {:?} from {:?}",
+            header.message_type, address
+        );
+        Ok(())
+    }
+}
+
+/// Allows a community to be registered in the [CommunityRegistry] while other parts of ipv8, like a
+/// [DiscoveryStrategy](crate::peerdiscovery::discovery::DiscoveryStrategy), hold on to it as well.
+impl<C: Community + 'static> Community for Arc<C> {
+    fn new(
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Arc::new(C::new(endpoint, task_manager)?))
+    }
+
+    fn with_identity(
+        my_peer: MyPeer,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Arc::new(C::with_identity(my_peer, endpoint, task_manager)?))
+    }
+
+    fn my_peer(&self) -> Option<&MyPeer> {
+        (**self).my_peer()
+    }
+
+    fn master_peer(&self) -> Option<&MasterPeer> {
+        (**self).master_peer()
+    }
+
+    fn get_mid(&self) -> Vec<u8> {
+        (**self).get_mid()
+    }
+
+    fn signature_policy(&self, message_type: u8) -> SignaturePolicy {
+        (**self).signature_policy(message_type)
+    }
+
+    fn known_peer_key(&self, address: &Address) -> Option<Ed25519PublicKey> {
+        (**self).known_peer_key(address)
+    }
+
+    fn global_time(&self) -> Option<&GlobalTime> {
+        (**self).global_time()
+    }
+
+    fn unload(&self) -> Result<(), Box<dyn Error>> {
+        (**self).unload()
+    }
+
+    /// Dispatches to the handlers of the shared community, the [Arc] itself has none
     fn on_receive(
         &self,
         header: Header,
         deserializer: PacketDeserializer,
         address: Address,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), Box<dyn Error>> {
+        (**self).dispatch(header, deserializer, address)
+    }
 }
 
 /// Every different kind of community is registered here with it's MID.
 ///
 /// So that incoming messages can be distributed to the right communities. Makes use of a hashmap to achieve
 /// O(1) lookup time.
+///
+/// IPv8 registers its registry as a [Receiver] of its endpoint, so every received packet is
+/// [forwarded](CommunityRegistry::forward_message) to its community. Communities can be added and unloaded while
+/// packets are being handled.
 pub struct CommunityRegistry {
     // mid, community
     #[cfg(test)]
-    pub communities: HashMap<Vec<u8>, Box<dyn Community>>,
+    pub communities: RwLock<HashMap<Vec<u8>, Arc<dyn Community>>>,
     #[cfg(not(test))]
     /// A HashMap of all the communities so we can know who to send what packet
-    communities: HashMap<Vec<u8>, Box<dyn Community>>,
+    communities: RwLock<HashMap<Vec<u8>, Arc<dyn Community>>>,
+    /// The number of received packets which did not reach their community or which it failed to handle
+    failed_packets: AtomicUsize,
+    /// Drops packets of addresses and communities which send or receive too many, before their signature is checked
+    rate_limiter: RateLimiter,
+    /// Where the traffic of every community is counted
+    statistics: Arc<Statistics>,
+    /// The identity of the node, which communities are loaded with
+    my_peer: Option<MyPeer>,
 }
 
 impl CommunityRegistry {
-    /// Adds a community to the registry.
-    pub fn add_community(&mut self, item: Box<dyn Community>) -> Result<(), Box<dyn Error>> {
-        match self.communities.insert(item.get_mid(), item) {
-            // none means the key wasn't already present in the map, some means it was and it returns it.
-            // We don't care about this.
-            _ => Ok(()),
+    /// Creates an empty registry. Communities are [loaded](CommunityRegistry::load_community) with the identity of
+    /// the node, a registry without one can only get communities [added](CommunityRegistry::add_community). Incoming
+    /// packets are checked against the rate limiter and counted in the statistics.
+    pub fn new(
+        my_peer: Option<MyPeer>,
+        rate_limiter: RateLimiter,
+        statistics: Arc<Statistics>,
+    ) -> Self {
+        Self {
+            failed_packets: AtomicUsize::new(0),
+            communities: RwLock::new(HashMap::new()),
+            rate_limiter,
+            statistics,
+            my_peer,
+        }
+    }
+
+    /// Adds a community to the registry. Fails when a community with the same mid was added already, which keeps
+    /// receiving the messages of that mid.
+    pub fn add_community(&self, item: Box<dyn Community>) -> Result<(), Ipv8Error> {
+        let mid = item.get_mid();
+        match self
+            .communities
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the registered communities")))?
+            .entry(mid)
+        {
+            Entry::Occupied(entry) => Err(Ipv8Error::DuplicateCommunity {
+                mid: entry.key().clone(),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::from(item));
+                Ok(())
+            }
+        }
+    }
+
+    /// Constructs a community with the identity of the node and adds it to the registry. Returns the community, so
+    /// other parts of the application can use it too.
+    pub fn load_community<C: Community + 'static>(
+        &self,
+        endpoint: &Arc<dyn Endpoint>,
+        task_manager: &Arc<TaskManager>,
+    ) -> Result<Arc<C>, Ipv8Error> {
+        let my_peer = self.my_peer.clone().ok_or(Ipv8Error::NoIdentity)?;
+        let community = Arc::new(C::with_identity(my_peer, endpoint, task_manager)?);
+        if let Err(e) = self.add_community(Box::new(Arc::clone(&community))) {
+            // The community is not used, so its tasks should not keep running
+            if let Err(unload) = community.unload() {
+                warn!(
+                    "A community which could not be added failed to unload: {}",
+                    unload
+                );
+            }
+            return Err(e);
         }
+        Ok(community)
+    }
+
+    /// Returns the identity of the node, if it has one
+    pub fn my_peer(&self) -> Option<&MyPeer> {
+        self.my_peer.as_ref()
+    }
+
+    /// Returns the [RateLimiter] incoming packets are checked against, for example to blacklist an address
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Returns the [Statistics] received packets are counted in
+    pub fn statistics(&self) -> &Arc<Statistics> {
+        &self.statistics
+    }
+
+    /// Removes the community with the given mid from the registry and [unloads](Community::unload) it. Packets which
+    /// were already being handled by the community still reach it.
+    pub fn unload_community(&self, mid: &[u8]) -> Result<(), Ipv8Error> {
+        let community = self
+            .communities
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the registered communities")))?
+            .remove(mid)
+            .ok_or_else(|| Ipv8Error::UnknownCommunity { mid: mid.to_vec() })?;
+        Ok(community.unload()?)
+    }
+
+    /// Removes all communities from the registry and [unloads](Community::unload) them. All communities are unloaded
+    /// even when some fail to, the first error is returned.
+    pub fn unload_all(&self) -> Result<(), Ipv8Error> {
+        let communities: Vec<_> = self
+            .communities
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the registered communities")))?
+            .drain()
+            .collect();
+
+        let mut result = Ok(());
+        for (_, community) in communities {
+            if let Err(e) = community.unload() {
+                warn!("A community failed to unload: {}", e);
+                if result.is_ok() {
+                    result = Err(Ipv8Error::from(e));
+                }
+            }
+        }
+        result
     }
 
     /// Forwards the message to the corresponding community
-    pub fn forward_message(&self, packet: Packet, address: Address) -> Result<(), Box<dyn Error>> {
-        // deserialize the header
-        let deserializer = packet.start_deserialize();
+    pub fn forward_message(&self, packet: Packet, address: Address) -> Result<(), Ipv8Error> {
+        // Drop floods as early as possible, a blacklisted peer does not even get its header parsed
+        if let Err(e) = self
+            .rate_limiter
+            .check_address(&address, || self.is_verified(&address))
+        {
+            self.statistics.record_rate_limited()?;
+            return Err(e);
+        }
+        let size = packet.raw().len();
 
-        // We use peek here instead of get, even though we give the header along with the receive call
-        // this is because at this point, the header is not verified yet so we still assume the message is valid.
-        // We can't verify the header here yet as not all messages have a signature. Communities will have to decide
-        // on their own if they want to verify the header. We do give it along as only having to deserialize the header once
-        // makes it slightly more efficient.
-        let header = deserializer.peek_header()?;
+        // We use peek here instead of get, even though we give the header along with the receive call.
+        // Communities get the deserializer positioned at the header, so they can decode the message themselves.
+        // We do give the header along as only having to deserialize the header once makes it slightly more efficient.
+        let mut deserializer = packet.start_deserialize();
+        let header = match deserializer.peek_header() {
+            Ok(header) => header,
+            Err(e) => {
+                self.statistics.record_undecodable()?;
+                return Err(e);
+            }
+        };
+        let (mid, message_type) = match (header.mid_hash.as_ref(), header.message_type) {
+            (Some(mid), Some(message_type)) => (mid, message_type as u8),
+            _ => {
+                self.statistics.record_undecodable()?;
+                return Err(Ipv8Error::IncompleteHeader { address });
+            }
+        };
 
-        // get the mid from the header and use it for a hashtable lookup
-        let mid = header.mid_hash.as_ref().ok_or(MidError)?;
-        let community = self.communities.get(mid).ok_or(UnknownCommunityError)?;
+        // use the mid for a hashtable lookup. The lock is released before the community gets the packet, so
+        // communities can be added while it is handled.
+        let community = self
+            .communities
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the registered communities")))?
+            .get(mid)
+            .cloned();
+        let community = match community {
+            Some(community) => community,
+            None => {
+                self.statistics.record_unknown_community()?;
+                return Err(Ipv8Error::UnknownCommunity { mid: mid.clone() });
+            }
+        };
+        self.statistics.record_received(mid, message_type, size)?;
+        if let Err(e) = self.rate_limiter.check_community(mid) {
+            self.statistics.record_rate_limited()?;
+            return Err(e);
+        }
+
+        // Not all messages have a signature, so the community decides which ones should have one. Deprecated
+        // messages are only logged by the community, whatever their signature.
+        let policy = community.signature_policy(message_type);
+        if deprecated_message(message_type).is_none()
+            && !Self::verify(&*community, policy, &header, &mut deserializer, &address)
+        {
+            self.statistics
+                .record_signature_failure(mid, message_type)?;
+            debug!(
+                "Dropped message {} from {:?} as it is not {:?}",
+                message_type, address, policy
+            );
+            return Err(Ipv8Error::RejectedPacket {
+                mid: mid.clone(),
+                message_type,
+                address,
+                policy,
+            });
+        }
 
         // Actually forward it
-        community.receive(header, deserializer, address)
+        let mid = mid.clone();
+        let result = community.receive(header, deserializer, address);
+        if result.is_err() {
+            self.statistics.record_decode_error(&mid, message_type)?;
+        }
+        Ok(result?)
+    }
+
+    /// Returns the number of received packets which did not reach their community, or which the community failed to
+    /// handle. The [statistics](CommunityRegistry::statistics) tell why.
+    pub fn failed_packets(&self) -> usize {
+        self.failed_packets.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if any community knows the address as that of a verified peer
+    fn is_verified(&self, address: &Address) -> bool {
+        match self.communities.read() {
+            Ok(communities) => communities
+                .values()
+                .any(|community| community.known_peer_key(address).is_some()),
+            Err(_) => false,
+        }
+    }
+
+    /// Checks the packet against the signature policy. The deserializer is left positioned at the header.
+    fn verify(
+        community: &dyn Community,
+        policy: SignaturePolicy,
+        header: &Header,
+        deserializer: &mut PacketDeserializer,
+        address: &Address,
+    ) -> bool {
+        let start = deserializer.index;
+        deserializer.index += header.size;
+
+        let valid = match policy {
+            SignaturePolicy::Unsigned => true,
+            SignaturePolicy::Signed => deserializer.verify(),
+            SignaturePolicy::SignedByKnownPeer => match community.known_peer_key(address) {
+                Some(key) => deserializer.verify_with(key),
+                None => false,
+            },
+        };
+
+        deserializer.index = start;
+        valid
+    }
+}
+
+/// Forwards every received packet to its community. The endpoint has nowhere to report errors to, so they are only
+/// logged and counted.
+impl Receiver for CommunityRegistry {
+    fn on_receive(&self, packet: Packet, address: Address) {
+        if let Err(e) = self.forward_message(packet, address) {
+            self.failed_packets.fetch_add(1, Ordering::SeqCst);
+            debug!("Failed to handle a packet from {:?}: {}", address, e);
+        }
     }
 }
 
 impl Default for CommunityRegistry {
-    /// Returns a new community registry with all the built-in communities already registered.
+    /// Returns a new, empty community registry. The built-in communities are registered by [IPv8::new](crate::IPv8::new).
     /// All custom communities can be added with the [add_community](#method.add_community) method.
+    /// Packets are rate limited as in the default [Config](crate::configuration::Config).
+    /// The registry has no identity, see [new](CommunityRegistry::new).
     fn default() -> Self {
-        Self {
-            communities: HashMap::new(),
-        }
+        Self::new(None, RateLimiter::default(), Arc::new(Statistics::new()))
     }
 }
 
@@ -180,8 +568,6 @@ mod tests {
     use crate::networking::address::Address;
     use std::net::{SocketAddr, IpAddr};
     use std::error::Error;
-    use crate::networking::NetworkSender;
-    use crate::community::peer::Peer;
     use crate::community::{Community, CommunityRegistry};
     use crate::serialization::header::Header;
     use crate::serialization::{PacketDeserializer, Packet};
@@ -190,15 +576,39 @@ mod tests {
     use crate::configuration::Config;
     use crate::serialization::header::HeaderVersion::PyIPV8Header;
     use std::sync::atomic::Ordering;
-    use crate::networking::test_helper::localhost;
+    use crate::networking::simulation::SimulatedNetwork;
+    use std::time::Duration;
+    use crate::networking::test_helper::{localhost, localhost_endpoint};
     use crate::crypto::signature::KeyPair;
+    use crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
 
     pub struct TestCommunity {
-        peer: Peer,
+        master_peer: MasterPeer,
+    }
+
+    /// Creates a message of type 42 for the given community, signed like py-ipv8 does
+    fn signed_packet(mid: Vec<u8>, keypair: &KeyPair) -> Packet {
+        let mut packet = Packet::new(Header {
+            size: 23,
+            version: PyIPV8Header,
+            mid_hash: Some(mid),
+            message_type: Some(42),
+        })
+        .unwrap();
+        packet
+            .add(&BinMemberAuthenticationPayload {
+                public_key_bin: keypair.public_key().unwrap(),
+                encryption_key_bin: [0; 32],
+            })
+            .unwrap();
+        packet.sign(keypair).unwrap()
     }
 
     impl Community for TestCommunity {
-        fn new(_endpoint: &NetworkSender) -> Result<Self, Box<dyn Error>> {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
             let pk = KeyPair::from_seed_unchecked(&[
                 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
                 23, 24, 25, 26, 27, 28, 29, 30, 31,
@@ -206,20 +616,17 @@ mod tests {
             .unwrap();
             // Actually create the community
             Ok(TestCommunity {
-                peer: Peer::new(
-                    pk.public_key().unwrap(),
-                    Address(SocketAddr::new(
-                        IpAddr::V4(Ipv4Addr::new(42, 42, 42, 42)),
-                        8000,
-                    )),
-                    true,
-                ),
+                master_peer: MasterPeer::new(pk.public_key().unwrap()),
             })
         }
 
+        fn master_peer(&self) -> Option<&MasterPeer> {
+            Some(&self.master_peer)
+        }
+
         // Returns the hash of our master peer
         fn get_mid(&self) -> Vec<u8> {
-            self.peer.get_sha1()
+            self.master_peer.mid()
         }
 
         // The function which will be called when the community receives a packet
@@ -245,7 +652,7 @@ mod tests {
 
         let ipv8 = IPv8::new(config).unwrap();
 
-        let community = TestCommunity::new(&ipv8.network_sender).unwrap();
+        let community = TestCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap();
         for i in &[
             255, 254, 253, 252, 251, 248, 247, 244, 243, 242, 241, 240, 239, 238, 237, 236, 235,
         ] {
@@ -269,18 +676,305 @@ mod tests {
     fn test_add_community() {
         let config = Config::default();
         let ipv8 = IPv8::new(config).unwrap();
-        let community = Box::new(TestCommunity::new(&ipv8.network_sender).unwrap());
-        let the_same = Box::new(TestCommunity::new(&ipv8.network_sender).unwrap());
+        let community = Box::new(TestCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap());
+        let the_same = Box::new(TestCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap());
         let mid = &*community.get_mid();
-        let mut registry: CommunityRegistry = CommunityRegistry::default();
+        let registry: CommunityRegistry = CommunityRegistry::default();
 
         registry.add_community(community).unwrap();
 
-        let get = registry.communities.get(mid).unwrap();
+        let communities = registry.communities.read().unwrap();
+        let get = communities.get(mid).unwrap();
 
         assert_eq!(the_same.get_mid(), get.get_mid()); // TODO: More thorough comparison
     }
 
+    #[test]
+    fn test_add_duplicate_community() {
+        let endpoint = localhost_endpoint();
+        let community = TestCommunity::new(&endpoint, &Arc::default()).unwrap();
+        let expected = community.get_mid();
+        let registry = CommunityRegistry::default();
+        registry.add_community(Box::new(community)).unwrap();
+
+        match registry.add_community(Box::new(
+            TestCommunity::new(&endpoint, &Arc::default()).unwrap(),
+        )) {
+            Err(Ipv8Error::DuplicateCommunity { mid }) => assert_eq!(mid, expected),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_builtin_communities() {
+        let config = Config {
+            receiving_address: localhost(),
+            sending_address: localhost(),
+            ..Config::default()
+        };
+        let ipv8 = IPv8::new(config).unwrap();
+
+        assert!(ipv8
+            .communities
+            .communities
+            .read()
+            .unwrap()
+            .contains_key(&crate::community::discovery::DISCOVERY_MID[..]));
+    }
+
+    #[test]
+    fn test_load_community() {
+        struct IdentityCommunity(MyPeer, Arc<TaskManager>);
+
+        impl Community for IdentityCommunity {
+            fn new(
+                endpoint: &Arc<dyn Endpoint>,
+                task_manager: &Arc<TaskManager>,
+            ) -> Result<Self, Box<dyn Error>> {
+                Ok(IdentityCommunity(
+                    MyPeer::generate(endpoint.local_address()?)?,
+                    Arc::clone(task_manager),
+                ))
+            }
+
+            fn with_identity(
+                my_peer: MyPeer,
+                _endpoint: &Arc<dyn Endpoint>,
+                task_manager: &Arc<TaskManager>,
+            ) -> Result<Self, Box<dyn Error>> {
+                Ok(IdentityCommunity(my_peer, Arc::clone(task_manager)))
+            }
+
+            fn my_peer(&self) -> Option<&MyPeer> {
+                Some(&self.0)
+            }
+
+            fn get_mid(&self) -> Vec<u8> {
+                vec![8; 20]
+            }
+
+            fn on_receive(
+                &self,
+                _header: Header,
+                _deserializer: PacketDeserializer,
+                _address: Address,
+            ) -> Result<(), Box<dyn Error>> {
+                Ok(())
+            }
+        }
+
+        // a registry without an identity can't load communities
+        let registry = CommunityRegistry::default();
+        assert!(registry
+            .load_community::<IdentityCommunity>(&localhost_endpoint(), &Arc::default())
+            .is_err());
+
+        let config = Config {
+            sending_address: localhost(),
+            default_hosts: vec![],
+            ..Config::default()
+        };
+        let ipv8 = IPv8::new(config).unwrap();
+        let community = ipv8.load_community::<IdentityCommunity>().unwrap();
+        let node = ipv8.communities.my_peer().unwrap();
+        assert_eq!(community.0.public_key(), node.public_key());
+        assert_eq!(node.address(), &ipv8.endpoint.local_address().unwrap());
+        // its tasks run with those of the node
+        assert!(Arc::ptr_eq(&community.1, &ipv8.task_manager));
+
+        // the built-in communities sign with the same key
+        let communities = ipv8.communities.communities.read().unwrap();
+        let discovery = &communities[&crate::community::discovery::DISCOVERY_MID[..]];
+        assert_eq!(discovery.my_peer().unwrap().public_key(), node.public_key());
+        assert!(communities.contains_key(&vec![8; 20]));
+    }
+
+    #[test]
+    fn test_shared_community() {
+        let sender = localhost_endpoint();
+        let community = Arc::new(TestCommunity::new(&sender, &Arc::default()).unwrap());
+        let mid = community.get_mid();
+        let registry = CommunityRegistry::default();
+        registry.add_community(Box::new(community.clone())).unwrap();
+
+        registry
+            .forward_message(signed_packet(mid, &KeyPair::new().unwrap()), localhost())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_signature_policy() {
+        struct PolicyCommunity {
+            known: KeyPair,
+        }
+
+        impl Community for PolicyCommunity {
+            fn new(
+                _endpoint: &Arc<dyn Endpoint>,
+                _task_manager: &Arc<TaskManager>,
+            ) -> Result<Self, Box<dyn Error>> {
+                Ok(PolicyCommunity {
+                    known: KeyPair::new()?,
+                })
+            }
+
+            fn get_mid(&self) -> Vec<u8> {
+                vec![7; 20]
+            }
+
+            fn signature_policy(&self, message_type: u8) -> SignaturePolicy {
+                match message_type {
+                    1 => SignaturePolicy::Unsigned,
+                    2 => SignaturePolicy::SignedByKnownPeer,
+                    _ => SignaturePolicy::Signed,
+                }
+            }
+
+            fn known_peer_key(&self, _address: &Address) -> Option<Ed25519PublicKey> {
+                self.known.public_key().ok()
+            }
+
+            fn on_receive(
+                &self,
+                _header: Header,
+                _deserializer: PacketDeserializer,
+                _address: Address,
+            ) -> Result<(), Box<dyn Error>> {
+                Ok(())
+            }
+        }
+
+        let sender = localhost_endpoint();
+        let community = Arc::new(PolicyCommunity::new(&sender, &Arc::default()).unwrap());
+        let registry = CommunityRegistry::default();
+        registry.add_community(Box::new(community.clone())).unwrap();
+
+        let packet =
+            |message_type: u8| Packet::new(Header::py_ipv8_header([7; 20], message_type)).unwrap();
+
+        // unsigned messages only pass when they are allowed to be unsigned
+        registry.forward_message(packet(1), localhost()).unwrap();
+        assert!(registry.forward_message(packet(3), localhost()).is_err());
+
+        // signed with the key of the peer at the address
+        registry
+            .forward_message(packet(2).sign(&community.known).unwrap(), localhost())
+            .unwrap();
+        assert!(registry
+            .forward_message(
+                packet(2).sign(&KeyPair::new().unwrap()).unwrap(),
+                localhost()
+            )
+            .is_err());
+
+        // signed with the key in the message, which has to match the signature
+        let mut forged = signed_packet(vec![7; 20], &KeyPair::new().unwrap());
+        let index = forged.0.len() - 65;
+        forged.0[index] ^= 1;
+        assert!(registry.forward_message(forged, localhost()).is_err());
+        registry
+            .forward_message(
+                signed_packet(vec![7; 20], &KeyPair::new().unwrap()),
+                localhost(),
+            )
+            .unwrap();
+
+        let snapshot = registry.statistics().snapshot().unwrap();
+        assert_eq!(snapshot.community(&[7; 20]).signature_failures, 3);
+    }
+
+    #[test]
+    fn test_deprecated_unsigned() {
+        let community = TestCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let mid = community.get_mid();
+        let registry = CommunityRegistry::default();
+        registry.add_community(Box::new(community)).unwrap();
+
+        // deprecated messages are captured before their signature is checked, so even a bare header passes
+        let packet = Packet::new(Header {
+            size: 23,
+            version: PyIPV8Header,
+            mid_hash: Some(mid.clone()),
+            message_type: Some(255),
+        })
+        .unwrap();
+        registry.forward_message(packet, localhost()).unwrap();
+
+        let snapshot = registry.statistics().snapshot().unwrap();
+        assert_eq!(snapshot.messages[&mid][&255].signature_failures, 0);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let community = TestCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let mid = community.get_mid();
+        let registry = CommunityRegistry::default();
+        registry.add_community(Box::new(community)).unwrap();
+
+        let packet = signed_packet(mid.clone(), &KeyPair::new().unwrap());
+        let size = packet.raw().len();
+        registry.forward_message(packet, localhost()).unwrap();
+        let mut forged = signed_packet(mid.clone(), &KeyPair::new().unwrap());
+        let index = forged.0.len() - 65;
+        forged.0[index] ^= 1;
+        assert!(registry.forward_message(forged, localhost()).is_err());
+        assert!(registry
+            .forward_message(
+                signed_packet(vec![9; 20], &KeyPair::new().unwrap()),
+                localhost()
+            )
+            .is_err());
+        assert!(registry
+            .forward_message(Packet(vec![1, 2, 3]), localhost())
+            .is_err());
+
+        let snapshot = registry.statistics().snapshot().unwrap();
+        let statistics = snapshot.messages[&mid][&42];
+        assert_eq!(statistics.packets_received, 2);
+        assert_eq!(statistics.bytes_received, 2 * size);
+        assert_eq!(statistics.signature_failures, 1);
+        assert_eq!(statistics.decode_errors, 0);
+        assert_eq!(snapshot.unknown_community, 1);
+        assert_eq!(snapshot.undecodable, 1);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let config = Config {
+            address_rate_limit: Some(ratelimit::RateLimit {
+                rate: 0.001,
+                burst: 3.0,
+            }),
+            blacklist_threshold: 2,
+            ..Config::default()
+        };
+
+        let community = TestCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let mid = community.get_mid();
+        let registry =
+            CommunityRegistry::new(None, RateLimiter::new(&config), Arc::new(Statistics::new()));
+        registry.add_community(Box::new(community)).unwrap();
+
+        let keypair = KeyPair::new().unwrap();
+        let address = localhost();
+        for _ in 0..3 {
+            registry
+                .forward_message(signed_packet(mid.clone(), &keypair), address)
+                .unwrap();
+        }
+        for _ in 0..2 {
+            assert!(registry
+                .forward_message(signed_packet(mid.clone(), &keypair), address)
+                .is_err());
+        }
+
+        // dropped packets are not even verified
+        let snapshot = registry.statistics().snapshot().unwrap();
+        assert_eq!(snapshot.community(&mid).signature_failures, 0);
+        assert!(registry.rate_limiter().is_blacklisted(&address).unwrap());
+        assert_eq!(registry.rate_limiter().limited_packets(), 2);
+    }
+
     #[test]
     fn test_networking() {
         let mut config = Config::default();
@@ -288,24 +982,16 @@ mod tests {
         config.sending_address = localhost();
         config.buffersize = 2048;
 
-        let mut ipv8 = IPv8::new(config).unwrap();
+        let ipv8 = IPv8::new(config).unwrap();
 
-        let community = TestCommunity::new(&ipv8.network_sender).unwrap();
+        let community = TestCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap();
         let mid = community.get_mid();
 
         ipv8.communities.add_community(Box::new(community)).unwrap();
 
         // now simulate a packet coming in
-        // Create a packet to test the community with
-        let packet = Packet::new(Header {
-            size: 23,
-            version: PyIPV8Header,
-            mid_hash: Some(mid),
-            message_type: Some(42),
-        })
-        .unwrap();
-
-        // Normally you would want to sign the packet here
+        // Create a packet to test the community with, signed as the community requires by default
+        let packet = signed_packet(mid, &KeyPair::new().unwrap());
 
         // Send the packet
         ipv8.communities
@@ -318,4 +1004,81 @@ mod tests {
             )
             .unwrap();
     }
+
+    #[test]
+    fn test_receive_from_network() {
+        struct CountingCommunity(Arc<AtomicUsize>);
+
+        impl Community for CountingCommunity {
+            fn new(
+                _endpoint: &Arc<dyn Endpoint>,
+                _task_manager: &Arc<TaskManager>,
+            ) -> Result<Self, Box<dyn Error>> {
+                Ok(CountingCommunity(Arc::new(AtomicUsize::new(0))))
+            }
+
+            fn get_mid(&self) -> Vec<u8> {
+                vec![5; 20]
+            }
+
+            fn signature_policy(&self, _message_type: u8) -> SignaturePolicy {
+                SignaturePolicy::Unsigned
+            }
+
+            fn on_receive(
+                &self,
+                _header: Header,
+                _deserializer: PacketDeserializer,
+                _address: Address,
+            ) -> Result<(), Box<dyn Error>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let network = SimulatedNetwork::new(0);
+        let address = Address("10.0.0.1:8000".parse().unwrap());
+        let endpoint = network.add_endpoint(address);
+        let config = Config {
+            default_hosts: vec![],
+            ..Config::default()
+        };
+        let ipv8 = IPv8::with_endpoint(config, endpoint.clone(), endpoint.task_manager()).unwrap();
+        let community = CountingCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap();
+        let count = Arc::clone(&community.0);
+        let handle = ipv8.start().unwrap();
+
+        // communities added to a running node receive packets too
+        handle
+            .communities
+            .add_community(Box::new(community))
+            .unwrap();
+        let sender = network.add_endpoint(Address("10.0.0.2:8000".parse().unwrap()));
+        sender
+            .send(
+                &address,
+                Packet::new(Header::py_ipv8_header([5; 20], 1)).unwrap(),
+            )
+            .unwrap();
+        sender.send(&address, Packet(vec![1, 2, 3])).unwrap();
+        network.advance(Duration::from_millis(50)).unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(handle.communities.failed_packets(), 1);
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn test_unload() {
+        let registry = CommunityRegistry::default();
+        let community = TestCommunity::new(&localhost_endpoint(), &Arc::default()).unwrap();
+        let mid = community.get_mid();
+        registry.add_community(Box::new(community)).unwrap();
+
+        registry.unload_community(&mid).unwrap();
+        assert!(registry.unload_community(&mid).is_err());
+        assert!(registry
+            .forward_message(signed_packet(mid, &KeyPair::new().unwrap()), localhost())
+            .is_err());
+    }
 }
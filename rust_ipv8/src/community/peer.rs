@@ -1,10 +1,21 @@
 //! The Peer module containg the peer struct which is used to keep track of a peer's public key, address and "intro"
+//!
+//! Also contains the identities of a community itself: [MyPeer], the key we sign our messages with, and
+//! [MasterPeer], the key the community is identified by.
 
-use crate::crypto::signature::{Ed25519PublicKey};
+use crate::crypto::signature::{Ed25519PublicKey, KeyPair};
+use crate::error::Ipv8Error;
 use crate::networking::address::Address;
 use ring::digest::{digest, SHA1};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The number of ping round trip times remembered per peer
+const PING_HISTORY: usize = 5;
 
 /// Represents an IPv8 peer. Contains it's address and key.
+#[derive(Debug, Clone)]
 pub struct Peer {
     /// The peer's public key. It's sha1 hash is used to identify incoming messages.
     key: Ed25519PublicKey,
@@ -14,15 +25,28 @@ pub struct Peer {
     ///
     /// **_Note_**: We are not completely sure what this does please refer to py-ipv8 for more information
     intro: bool,
+    /// The last time we received a message from this peer
+    last_response: Instant,
+    /// The last time we sent a message to this peer, if ever
+    last_request: Option<Instant>,
+    /// The round trip times of the last few pings to this peer
+    pings: VecDeque<Duration>,
+    /// The number of requests in a row this peer did not respond to
+    failures: u32,
 }
 
 impl Peer {
-    /// Constructs a new Peer object
-    pub fn new(key: Ed25519PublicKey, address: Address, intro: bool) -> Self {
+    /// Constructs a new Peer object, which we just received a message from at `now`. The time comes from the
+    /// [Clock](crate::taskmanager::Clock) of the [Network](crate::peerdiscovery::network::Network) it is added to.
+    pub fn new(key: Ed25519PublicKey, address: Address, intro: bool, now: Instant) -> Self {
         Self {
             key,
             address,
             intro,
+            last_response: now,
+            last_request: None,
+            pings: VecDeque::with_capacity(PING_HISTORY),
+            failures: 0,
         }
     }
 
@@ -31,15 +55,179 @@ impl Peer {
     pub fn get_sha1(&self) -> Vec<u8> {
         digest(&SHA1, &self.key).as_ref().to_owned()
     }
+
+    /// Returns the public key of this peer
+    pub fn key(&self) -> &Ed25519PublicKey {
+        &self.key
+    }
+
+    /// Returns the address we last received a message from this peer on
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Returns if this peer is an "intro", see the note on the field.
+    pub fn intro(&self) -> bool {
+        self.intro
+    }
+
+    /// Returns the last time we received a message from this peer
+    pub fn last_response(&self) -> Instant {
+        self.last_response
+    }
+
+    /// Returns how long before `now` we last received a message from this peer
+    pub fn time_since_last_response(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_response)
+    }
+
+    /// Marks that we received a message from this peer on the given address at `now`.
+    /// Peers can change address when their NAT mapping changes.
+    pub fn update(&mut self, address: Address, now: Instant) {
+        self.address = address;
+        self.last_response = now;
+        self.failures = 0;
+    }
+
+    /// Returns the last time we sent a message to this peer, if we ever did
+    pub fn last_request(&self) -> Option<Instant> {
+        self.last_request
+    }
+
+    /// Marks that we sent a message to this peer at `now`
+    pub fn update_last_request(&mut self, now: Instant) {
+        self.last_request = Some(now);
+    }
+
+    /// Remembers the round trip time of a ping. Only the last few are kept.
+    pub fn add_ping(&mut self, round_trip_time: Duration) {
+        if self.pings.len() == PING_HISTORY {
+            self.pings.pop_front();
+        }
+        self.pings.push_back(round_trip_time);
+    }
+
+    /// Returns the round trip times of the last few pings, oldest first
+    pub fn pings(&self) -> &VecDeque<Duration> {
+        &self.pings
+    }
+
+    /// Returns the median round trip time of the last few pings, or None if this peer was never pinged
+    pub fn get_median_ping(&self) -> Option<Duration> {
+        if self.pings.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.pings.iter().cloned().collect();
+        sorted.sort();
+        Some(sorted[sorted.len() / 2])
+    }
+
+    /// Returns the average round trip time of the last few pings, or None if this peer was never pinged
+    pub fn get_average_ping(&self) -> Option<Duration> {
+        if self.pings.is_empty() {
+            return None;
+        }
+        Some(self.pings.iter().sum::<Duration>() / self.pings.len() as u32)
+    }
+
+    /// Returns the number of requests in a row this peer did not respond to
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Marks that this peer did not respond to a request. Reset once it responds again.
+    pub fn add_failure(&mut self) {
+        self.failures += 1;
+    }
+}
+
+/// Our own identity: the key we sign messages with and the address we receive them on, like py-ipv8's `my_peer`.
+///
+/// Usually the whole node uses one identity, which the [CommunityRegistry](crate::community::CommunityRegistry) hands
+/// to the communities it [loads](crate::community::CommunityRegistry::load_community). Clones share the key.
+#[derive(Clone)]
+pub struct MyPeer {
+    /// The key our messages are signed with
+    keypair: Arc<KeyPair>,
+    /// The public part of the key, which other peers know us by
+    public_key: Ed25519PublicKey,
+    /// The address we receive messages on
+    address: Address,
+}
+
+impl MyPeer {
+    /// Creates an identity with the given key, receiving on the given address
+    pub fn new(keypair: Arc<KeyPair>, address: Address) -> Result<Self, Ipv8Error> {
+        Ok(Self {
+            public_key: keypair.public_key()?,
+            keypair,
+            address,
+        })
+    }
+
+    /// Creates an identity with a new random key, receiving on the given address
+    pub fn generate(address: Address) -> Result<Self, Ipv8Error> {
+        Self::new(Arc::new(KeyPair::new()?), address)
+    }
+
+    /// Returns the key our messages are signed with
+    pub fn keypair(&self) -> &KeyPair {
+        &self.keypair
+    }
+
+    /// Returns our public key
+    pub fn public_key(&self) -> &Ed25519PublicKey {
+        &self.public_key
+    }
+
+    /// Returns the address we receive messages on
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Returns the sha1 hash of our public key, which other peers know us by
+    pub fn get_sha1(&self) -> Vec<u8> {
+        digest(&SHA1, &self.public_key).as_ref().to_owned()
+    }
+
+    /// Returns ourselves as other peers see us, as if we responded at `now`
+    pub fn to_peer(&self, now: Instant) -> Peer {
+        Peer::new(self.public_key, self.address, false, now)
+    }
+}
+
+/// The master peer of a community. Only its public key matters: its sha1 hash is the mid the community is known by.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MasterPeer {
+    /// The public key of the master peer
+    key: Ed25519PublicKey,
+}
+
+impl MasterPeer {
+    /// Creates the master peer with the given public key
+    pub fn new(key: Ed25519PublicKey) -> Self {
+        Self { key }
+    }
+
+    /// Returns the public key of the master peer
+    pub fn key(&self) -> &Ed25519PublicKey {
+        &self.key
+    }
+
+    /// Returns the mid of the community, the sha1 hash of the public key
+    pub fn mid(&self) -> Vec<u8> {
+        digest(&SHA1, &self.key).as_ref().to_owned()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::community::peer::Peer;
+    use crate::community::peer::{MasterPeer, MyPeer, Peer};
 
     use std::net::{Ipv4Addr, SocketAddr, IpAddr};
     use crate::networking::address::Address;
     use crate::crypto::signature::KeyPair;
+    use std::time::{Duration, Instant};
 
     fn get_key() -> KeyPair {
         KeyPair::from_seed_unchecked(&[
@@ -58,10 +246,88 @@ mod tests {
 
     #[test]
     fn contructor_test() {
-        let peer = Peer::new(get_key().public_key().unwrap(), get_addr(), true);
+        let peer = Peer::new(
+            get_key().public_key().unwrap(),
+            get_addr(),
+            true,
+            Instant::now(),
+        );
 
         assert_eq!(get_key().public_key().unwrap(), peer.key);
         assert_eq!(get_addr(), peer.address);
         assert_eq!(true, peer.intro);
     }
+
+    #[test]
+    fn accessor_test() {
+        let mut peer = Peer::new(
+            get_key().public_key().unwrap(),
+            get_addr(),
+            false,
+            Instant::now(),
+        );
+        let created = peer.last_response();
+
+        assert_eq!(&get_key().public_key().unwrap(), peer.key());
+        assert_eq!(&get_addr(), peer.address());
+        assert!(!peer.intro());
+
+        let new_address = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 1));
+        let later = created + Duration::from_secs(5);
+        peer.update(new_address, later);
+        assert_eq!(&new_address, peer.address());
+        assert_eq!(later, peer.last_response());
+        assert_eq!(
+            Duration::from_secs(2),
+            peer.time_since_last_response(later + Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn liveness_test() {
+        let mut peer = Peer::new(
+            get_key().public_key().unwrap(),
+            get_addr(),
+            true,
+            Instant::now(),
+        );
+        assert_eq!(None, peer.last_request());
+        assert_eq!(None, peer.get_median_ping());
+
+        peer.update_last_request(Instant::now());
+        assert!(peer.last_request().is_some());
+
+        for millis in 1..=6 {
+            peer.add_ping(Duration::from_millis(millis * 10));
+        }
+        // only the last five pings are kept
+        assert_eq!(5, peer.pings().len());
+        assert_eq!(Some(Duration::from_millis(40)), peer.get_median_ping());
+        assert_eq!(Some(Duration::from_millis(40)), peer.get_average_ping());
+
+        peer.add_failure();
+        peer.add_failure();
+        assert_eq!(2, peer.failures());
+        peer.update(get_addr(), Instant::now());
+        assert_eq!(0, peer.failures());
+    }
+
+    #[test]
+    fn identity_test() {
+        let my_peer = MyPeer::new(std::sync::Arc::new(get_key()), get_addr()).unwrap();
+        let peer = Peer::new(
+            get_key().public_key().unwrap(),
+            get_addr(),
+            true,
+            Instant::now(),
+        );
+
+        assert_eq!(&get_key().public_key().unwrap(), my_peer.public_key());
+        assert_eq!(peer.get_sha1(), my_peer.get_sha1());
+        assert_eq!(my_peer.to_peer(Instant::now()).key(), peer.key());
+        assert_eq!(my_peer.clone().address(), &get_addr());
+
+        let master_peer = MasterPeer::new(get_key().public_key().unwrap());
+        assert_eq!(peer.get_sha1(), master_peer.mid());
+    }
 }
@@ -0,0 +1,452 @@
+//! Rate limiting of the packets the [CommunityRegistry](crate::community::CommunityRegistry) forwards.
+//!
+//! Every packet costs a token from the bucket of the address it came from and one from the bucket of the community it
+//! is for. Buckets refill at a steady [rate](RateLimit::rate) up to their [burst](RateLimit::burst) size, and a packet
+//! which finds its bucket empty is dropped before its signature is checked. An address which keeps sending after its
+//! bucket ran dry is blacklisted for a while, and all of its packets are dropped until the blacklisting expires.
+//!
+//! Source addresses are easily spoofed, so a flood from the address of a bootstrap server or a verified peer does not
+//! tell it came from that node. Those addresses are only rate limited and never blacklisted, otherwise anyone could cut
+//! a node off from the peers it depends on.
+
+use crate::configuration::Config;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::taskmanager::{Clock, SystemClock};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of buckets kept before idle ones are cleaned up
+const MIN_PRUNE_SIZE: usize = 1024;
+
+/// The limits of a token bucket
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RateLimit {
+    /// The number of packets per second accepted in the long run
+    pub rate: f64,
+    /// The number of packets accepted at once after a quiet period
+    pub burst: f64,
+}
+
+/// A token bucket
+#[derive(Debug)]
+struct Bucket {
+    /// The number of packets which can be accepted right now
+    tokens: f64,
+    /// The last time tokens were added
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Creates a full bucket
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    /// Refills the bucket and takes a token out of it. Returns false when there was no token to take.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds the tokens which came in since the last update
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let passed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + passed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// True when the bucket is full, so forgetting it changes nothing
+    fn is_full(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        self.tokens >= limit.burst
+    }
+}
+
+/// The rate limiting state of an address
+#[derive(Debug)]
+struct Sender {
+    /// The tokens of the address
+    bucket: Bucket,
+    /// The number of packets dropped since `first_violation`
+    violations: usize,
+    /// The first dropped packet counted in `violations`
+    first_violation: Option<Instant>,
+}
+
+/// The buckets and blacklist behind the lock
+#[derive(Default)]
+struct State {
+    /// The state of every address which sent a packet recently
+    senders: HashMap<Address, Sender>,
+    /// The bucket of every community which received a packet recently
+    communities: HashMap<Vec<u8>, Bucket>,
+    /// The blacklisted addresses, with the time their blacklisting expires
+    blacklist: HashMap<Address, Instant>,
+    /// The number of buckets at which idle buckets are cleaned up next
+    prune_at: usize,
+}
+
+/// Drops packets of addresses and communities which exceed their rate limit, see the
+/// [module documentation](crate::community::ratelimit).
+pub struct RateLimiter {
+    /// The limit for every address, or None to not limit addresses
+    address_limit: Option<RateLimit>,
+    /// The limit for every community, or None to not limit communities
+    community_limit: Option<RateLimit>,
+    /// The number of dropped packets after which an address is blacklisted, or 0 to never blacklist
+    blacklist_threshold: usize,
+    /// How long an address stays blacklisted, and the period in which its dropped packets are counted
+    blacklist_duration: Duration,
+    /// The addresses of the bootstrap servers, which are never blacklisted
+    bootstrap_addresses: HashSet<Address>,
+    /// The buckets and blacklist
+    state: Mutex<State>,
+    /// The clock the buckets refill by
+    clock: Arc<dyn Clock>,
+    /// The number of packets dropped because of a rate limit or the blacklist
+    limited_packets: AtomicUsize,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter with the limits of the configuration
+    pub fn new(config: &Config) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Creates a rate limiter with the limits of the configuration, whose buckets refill by the given clock
+    pub fn with_clock(config: &Config, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            address_limit: config.address_rate_limit,
+            community_limit: config.community_rate_limit,
+            blacklist_threshold: config.blacklist_threshold,
+            blacklist_duration: config.blacklist_duration,
+            bootstrap_addresses: config.default_hosts.iter().copied().collect(),
+            state: Mutex::new(State {
+                prune_at: MIN_PRUNE_SIZE,
+                ..State::default()
+            }),
+            clock,
+            limited_packets: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a rate limiter which accepts every packet
+    pub fn unlimited() -> Self {
+        Self::new(&Config {
+            address_rate_limit: None,
+            community_rate_limit: None,
+            blacklist_threshold: 0,
+            ..Config::default()
+        })
+    }
+
+    /// Takes a token for a packet from the address, blacklisting it when it sent too many packets while out of tokens.
+    /// Fails when the packet has to be dropped.
+    ///
+    /// `is_verified` is only asked before blacklisting the address. When it returns true the address belongs to a
+    /// verified peer, which is rate limited but not blacklisted, just like a bootstrap server.
+    pub fn check_address(
+        &self,
+        address: &Address,
+        is_verified: impl FnOnce() -> bool,
+    ) -> Result<(), Ipv8Error> {
+        let now = self.clock.now();
+        let mut state = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the rate limiter")))?;
+
+        if let Some(until) = state.blacklist.get(address) {
+            if *until > now {
+                self.limited_packets.fetch_add(1, Ordering::SeqCst);
+                return Err(Ipv8Error::Blacklisted { address: *address });
+            }
+            state.blacklist.remove(address);
+        }
+
+        let limit = match &self.address_limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        Self::prune(&mut state, limit, now);
+        let sender = state.senders.entry(*address).or_insert_with(|| Sender {
+            bucket: Bucket::new(limit, now),
+            violations: 0,
+            first_violation: None,
+        });
+        if sender.bucket.take(limit, now) {
+            return Ok(());
+        }
+
+        // Only count the dropped packets of the last blacklist period
+        match sender.first_violation {
+            Some(first) if now.saturating_duration_since(first) < self.blacklist_duration => {
+                sender.violations += 1
+            }
+            _ => {
+                sender.first_violation = Some(now);
+                sender.violations = 1;
+            }
+        }
+        let exceeded =
+            self.blacklist_threshold > 0 && sender.violations >= self.blacklist_threshold;
+        if exceeded {
+            sender.violations = 0;
+            sender.first_violation = None;
+        }
+        // The lock is not held while asking for verified peers, which takes the locks of the communities
+        drop(state);
+        self.limited_packets.fetch_add(1, Ordering::SeqCst);
+
+        if exceeded {
+            if self.bootstrap_addresses.contains(address) || is_verified() {
+                debug!(
+                    "Not blacklisting {:?}, its address may be spoofed by whoever exceeds its rate limit",
+                    address
+                );
+            } else {
+                warn!(
+                    "Blacklisted {:?} for {:?} as it keeps exceeding its rate limit",
+                    address, self.blacklist_duration
+                );
+                self.blacklist(*address, self.blacklist_duration)?;
+            }
+        }
+        Err(Ipv8Error::AddressRateLimited { address: *address })
+    }
+
+    /// Takes a token for a packet for the community with the given mid. Fails when the packet has to be dropped.
+    pub fn check_community(&self, mid: &[u8]) -> Result<(), Ipv8Error> {
+        let limit = match &self.community_limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let now = self.clock.now();
+        let mut state = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the rate limiter")))?;
+
+        let accepted = match state.communities.get_mut(mid) {
+            Some(bucket) => bucket.take(limit, now),
+            None => {
+                let mut bucket = Bucket::new(limit, now);
+                let accepted = bucket.take(limit, now);
+                state.communities.insert(mid.to_vec(), bucket);
+                accepted
+            }
+        };
+
+        if accepted {
+            Ok(())
+        } else {
+            self.limited_packets.fetch_add(1, Ordering::SeqCst);
+            Err(Ipv8Error::CommunityRateLimited { mid: mid.to_vec() })
+        }
+    }
+
+    /// Blacklists an address for the given time, all of its packets are dropped until then
+    pub fn blacklist(&self, address: Address, duration: Duration) -> Result<(), Ipv8Error> {
+        let until = self.clock.now() + duration;
+        self.state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the rate limiter")))?
+            .blacklist
+            .insert(address, until);
+        Ok(())
+    }
+
+    /// Returns true if the packets of the address are currently dropped because it is blacklisted
+    pub fn is_blacklisted(&self, address: &Address) -> Result<bool, Ipv8Error> {
+        let now = self.clock.now();
+        Ok(self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the rate limiter")))?
+            .blacklist
+            .get(address)
+            .is_some_and(|until| *until > now))
+    }
+
+    /// Returns the number of packets which were dropped because of a rate limit or the blacklist
+    pub fn limited_packets(&self) -> usize {
+        self.limited_packets.load(Ordering::SeqCst)
+    }
+
+    /// Forgets the full buckets and expired blacklistings once there are many buckets, so spoofed source addresses
+    /// can not make the limiter grow without bounds
+    fn prune(state: &mut State, limit: &RateLimit, now: Instant) {
+        if state.senders.len() < state.prune_at {
+            return;
+        }
+
+        state
+            .senders
+            .retain(|_, sender| !sender.bucket.is_full(limit, now));
+        state.blacklist.retain(|_, until| *until > now);
+        state.prune_at = (state.senders.len() * 2).max(MIN_PRUNE_SIZE);
+    }
+}
+
+impl Default for RateLimiter {
+    /// Returns a rate limiter with the limits of the default [Config]
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::simulation::VirtualClock;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    fn limiter(threshold: usize) -> (RateLimiter, Arc<VirtualClock>) {
+        let config = Config {
+            address_rate_limit: Some(RateLimit {
+                rate: 10.0,
+                burst: 5.0,
+            }),
+            community_rate_limit: Some(RateLimit {
+                rate: 100.0,
+                burst: 8.0,
+            }),
+            blacklist_threshold: threshold,
+            blacklist_duration: Duration::from_secs(60),
+            default_hosts: vec![bootstrap_address()],
+            ..Config::default()
+        };
+
+        let clock = Arc::new(VirtualClock::new());
+        (RateLimiter::with_clock(&config, clock.clone()), clock)
+    }
+
+    fn address() -> Address {
+        Address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(42, 42, 42, 42)),
+            42,
+        ))
+    }
+
+    fn other_address() -> Address {
+        Address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(42, 42, 42, 42)),
+            43,
+        ))
+    }
+
+    fn bootstrap_address() -> Address {
+        Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 42))
+    }
+
+    #[test]
+    fn test_address_bucket() {
+        let (limiter, clock) = limiter(0);
+
+        for _ in 0..5 {
+            limiter.check_address(&address(), || false).unwrap();
+        }
+        assert!(limiter.check_address(&address(), || false).is_err());
+        // other addresses have their own bucket
+        limiter.check_address(&other_address(), || false).unwrap();
+
+        // 10 packets per second is one every 100ms
+        clock.advance(Duration::from_millis(100));
+        limiter.check_address(&address(), || false).unwrap();
+        assert!(limiter.check_address(&address(), || false).is_err());
+        assert_eq!(limiter.limited_packets(), 2);
+    }
+
+    #[test]
+    fn test_community_bucket() {
+        let (limiter, clock) = limiter(0);
+
+        for _ in 0..8 {
+            limiter.check_community(&[1; 20]).unwrap();
+        }
+        assert!(limiter.check_community(&[1; 20]).is_err());
+        limiter.check_community(&[2; 20]).unwrap();
+
+        clock.advance(Duration::from_millis(10));
+        limiter.check_community(&[1; 20]).unwrap();
+    }
+
+    #[test]
+    fn test_blacklist() {
+        let (limiter, clock) = limiter(3);
+
+        for _ in 0..5 {
+            limiter.check_address(&address(), || false).unwrap();
+        }
+        for _ in 0..3 {
+            assert!(limiter.check_address(&address(), || false).is_err());
+        }
+        assert!(limiter.is_blacklisted(&address()).unwrap());
+        assert!(!limiter.is_blacklisted(&other_address()).unwrap());
+
+        // a refilled bucket does not help while blacklisted
+        clock.advance(Duration::from_secs(30));
+        assert!(limiter.check_address(&address(), || false).is_err());
+
+        clock.advance(Duration::from_secs(30));
+        assert!(!limiter.is_blacklisted(&address()).unwrap());
+        limiter.check_address(&address(), || false).unwrap();
+    }
+
+    #[test]
+    fn test_blacklist_exempt() {
+        let (limiter, _) = limiter(3);
+
+        // a flood from these addresses may well be spoofed
+        for _ in 0..10 {
+            let _ = limiter.check_address(&bootstrap_address(), || false);
+            let _ = limiter.check_address(&address(), || true);
+        }
+        assert!(!limiter.is_blacklisted(&bootstrap_address()).unwrap());
+        assert!(!limiter.is_blacklisted(&address()).unwrap());
+        // they are still rate limited
+        assert!(limiter.check_address(&address(), || true).is_err());
+        assert_eq!(limiter.limited_packets(), 11);
+    }
+
+    #[test]
+    fn test_prune() {
+        let (limiter, clock) = limiter(0);
+        for port in 0..MIN_PRUNE_SIZE as u16 {
+            let address = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+            limiter.check_address(&address, || false).unwrap();
+        }
+        assert_eq!(limiter.state.lock().unwrap().senders.len(), MIN_PRUNE_SIZE);
+
+        // all buckets are full again, so they are forgotten
+        clock.advance(Duration::from_secs(1));
+        limiter.check_address(&address(), || false).unwrap();
+        assert_eq!(limiter.state.lock().unwrap().senders.len(), 1);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = RateLimiter::unlimited();
+        for _ in 0..10_000 {
+            limiter.check_address(&address(), || false).unwrap();
+            limiter.check_community(&[1; 20]).unwrap();
+        }
+
+        // manual blacklisting still works
+        limiter
+            .blacklist(address(), Duration::from_secs(60))
+            .unwrap();
+        assert!(limiter.check_address(&address(), || false).is_err());
+    }
+}
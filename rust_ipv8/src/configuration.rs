@@ -1,44 +1,251 @@
 //! In this module, the IPv8 configuration can be specified.
 //!
-//! Also contains the default IPv8 configuration.
+//! Also contains the default IPv8 configuration, and [Config::from_file] which reads the configuration from a TOML
+//! file, modelled on the `default_config` of py-ipv8:
+//!
+//! ```toml
+//! # off, error, warn, info, debug or trace
+//! logging_level = "info"
+//! # The key of this node in the pkcs8 format, generated when the file does not exist yet
+//! key_file = "ec.pk8"
+//! # The time between two steps of the walker (s)
+//! walker_interval = 0.5
+//! # The number of peers the built-in DiscoveryCommunity walks towards
+//! target_peers = 20
+//! # Replaces the default bootstrap servers
+//! bootstrap = ["130.161.119.206:6421", "131.180.27.161:6521"]
+//!
+//! [interface]
+//! ip = "0.0.0.0"
+//! port = 8090
+//!
+//! # Communities started by the Launcher, by the class name they were registered with
+//! [[overlays]]
+//! class = "MyCommunity"
+//! walkers = [{ strategy = "RandomWalk", peers = 20 }, { strategy = "RandomChurn", peers = -1 }]
+//! ```
+//!
+//! Everything is optional, whatever is missing keeps its default.
 
-use std::net::{Ipv4Addr, SocketAddr, IpAddr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, IpAddr};
 use std::time::Duration;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::LevelFilter;
+use serde::Deserialize;
 use crate::networking::address::Address;
+use crate::error::Ipv8Error;
+use crate::community::ratelimit::RateLimit;
+use crate::networking::queue::DropPolicy;
 
 /// This struct configures the IPv8 instance. It contains all the options
 /// you can change when using rust_ipv8 in your code.
+#[derive(Clone)]
 pub struct Config {
-    /// the amount of space reserved for queueing up incoming messages (messages)
+    /// the max number of incoming messages waiting to be handled (messages)
     pub queuesize: usize,
+    /// which message is dropped when a message comes in while [queuesize](Config::queuesize) messages are waiting
+    pub drop_policy: DropPolicy,
     /// the size of the buffer reserved for incoming messages (bytes)
     pub buffersize: usize,
+    /// the max number of messages received with a single system call (messages)
+    pub batchsize: usize,
     /// frequency at which polling times out and events are checked (ms)
     /// None is as fast as possible
     pub pollinterval: Option<Duration>,
     /// the max number of threads to use in the network manager. 0 is \#cores.
     pub threadcount: usize,
+    /// the max rate of messages accepted from a single address (messages/s)
+    /// None is unlimited
+    pub address_rate_limit: Option<RateLimit>,
+    /// the max rate of messages accepted for a single community (messages/s)
+    /// None is unlimited
+    pub community_rate_limit: Option<RateLimit>,
+    /// the number of messages an address can exceed its rate limit with in [blacklist_duration](Config::blacklist_duration)
+    /// before it is blacklisted (messages). 0 never blacklists. Bootstrap servers and verified peers are never
+    /// blacklisted, as their address may be spoofed.
+    pub blacklist_threshold: usize,
+    /// the time an address stays blacklisted, all of its messages are dropped meanwhile
+    pub blacklist_duration: Duration,
 
     /// Default list of host used for peer discovery and bootstrapping
     pub default_hosts: Vec<Address>,
-    /// UDP Socket address used for sending packets
+    /// The time between two steps of the [Walker](crate::peerdiscovery::Walker)
+    pub walk_interval: Duration,
+    /// The number of verified peers the built-in DiscoveryCommunity walks towards
+    pub target_peers: usize,
+    /// The number of verified peers the [RandomChurn](crate::peerdiscovery::churn::RandomChurn) checks every step
+    pub churn_sample_size: usize,
+    /// The minimum time between two pings of the [RandomChurn](crate::peerdiscovery::churn::RandomChurn) to the same peer
+    pub churn_ping_interval: Duration,
+    /// Verified peers which have been silent for this long are pinged by the [RandomChurn](crate::peerdiscovery::churn::RandomChurn)
+    pub churn_inactive_time: Duration,
+    /// Pinged peers which have been silent for this long are removed by the [RandomChurn](crate::peerdiscovery::churn::RandomChurn)
+    pub churn_drop_time: Duration,
+    /// UDP Socket address used for sending packets. Sockets bound to an IPv6 address are dual-stack.
     pub sending_address: Address,
-    /// UDP Socket address used for receiving packets
+    /// UDP Socket address used for receiving packets. Sockets bound to an IPv6 address are dual-stack.
+    /// Only used when [single_socket](Config::single_socket) is false.
     pub receiving_address: Address,
+    /// Send and receive on the one socket bound to the [sending_address](Config::sending_address), like py-ipv8 does.
+    /// Peers reply to the port our packets come from and NAT holes are punched for that port, so with separate
+    /// sockets replies from peers outside the LAN never reach the receiver.
+    pub single_socket: bool,
+
+    /// The file the key of this node is stored in, see [KeyPair::from_file](crate::crypto::signature::KeyPair::from_file).
+    /// None gives the node a new random identity every time.
+    pub key_file: Option<PathBuf>,
+    /// The max level of the messages which are logged, applied by the [Launcher](crate::launcher::Launcher).
+    /// None leaves it to the logger.
+    pub log_level: Option<LevelFilter>,
+    /// The communities the [Launcher](crate::launcher::Launcher) starts
+    pub overlays: Vec<OverlayConfig>,
+}
+
+/// A community the [Launcher](crate::launcher::Launcher) starts, with the walkers which discover peers for it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayConfig {
+    /// The name the community type was registered with in the [Launcher](crate::launcher::Launcher)
+    pub class: String,
+    /// The walkers of this community
+    #[serde(default)]
+    pub walkers: Vec<WalkerConfig>,
+}
+
+/// A [DiscoveryStrategy](crate::peerdiscovery::discovery::DiscoveryStrategy) walking a community
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WalkerConfig {
+    /// The name of the strategy: RandomWalk, EdgeWalk or RandomChurn
+    pub strategy: String,
+    /// The number of verified peers the strategy walks towards. -1 never stops walking, like in py-ipv8.
+    pub peers: i64,
+}
+
+impl WalkerConfig {
+    /// Returns the target peer count to add the strategy to the [Walker](crate::peerdiscovery::Walker) with
+    pub fn target_peers(&self) -> usize {
+        usize::try_from(self.peers).unwrap_or(usize::MAX)
+    }
+}
+
+/// The layout of a configuration file, everything which is left out keeps its default
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    /// See [Config::log_level]
+    logging_level: Option<String>,
+    /// See [Config::key_file]
+    key_file: Option<PathBuf>,
+    /// See [Config::sending_address] and [Config::receiving_address]
+    interface: Option<InterfaceConfig>,
+    /// See [Config::walk_interval] (s)
+    walker_interval: Option<f64>,
+    /// See [Config::target_peers]
+    target_peers: Option<usize>,
+    /// See [Config::default_hosts]
+    bootstrap: Option<Vec<SocketAddr>>,
+    /// See [Config::overlays]
+    #[serde(default)]
+    overlays: Vec<OverlayConfig>,
+}
+
+/// The address to send and receive on. The ip applies to both sockets when [single_socket](Config::single_socket) is
+/// turned off, while the port only applies to the sending socket, as two sockets can't be bound to the same port.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InterfaceConfig {
+    /// The ip to bind to, defaults to the ip of the default [sending_address](Config::sending_address)
+    ip: Option<IpAddr>,
+    /// The port to bind to, defaults to the port of the default [sending_address](Config::sending_address)
+    port: Option<u16>,
+}
+
+impl Config {
+    /// Reads the configuration from a TOML file, see the [module documentation](crate::configuration) for its format
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Ipv8Error> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Reads the configuration from a TOML string, see the [module documentation](crate::configuration) for its format
+    pub fn from_toml(toml: &str) -> Result<Self, Ipv8Error> {
+        let file: ConfigFile =
+            toml::from_str(toml).map_err(|e| Ipv8Error::InvalidConfig(Box::new(e)))?;
+        let mut config = Config::default();
+
+        if let Some(level) = file.logging_level {
+            config.log_level = Some(
+                LevelFilter::from_str(&level).map_err(|e| Ipv8Error::InvalidConfig(Box::new(e)))?,
+            );
+        }
+        if let Some(interface) = file.interface {
+            let default = config.sending_address.0;
+            config.sending_address = Address(SocketAddr::new(
+                interface.ip.unwrap_or_else(|| default.ip()),
+                interface.port.unwrap_or_else(|| default.port()),
+            ));
+            if let Some(ip) = interface.ip {
+                config.receiving_address.0.set_ip(ip);
+            }
+        }
+        if let Some(interval) = file.walker_interval {
+            config.walk_interval = Duration::try_from_secs_f64(interval)
+                .map_err(|e| Ipv8Error::InvalidConfig(Box::new(e)))?;
+        }
+        if let Some(target_peers) = file.target_peers {
+            config.target_peers = target_peers;
+        }
+        if let Some(bootstrap) = file.bootstrap {
+            config.default_hosts = bootstrap.into_iter().map(Address).collect();
+        }
+        config.key_file = file.key_file;
+        config.overlays = file.overlays;
+
+        Ok(config)
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             queuesize: 100,
+            drop_policy: DropPolicy::DropNewest,
             buffersize: 2048,
+            batchsize: 32,
             pollinterval: None,
 
             // zero means equal to number of cores
             threadcount: 0,
 
-            sending_address: Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8000)),
-            receiving_address: Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0)),
+            address_rate_limit: Some(RateLimit {
+                rate: 100.0,
+                burst: 200.0,
+            }),
+            community_rate_limit: Some(RateLimit {
+                rate: 5000.0,
+                burst: 10000.0,
+            }),
+            blacklist_threshold: 1000,
+            blacklist_duration: Duration::from_secs(60),
+
+            // Dual-stack, falls back to IPv4 only when IPv6 is not available
+            sending_address: Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 8000)),
+            receiving_address: Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)),
+            single_socket: true,
+
+            walk_interval: Duration::from_millis(500),
+            target_peers: 20,
+            churn_sample_size: 8,
+            churn_ping_interval: Duration::from_secs(10),
+            churn_inactive_time: Duration::from_millis(27_500),
+            churn_drop_time: Duration::from_millis(57_500),
+
+            key_file: None,
+            log_level: None,
+            overlays: vec![],
 
             default_hosts: vec![
                 // Dispersy
@@ -103,3 +310,97 @@ impl Default for Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            logging_level = "debug"
+            key_file = "ec.pk8"
+            walker_interval = 0.25
+            target_peers = 5
+            bootstrap = ["1.2.3.4:6421", "[::1]:6422"]
+
+            [interface]
+            ip = "127.0.0.1"
+            port = 8090
+
+            [[overlays]]
+            class = "TestCommunity"
+            walkers = [{ strategy = "RandomWalk", peers = 20 }, { strategy = "RandomChurn", peers = -1 }]
+
+            [[overlays]]
+            class = "OtherCommunity"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.log_level, Some(LevelFilter::Debug));
+        assert_eq!(config.key_file, Some(PathBuf::from("ec.pk8")));
+        assert_eq!(config.walk_interval, Duration::from_millis(250));
+        assert_eq!(config.target_peers, 5);
+        assert_eq!(
+            config.default_hosts,
+            vec![
+                Address("1.2.3.4:6421".parse().unwrap()),
+                Address("[::1]:6422".parse().unwrap())
+            ]
+        );
+        assert_eq!(
+            config.sending_address,
+            Address("127.0.0.1:8090".parse().unwrap())
+        );
+        // the receiving socket, when there is one, binds to the same ip on a port of its own
+        assert_eq!(
+            config.receiving_address,
+            Address("127.0.0.1:0".parse().unwrap())
+        );
+
+        assert_eq!(config.overlays.len(), 2);
+        assert_eq!(config.overlays[0].class, "TestCommunity");
+        assert_eq!(config.overlays[0].walkers[0].target_peers(), 20);
+        assert_eq!(config.overlays[0].walkers[1].target_peers(), usize::MAX);
+        assert!(config.overlays[1].walkers.is_empty());
+    }
+
+    #[test]
+    fn test_from_toml_defaults() {
+        let config = Config::from_toml("[interface]\nport = 8090").unwrap();
+        let default = Config::default();
+
+        assert_eq!(
+            config.sending_address.0.ip(),
+            default.sending_address.0.ip()
+        );
+        assert_eq!(config.sending_address.0.port(), 8090);
+        assert_eq!(config.receiving_address, default.receiving_address);
+        assert_eq!(config.default_hosts, default.default_hosts);
+        assert_eq!(config.walk_interval, default.walk_interval);
+        assert_eq!(config.log_level, None);
+        assert!(config.overlays.is_empty());
+    }
+
+    #[test]
+    fn test_from_toml_invalid() {
+        assert!(Config::from_toml("unknown_option = 1").is_err());
+        assert!(Config::from_toml("logging_level = \"loud\"").is_err());
+        assert!(Config::from_toml("bootstrap = [\"not an address\"]").is_err());
+        assert!(Config::from_toml("walker_interval = -1.0").is_err());
+        assert!(Config::from_toml("[[overlays]]\nwalkers = []").is_err());
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path =
+            std::env::temp_dir().join(format!("rust_ipv8_config_{}.toml", std::process::id()));
+        fs::write(&path, "target_peers = 3").unwrap();
+        assert_eq!(Config::from_file(&path).unwrap().target_peers, 3);
+        fs::remove_file(&path).unwrap();
+
+        assert!(Config::from_file(&path).is_err());
+    }
+}
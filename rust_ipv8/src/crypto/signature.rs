@@ -2,22 +2,15 @@
 
 use ring;
 use untrusted::Input;
+use crate::error::Ipv8Error;
 use crate::serialization::Packet;
-use std::error::Error;
 use serde::{Serialize, Serializer, ser::SerializeTuple};
 use zerocopy::{AsBytes, FromBytes};
 use crate::payloads::Ipv8Payload;
 use ring::signature::KeyPair as RingKeyPair;
-
-create_error!(
-    KeyRejectedError,
-    "During the creation of a keypair, an error occurred. The bytes given are not a valid key."
-);
-create_error!(
-    KeyGenerationError,
-    "During the generation of a keypair, an error occurred. The key could not be generated."
-);
-create_error!(SigningError, "During the signing, a problem occurred.");
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 
 /// Type representing a public key. Just a 32 byte array under the hood.
 pub type Ed25519PublicKey = [u8; 32];
@@ -52,20 +45,39 @@ impl Serialize for Signature {
 
 impl KeyPair {
     /// Generates a new random keypair
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    pub fn new() -> Result<Self, Ipv8Error> {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8_bytes = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)?;
+
+        KeyPair::from_bytes(pkcs8_bytes.as_ref())
+    }
+
+    /// Loads the keypair stored in the pkcs8 format in the given file. When the file does not exist yet, a new random
+    /// keypair is generated and stored in it, so the node keeps its identity across restarts.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Ipv8Error> {
+        let path = path.as_ref();
+        if path.exists() {
+            return KeyPair::from_bytes(&fs::read(path)?);
+        }
+
         let rng = ring::rand::SystemRandom::new();
-        let pkcs8_bytes = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
-            .or_else(|_| Err(Box::new(KeyGenerationError)))?;
+        let pkcs8_bytes = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // The key is the identity of the node, nobody else should be able to read it
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(pkcs8_bytes.as_ref())?;
 
         KeyPair::from_bytes(pkcs8_bytes.as_ref())
     }
 
     /// Constructs a keypair from a bytearray containing data in
     /// the pkcs8 format
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Ipv8Error> {
         let trusted_bytes = untrusted::Input::from(bytes);
-        let ring_key = ring::signature::Ed25519KeyPair::from_pkcs8(trusted_bytes)
-            .or_else(|_| Err(Box::new(KeyRejectedError)))?;
+        let ring_key = ring::signature::Ed25519KeyPair::from_pkcs8(trusted_bytes)?;
         Ok(KeyPair(ring_key))
     }
 
@@ -73,44 +85,50 @@ impl KeyPair {
     pub fn from_seed_checked(
         seed: &[u8; 32],
         publickey: &Ed25519PublicKey,
-    ) -> Result<Self, Box<dyn Error>> {
+    ) -> Result<Self, Ipv8Error> {
         let trusted_seed = untrusted::Input::from(seed);
         let public = untrusted::Input::from(&*publickey);
         let ring_key =
-            ring::signature::Ed25519KeyPair::from_seed_and_public_key(trusted_seed, public)
-                .or_else(|_| Err(Box::new(KeyRejectedError)))?;
+            ring::signature::Ed25519KeyPair::from_seed_and_public_key(trusted_seed, public)?;
         Ok(KeyPair(ring_key))
     }
 
     #[doc(hidden)]
-    pub fn from_seed_unchecked(seed: &[u8; 32]) -> Result<Self, Box<dyn Error>> {
+    pub fn from_seed_unchecked(seed: &[u8; 32]) -> Result<Self, Ipv8Error> {
         warn!("DANGER ZONE! Creating seed without checking it against a public key");
         let trusted_seed = untrusted::Input::from(seed);
-        let ring_key = ring::signature::Ed25519KeyPair::from_seed_unchecked(trusted_seed)
-            .or_else(|_| Err(Box::new(KeyRejectedError)))?;
+        let ring_key = ring::signature::Ed25519KeyPair::from_seed_unchecked(trusted_seed)?;
         Ok(KeyPair(ring_key))
     }
 
     /// Returns the Public part of the KeyPair
-    pub fn public_key(&self) -> Result<Ed25519PublicKey, Box<dyn Error>> {
+    pub fn public_key(&self) -> Result<Ed25519PublicKey, Ipv8Error> {
         let pk = &self.0;
         let pk2 = pk.public_key();
-        let key = *zerocopy::LayoutVerified::<_, [u8; 32]>::new(pk2.as_ref())
-            .ok_or_else(|| Box::new(KeyRejectedError))?;
+        let key = *zerocopy::LayoutVerified::<_, [u8; 32]>::new(pk2.as_ref()).ok_or(
+            Ipv8Error::Conversion {
+                expected: 32,
+                actual: pk2.as_ref().len(),
+            },
+        )?;
         Ok(key)
     }
 }
 
 /// Helper method which can be used for signing [Packets](crate::serialization::Packet)
-pub fn sign_packet(keypair: &KeyPair, message: &Packet) -> Result<Signature, Box<dyn Error>> {
+pub fn sign_packet(keypair: &KeyPair, message: &Packet) -> Result<Signature, Ipv8Error> {
     sign(&keypair, &message.raw())
 }
 
 /// A function which takes in a KeyPair and message and returns only the signature
-pub fn sign(keypair: &KeyPair, message: &[u8]) -> Result<Signature, Box<dyn Error>> {
+pub fn sign(keypair: &KeyPair, message: &[u8]) -> Result<Signature, Ipv8Error> {
     let signature = keypair.0.sign(message);
-    let sig = *zerocopy::LayoutVerified::<_, [u8; 64]>::new(signature.as_ref())
-        .ok_or_else(|| Box::new(SigningError))?;
+    let sig = *zerocopy::LayoutVerified::<_, [u8; 64]>::new(signature.as_ref()).ok_or(
+        Ipv8Error::Conversion {
+            expected: Signature::ED25519_SIGNATURE_BYTES,
+            actual: signature.as_ref().len(),
+        },
+    )?;
     Ok(Signature(sig))
 }
 
@@ -176,6 +194,26 @@ mod tests {
         assert!(verify_raw(&pk.public_key().unwrap(), &*p.0, &sig.0))
     }
 
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("rust_ipv8_key_{}.pk8", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let generated = KeyPair::from_file(&path).unwrap();
+        let loaded = KeyPair::from_file(&path).unwrap();
+        assert_eq!(
+            generated.public_key().unwrap(),
+            loaded.public_key().unwrap()
+        );
+
+        fs::write(&path, [1, 2, 3]).unwrap();
+        assert!(matches!(
+            KeyPair::from_file(&path),
+            Err(Ipv8Error::KeyRejected { .. })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_from_seed_unchecked() {
         let seed = [
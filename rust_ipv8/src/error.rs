@@ -1,34 +1,445 @@
-#![macro_use]
-#![doc(hidden)]
-macro_rules! create_error {
-    ( $name: ident, $message: expr) => {
-        #[derive(Debug)]
-        #[doc(hidden)]
-        pub struct $name;
-
-        impl std::fmt::Display for $name {
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(f, $message)
+//! The errors of rust_ipv8.
+//!
+//! Everything which fails in rust_ipv8 itself fails with an [Ipv8Error], which tells what went wrong and where
+//! (the offset in the packet, the mid of the community, the address of the peer...) so callers can match on it.
+//!
+//! The traits users implement, like [Community](crate::community::Community) and
+//! [Endpoint](crate::networking::endpoint::Endpoint), still return a `Box<dyn Error>` so implementations can fail
+//! with errors of their own. An [Ipv8Error] in there is turned back into one by [Ipv8Error::from].
+//!
+//! An [Ipv8Error] is [Send] and [Sync], so it can be passed between threads and tasks.
+
+use crate::community::SignaturePolicy;
+use crate::crypto::signature::Ed25519PublicKey;
+use crate::networking::address::Address;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything which can go wrong in rust_ipv8, see the [module documentation](crate::error)
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Ipv8Error {
+    /// A payload could not be serialized
+    Serialize(bincode::Error),
+    /// A packet could not be deserialized from the given offset on, for example because it is too short
+    Deserialize {
+        /// The offset in the packet the payload which failed starts at
+        offset: usize,
+        /// Why it failed
+        source: bincode::Error,
+    },
+    /// Bytes could not be converted to a fixed size type as their size is wrong
+    Conversion {
+        /// The size of the type
+        expected: usize,
+        /// The number of bytes given
+        actual: usize,
+    },
+
+    /// The bytes given are not a valid key
+    KeyRejected {
+        /// Why the key was rejected
+        reason: &'static str,
+    },
+    /// A key could not be generated, or a message could not be signed
+    Crypto,
+    /// The signature of a message could not be verified
+    InvalidSignature {
+        /// The key the message claims to be signed with
+        key: Ed25519PublicKey,
+    },
+
+    /// A received packet has no mid or no message type in its header
+    IncompleteHeader {
+        /// The address the packet came from
+        address: Address,
+    },
+    /// A message has no message type in its header
+    MissingMessageType,
+    /// A mid is not 20 bytes long
+    InvalidMid {
+        /// The mid
+        mid: Vec<u8>,
+    },
+    /// No community with the mid is loaded
+    UnknownCommunity {
+        /// The mid
+        mid: Vec<u8>,
+    },
+    /// A community with the mid is loaded already
+    DuplicateCommunity {
+        /// The mid
+        mid: Vec<u8>,
+    },
+    /// A received packet did not meet the [SignaturePolicy] of its message type
+    RejectedPacket {
+        /// The mid of the community the packet is for
+        mid: Vec<u8>,
+        /// The message type of the packet
+        message_type: u8,
+        /// The address the packet came from
+        address: Address,
+        /// The policy the packet did not meet
+        policy: SignaturePolicy,
+    },
+    /// The registry has no identity to load communities with
+    NoIdentity,
+    /// A community has no clock to claim the global time of a message from
+    NoGlobalTime {
+        /// The mid of the community
+        mid: Vec<u8>,
+    },
+    /// The global time of a message is too far ahead of our neighbours
+    GlobalTimeAhead {
+        /// The address the message came from
+        address: Address,
+        /// The global time of the message
+        global_time: u64,
+        /// The highest global time accepted
+        limit: u64,
+    },
+    /// A received packet was dropped as its sender exceeded its rate limit
+    AddressRateLimited {
+        /// The sender
+        address: Address,
+    },
+    /// A received packet was dropped as its community exceeded its rate limit
+    CommunityRateLimited {
+        /// The mid of the community
+        mid: Vec<u8>,
+    },
+    /// A received packet was dropped as its sender is blacklisted
+    Blacklisted {
+        /// The sender
+        address: Address,
+    },
+    /// The mailbox of a community actor is full or the actor is unloaded, the message was dropped
+    MailboxFull {
+        /// The mid of the community
+        mid: Vec<u8>,
+    },
+
+    /// A socket could not be bound to the address
+    Bind {
+        /// The address
+        address: Address,
+        /// Why it failed
+        source: io::Error,
+    },
+    /// A packet could not be sent to the address
+    Send {
+        /// The address
+        address: Address,
+        /// Why it failed
+        source: io::Error,
+    },
+    /// The endpoint was already opened, it can not be opened again or get new listeners
+    EndpointOpened,
+    /// The endpoint is not open
+    EndpointClosed,
+    /// The simulated network already has an endpoint at the address
+    AddressInUse {
+        /// The address
+        address: Address,
+    },
+    /// A [TokioEndpoint](crate::networking::tokioendpoint), an async handler or an async task was created outside
+    /// of a tokio runtime
+    NoRuntime,
+
+    /// A task with the name is still pending
+    TaskExists {
+        /// The name of the task
+        name: String,
+    },
+    /// A repeating task was registered with an interval of zero, which would never let the other tasks run
+    ZeroInterval {
+        /// The name of the task
+        name: String,
+    },
+    /// A task was cancelled before it ran, so the future waiting for it never completes
+    TaskCancelled {
+        /// The name of the task
+        name: String,
+    },
+    /// A cache with the prefix and number already exists
+    CacheExists {
+        /// The prefix of the cache
+        prefix: &'static str,
+        /// The number of the cache
+        number: u16,
+    },
+    /// All numbers for the prefix are in use
+    CacheFull {
+        /// The prefix
+        prefix: &'static str,
+    },
+
+    /// The configuration contains an overlay class which is not registered in the
+    /// [Launcher](crate::launcher::Launcher)
+    UnknownClass {
+        /// The class name
+        class: String,
+    },
+    /// The configuration contains an unknown walker strategy
+    UnknownStrategy {
+        /// The strategy name
+        strategy: String,
+    },
+    /// The configuration contains walkers for a community which is not an overlay
+    NotAnOverlay {
+        /// The class name of the community
+        class: String,
+    },
+    /// The configuration could not be read, for example because a value has the wrong type
+    InvalidConfig(Box<dyn Error + Send + Sync>),
+
+    /// A lock was poisoned as a thread panicked while holding it
+    LockPoisoned(&'static str),
+    /// A thread panicked, so it could not be joined
+    ThreadPanicked(&'static str),
+    /// Reading or writing a file or socket failed
+    Io(io::Error),
+    /// An error of something implemented outside of rust_ipv8, like a community
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for Ipv8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ipv8Error::Serialize(e) => write!(f, "Could not serialize a payload: {}", e),
+            Ipv8Error::Deserialize { offset, source } => {
+                write!(
+                    f,
+                    "Could not deserialize the packet at offset {}: {}",
+                    offset, source
+                )
+            }
+            Ipv8Error::Conversion { expected, actual } => write!(
+                f,
+                "Converting {} bytes to a fixed size type of {} bytes failed",
+                actual, expected
+            ),
+            Ipv8Error::KeyRejected { reason } => {
+                write!(f, "The bytes given are not a valid key: {}", reason)
+            }
+            Ipv8Error::Crypto => write!(f, "A cryptographic operation failed"),
+            Ipv8Error::InvalidSignature { key } => {
+                write!(f, "The message is not signed with key {:02x?}", key)
+            }
+            Ipv8Error::IncompleteHeader { address } => {
+                write!(
+                    f,
+                    "The packet from {:?} has no mid or message type",
+                    address
+                )
+            }
+            Ipv8Error::MissingMessageType => {
+                write!(f, "The header of the message has no message type")
+            }
+            Ipv8Error::InvalidMid { mid } => write!(f, "The mid {:02x?} is not 20 bytes long", mid),
+            Ipv8Error::UnknownCommunity { mid } => {
+                write!(f, "No community with mid {:02x?} is loaded", mid)
+            }
+            Ipv8Error::DuplicateCommunity { mid } => {
+                write!(f, "A community with mid {:02x?} is loaded already", mid)
+            }
+            Ipv8Error::RejectedPacket {
+                mid,
+                message_type,
+                address,
+                policy,
+            } => write!(
+                f,
+                "Message {} for community {:02x?} from {:?} is not {:?}",
+                message_type, mid, address, policy
+            ),
+            Ipv8Error::NoIdentity => {
+                write!(f, "The registry has no identity to load communities with")
             }
+            Ipv8Error::NoGlobalTime { mid } => {
+                write!(f, "Community {:02x?} has no global time", mid)
+            }
+            Ipv8Error::GlobalTimeAhead {
+                address,
+                global_time,
+                limit,
+            } => write!(
+                f,
+                "The global time {} of the message from {:?} is beyond the limit of {}",
+                global_time, address, limit
+            ),
+            Ipv8Error::AddressRateLimited { address } => {
+                write!(f, "{:?} exceeded its rate limit", address)
+            }
+            Ipv8Error::CommunityRateLimited { mid } => {
+                write!(f, "Community {:02x?} exceeded its rate limit", mid)
+            }
+            Ipv8Error::Blacklisted { address } => write!(f, "{:?} is blacklisted", address),
+            Ipv8Error::MailboxFull { mid } => {
+                write!(f, "The mailbox of community {:02x?} is full", mid)
+            }
+            Ipv8Error::Bind { address, source } => {
+                write!(f, "Could not bind to {:?}: {}", address, source)
+            }
+            Ipv8Error::Send { address, source } => {
+                write!(f, "Could not send to {:?}: {}", address, source)
+            }
+            Ipv8Error::EndpointOpened => write!(
+                f,
+                "The endpoint was already opened, it can not be opened again or get new listeners"
+            ),
+            Ipv8Error::EndpointClosed => write!(f, "The endpoint is not open"),
+            Ipv8Error::AddressInUse { address } => {
+                write!(
+                    f,
+                    "The simulated network already has an endpoint at {:?}",
+                    address
+                )
+            }
+            Ipv8Error::NoRuntime => write!(f, "This can only be used from within a tokio runtime"),
+            Ipv8Error::TaskExists { name } => {
+                write!(f, "A task named {} is already registered", name)
+            }
+            Ipv8Error::ZeroInterval { name } => {
+                write!(f, "The task {} can not repeat every 0 seconds", name)
+            }
+            Ipv8Error::TaskCancelled { name } => {
+                write!(f, "The task {} was cancelled before it ran", name)
+            }
+            Ipv8Error::CacheExists { prefix, number } => {
+                write!(f, "A cache {}:{} already exists", prefix, number)
+            }
+            Ipv8Error::CacheFull { prefix } => {
+                write!(f, "All numbers for prefix {} are in use", prefix)
+            }
+            Ipv8Error::UnknownClass { class } => {
+                write!(
+                    f,
+                    "The overlay class {} is not registered in the launcher",
+                    class
+                )
+            }
+            Ipv8Error::UnknownStrategy { strategy } => {
+                write!(f, "Unknown walker strategy {}", strategy)
+            }
+            Ipv8Error::NotAnOverlay { class } => {
+                write!(
+                    f,
+                    "Community {} is not an overlay, it can not have walkers",
+                    class
+                )
+            }
+            Ipv8Error::InvalidConfig(e) => write!(f, "The configuration is invalid: {}", e),
+            Ipv8Error::LockPoisoned(lock) => write!(f, "The lock on {} was poisoned", lock),
+            Ipv8Error::ThreadPanicked(thread) => write!(f, "The thread of {} panicked", thread),
+            Ipv8Error::Io(e) => write!(f, "{}", e),
+            Ipv8Error::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for Ipv8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Ipv8Error::Serialize(e) | Ipv8Error::Deserialize { source: e, .. } => Some(e),
+            Ipv8Error::Bind { source, .. } | Ipv8Error::Send { source, .. } => Some(source),
+            Ipv8Error::Io(e) => Some(e),
+            Ipv8Error::InvalidConfig(e) | Ipv8Error::Other(e) => Some(&**e),
+            _ => None,
         }
+    }
+}
 
-        impl std::error::Error for $name {
-            fn description(&self) -> &str {
-                $message
-            }
+impl From<bincode::Error> for Ipv8Error {
+    fn from(e: bincode::Error) -> Self {
+        Ipv8Error::Serialize(e)
+    }
+}
+
+impl From<io::Error> for Ipv8Error {
+    fn from(e: io::Error) -> Self {
+        Ipv8Error::Io(e)
+    }
+}
+
+impl From<ring::error::KeyRejected> for Ipv8Error {
+    fn from(e: ring::error::KeyRejected) -> Self {
+        Ipv8Error::KeyRejected {
+            reason: e.description_(),
         }
-    };
+    }
+}
+
+impl From<ring::error::Unspecified> for Ipv8Error {
+    fn from(_: ring::error::Unspecified) -> Self {
+        Ipv8Error::Crypto
+    }
+}
+
+/// Unwraps the errors of rust_ipv8 which were boxed by a trait method, so they can still be matched on. Other errors
+/// may not be [Send], so only their message is kept.
+impl From<Box<dyn Error>> for Ipv8Error {
+    fn from(e: Box<dyn Error>) -> Self {
+        match e.downcast::<Ipv8Error>() {
+            Ok(e) => *e,
+            Err(e) => Ipv8Error::Other(e.to_string().into()),
+        }
+    }
+}
+
+/// Unwraps the errors of rust_ipv8 which were boxed, so they can still be matched on
+impl From<Box<dyn Error + Send + Sync>> for Ipv8Error {
+    fn from(e: Box<dyn Error + Send + Sync>) -> Self {
+        match e.downcast::<Ipv8Error>() {
+            Ok(e) => *e,
+            Err(e) => Ipv8Error::Other(e),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::error::Error;
+    use super::*;
+
+    #[test]
+    fn test_from_boxed() {
+        let boxed: Box<dyn Error> = Box::new(Ipv8Error::UnknownCommunity { mid: vec![1; 20] });
+        match Ipv8Error::from(boxed) {
+            Ipv8Error::UnknownCommunity { mid } => assert_eq!(mid, vec![1; 20]),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let boxed: Box<dyn Error> = "yeet".into();
+        let error = Ipv8Error::from(boxed);
+        assert_eq!(format!("{}", error), "yeet");
+        assert!(error.source().is_some());
+
+        let boxed: Box<dyn Error + Send + Sync> = Box::new(Ipv8Error::NoIdentity);
+        assert!(matches!(Ipv8Error::from(boxed), Ipv8Error::NoIdentity));
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Ipv8Error>();
+    }
 
     #[test]
-    fn test_errors() {
-        create_error!(TestError, "yeet");
-        assert_eq!(TestError.description(), "yeet");
-        assert_eq!(format!("{:?}", TestError), "TestError");
-        assert_eq!(format!("{}", TestError), "yeet");
+    fn test_display() {
+        assert_eq!(
+            format!("{}", Ipv8Error::LockPoisoned("the statistics")),
+            "The lock on the statistics was poisoned"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Ipv8Error::Conversion {
+                    expected: 4,
+                    actual: 3
+                }
+            ),
+            "Converting 3 bytes to a fixed size type of 4 bytes failed"
+        );
     }
 }
@@ -0,0 +1,321 @@
+//! Starts IPv8 with the communities listed in its [Config], like the launcher of py-ipv8 does with its
+//! [configuration](https://github.com/Tribler/py-ipv8/blob/master/ipv8/configuration.py).
+//!
+//! Community types are registered in the [Launcher] under a class name. The [overlays](Config::overlays) of a
+//! config, usually read with [Config::from_file], refer to them by that name. The built-in DiscoveryCommunity is
+//! loaded by [IPv8] itself, so it is not registered:
+//!
+//! ```ignore
+//! use rust_ipv8::configuration::Config;
+//! use rust_ipv8::launcher::Launcher;
+//!
+//! let mut launcher = Launcher::new();
+//! launcher.register_overlay::<MyCommunity>("MyCommunity");
+//!
+//! let config = Config::from_file("ipv8.toml").unwrap();
+//! let ipv8 = launcher.launch(config).unwrap();
+//! let handle = ipv8.start().unwrap();
+//! ```
+
+use crate::community::Community;
+use crate::configuration::{Config, OverlayConfig};
+use crate::error::Ipv8Error;
+use crate::peerdiscovery::churn::RandomChurn;
+use crate::peerdiscovery::discovery::{DiscoveryStrategy, EdgeWalk, RandomWalk};
+use crate::peerdiscovery::Overlay;
+use crate::IPv8;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// The names of the strategies walkers can be configured with
+const STRATEGIES: [&str; 3] = ["RandomWalk", "EdgeWalk", "RandomChurn"];
+
+/// Loads a community into IPv8, returns it as an overlay when it can be walked
+type Loader = Box<dyn Fn(&IPv8) -> Result<Option<Arc<dyn Overlay>>, Box<dyn Error>> + Send + Sync>;
+
+/// A community type registered in the [Launcher]
+struct Registration {
+    /// Loads a community of the type
+    load: Loader,
+    /// True if the communities of the type can be walked
+    overlay: bool,
+}
+
+/// Instantiates the communities of a config by their class name, see the [module documentation](crate::launcher)
+#[derive(Default)]
+pub struct Launcher {
+    /// The community types which can be loaded, by class name
+    loaders: HashMap<String, Registration>,
+}
+
+impl Launcher {
+    /// Creates a launcher without any community types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a community type under a class name. Communities registered like this can't have walkers, use
+    /// [register_overlay](Launcher::register_overlay) for that.
+    pub fn register_community<C: Community + 'static>(&mut self, class: &str) {
+        self.loaders.insert(
+            class.to_owned(),
+            Registration {
+                load: Box::new(|ipv8| {
+                    ipv8.load_community::<C>()?;
+                    Ok(None)
+                }),
+                overlay: false,
+            },
+        );
+    }
+
+    /// Registers a community type which can be walked under a class name
+    pub fn register_overlay<C: Community + Overlay + 'static>(&mut self, class: &str) {
+        self.loaders.insert(
+            class.to_owned(),
+            Registration {
+                load: Box::new(|ipv8| {
+                    let overlay: Arc<dyn Overlay> = ipv8.load_community::<C>()?;
+                    Ok(Some(overlay))
+                }),
+                overlay: true,
+            },
+        );
+    }
+
+    /// Creates a new IPv8 instance sending and receiving over UDP, see [IPv8::new], and
+    /// [loads the overlays](Launcher::load_overlays) of the config into it. The [log level](Config::log_level) of
+    /// the config is applied first.
+    pub fn launch(&self, config: Config) -> Result<IPv8, Ipv8Error> {
+        if let Some(level) = config.log_level {
+            log::set_max_level(level);
+        }
+        self.check(&config.overlays)?;
+
+        let mut ipv8 = IPv8::new(config)?;
+        self.load_overlays(&mut ipv8)?;
+        Ok(ipv8)
+    }
+
+    /// Loads every overlay in the config of the IPv8 instance and adds its walkers to the walker of the instance.
+    /// Nothing is loaded when the config contains an unknown class or strategy, or walkers for a community which is
+    /// not an overlay.
+    pub fn load_overlays(&self, ipv8: &mut IPv8) -> Result<(), Ipv8Error> {
+        let overlays = ipv8.config.overlays.clone();
+        self.check(&overlays)?;
+
+        for config in overlays {
+            // Only overlays are returned, and only overlays can have walkers
+            let overlay = match (self.loaders[&config.class].load)(ipv8)? {
+                Some(overlay) => overlay,
+                None => continue,
+            };
+            for walker in &config.walkers {
+                let strategy =
+                    self.strategy(&walker.strategy, Arc::clone(&overlay), &ipv8.config)?;
+                ipv8.walker.add_strategy(strategy, walker.target_peers());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every class and strategy in the overlays is known, and that only overlays have walkers
+    fn check(&self, overlays: &[OverlayConfig]) -> Result<(), Ipv8Error> {
+        for config in overlays {
+            let registration =
+                self.loaders
+                    .get(&config.class)
+                    .ok_or_else(|| Ipv8Error::UnknownClass {
+                        class: config.class.clone(),
+                    })?;
+            if !registration.overlay && !config.walkers.is_empty() {
+                return Err(Ipv8Error::NotAnOverlay {
+                    class: config.class.clone(),
+                });
+            }
+            if let Some(walker) = config
+                .walkers
+                .iter()
+                .find(|walker| !STRATEGIES.contains(&walker.strategy.as_str()))
+            {
+                return Err(Ipv8Error::UnknownStrategy {
+                    strategy: walker.strategy.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the strategy with the given name for an overlay
+    fn strategy(
+        &self,
+        name: &str,
+        overlay: Arc<dyn Overlay>,
+        config: &Config,
+    ) -> Result<Box<dyn DiscoveryStrategy>, Ipv8Error> {
+        Ok(match name {
+            "RandomWalk" => Box::new(RandomWalk::new(overlay)),
+            "EdgeWalk" => Box::new(EdgeWalk::new(overlay)),
+            "RandomChurn" => Box::new(RandomChurn::from_config(overlay, config)),
+            _ => {
+                return Err(Ipv8Error::UnknownStrategy {
+                    strategy: name.to_owned(),
+                })
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::community::discovery::{DiscoveryCommunity, DISCOVERY_MID};
+    use crate::configuration::WalkerConfig;
+    use crate::networking::address::Address;
+    use crate::networking::endpoint::Endpoint;
+    use crate::peerdiscovery::network::Network;
+    use crate::serialization::header::Header;
+    use crate::serialization::PacketDeserializer;
+    use crate::taskmanager::TaskManager;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::RwLock;
+
+    static WALKS: AtomicUsize = AtomicUsize::new(0);
+
+    struct TestOverlay {
+        network: RwLock<Network>,
+    }
+
+    impl Community for TestOverlay {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
+            Ok(TestOverlay {
+                network: RwLock::new(Network::new()),
+            })
+        }
+
+        fn get_mid(&self) -> Vec<u8> {
+            vec![43; 20]
+        }
+
+        fn on_receive(
+            &self,
+            _header: Header,
+            _deserializer: PacketDeserializer,
+            _address: Address,
+        ) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+    }
+
+    impl Overlay for TestOverlay {
+        fn network(&self) -> &RwLock<Network> {
+            &self.network
+        }
+
+        fn walk_to(&self, _address: &Address) -> Result<(), Box<dyn Error>> {
+            WALKS.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn send_ping(&self, _address: &Address) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+
+        fn bootstrap_addresses(&self) -> Vec<Address> {
+            vec![Address(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+                1,
+            ))]
+        }
+    }
+
+    fn config(class: &str, strategy: &str) -> Config {
+        Config {
+            sending_address: Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+            default_hosts: vec![],
+            overlays: vec![OverlayConfig {
+                class: class.to_owned(),
+                walkers: vec![WalkerConfig {
+                    strategy: strategy.to_owned(),
+                    peers: 20,
+                }],
+            }],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_launch() {
+        let mut launcher = Launcher::new();
+        launcher.register_overlay::<TestOverlay>("TestOverlay");
+
+        let mut ipv8 = launcher
+            .launch(config("TestOverlay", "RandomWalk"))
+            .unwrap();
+        assert!(ipv8
+            .communities
+            .communities
+            .read()
+            .unwrap()
+            .contains_key(&vec![43; 20]));
+
+        // Without any peers the walker bootstraps the overlay
+        ipv8.walker.tick();
+        assert_eq!(WALKS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_launch_unknown() {
+        let mut launcher = Launcher::new();
+        assert!(launcher
+            .launch(config("TestOverlay", "RandomWalk"))
+            .is_err());
+
+        launcher.register_overlay::<TestOverlay>("TestOverlay");
+        assert!(launcher.launch(config("TestOverlay", "Teleport")).is_err());
+    }
+
+    #[test]
+    fn test_launch_builtin() {
+        let mut launcher = Launcher::new();
+        launcher.register_overlay::<DiscoveryCommunity>("DiscoveryCommunity");
+        match launcher.launch(config("DiscoveryCommunity", "RandomWalk")) {
+            Err(Ipv8Error::DuplicateCommunity { mid }) => assert_eq!(mid, DISCOVERY_MID.to_vec()),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("the DiscoveryCommunity was loaded twice"),
+        }
+    }
+
+    #[test]
+    fn test_walkers_need_overlay() {
+        let mut launcher = Launcher::new();
+        launcher.register_community::<TestOverlay>("TestCommunity");
+        let mut ipv8 = IPv8::new(config("TestCommunity", "RandomWalk")).unwrap();
+        match launcher.load_overlays(&mut ipv8) {
+            Err(Ipv8Error::NotAnOverlay { class }) => assert_eq!(class, "TestCommunity"),
+            result => panic!("unexpected result {:?}", result),
+        }
+        // nothing is loaded
+        assert!(!ipv8
+            .communities
+            .communities
+            .read()
+            .unwrap()
+            .contains_key(&vec![43; 20]));
+
+        let mut config = config("TestCommunity", "RandomWalk");
+        config.overlays[0].walkers.clear();
+        let ipv8 = launcher.launch(config).unwrap();
+        assert!(ipv8
+            .communities
+            .communities
+            .read()
+            .unwrap()
+            .contains_key(&vec![43; 20]));
+    }
+}
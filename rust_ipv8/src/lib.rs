@@ -10,15 +10,33 @@ pub mod util;
 pub mod community;
 pub mod configuration;
 pub mod crypto;
+pub mod launcher;
 pub mod networking;
 pub mod payloads;
+pub mod peerdiscovery;
+pub mod requestcache;
+pub mod statistics;
+pub mod taskmanager;
 
 use configuration::Config;
-use crate::networking::{NetworkSender, NetworkReceiver};
-use std::error::Error;
+use crate::networking::endpoint::{Endpoint, UdpEndpoint};
+use crate::taskmanager::TaskManager;
+#[cfg(feature = "tokio")]
+use crate::networking::tokioendpoint::TokioEndpoint;
 use crate::community::CommunityRegistry;
+use crate::error::Ipv8Error;
+use crate::community::discovery::DiscoveryCommunity;
+use crate::community::peer::MyPeer;
+use crate::community::ratelimit::RateLimiter;
+use crate::community::Community;
+use crate::crypto::signature::KeyPair;
+use crate::statistics::{Statistics, StatisticsEndpoint, StatisticsSnapshot};
+use crate::peerdiscovery::churn::RandomChurn;
+use crate::peerdiscovery::{Walker, WALKER_TASK};
+use crate::peerdiscovery::discovery::RandomWalk;
 use rayon::{ThreadPoolBuilder};
-use std::sync::Once;
+use std::mem;
+use std::sync::{Arc, Once};
 
 /// The IPv8 instance.
 /// This struct is how you can interact with the network.
@@ -33,13 +51,17 @@ use std::sync::Once;
 pub struct IPv8 {
     /// Defines the config used forIPv8
     pub config: Config,
-    /// The network receiver which forwards the packets to the correct communities
-    pub network_receiver: NetworkReceiver,
-    /// The sender used for sending packets over the network
-    pub network_sender: NetworkSender,
+    /// The endpoint packets are sent and received over
+    pub endpoint: Arc<dyn Endpoint>,
+    /// The tasks of all communities and of the walker
+    pub task_manager: Arc<TaskManager>,
 
-    /// The registry containing all the communities
-    pub communities: CommunityRegistry,
+    /// The registry containing all the communities, which receives every packet from the endpoint
+    pub communities: Arc<CommunityRegistry>,
+    /// The walker which periodically looks for new peers for every overlay
+    pub walker: Walker,
+    /// The traffic of every community
+    pub statistics: Arc<Statistics>,
 }
 
 // To keep track if the threadpool is already started
@@ -47,9 +69,9 @@ pub struct IPv8 {
 static THREADPOOL_START: Once = Once::new();
 
 impl IPv8 {
-    /// Creates a new instance of the ipv8 struct
-    pub fn new(config: configuration::Config) -> Result<Self, Box<dyn Error>> {
-        // Setup the global threadpool
+    /// Creates a new instance of the ipv8 struct, sending and receiving over UDP
+    pub fn new(config: configuration::Config) -> Result<Self, Ipv8Error> {
+        // Setup the global threadpool, which the UdpEndpoint passes received packets to and community actors run on
         {
             let mut started = None;
 
@@ -61,23 +83,183 @@ impl IPv8 {
                 )
             });
 
-            if let Some(s) = started {
-                s?
+            // The pool starts by itself when it is used before IPv8 is created, it just has the default size then
+            if let Some(Err(e)) = started {
+                warn!("Could not configure the global thread pool: {}", e);
             }
         }
 
-        let network_receiver = NetworkReceiver::new(&config.receiving_address)?;
-        let network_sender = NetworkSender::new(&config.sending_address)?;
+        let task_manager = Arc::new(TaskManager::new());
+        let endpoint = Arc::new(UdpEndpoint::new(&config, Arc::clone(&task_manager))?);
+        Self::with_endpoint(config, endpoint, task_manager)
+    }
+
+    /// Creates a new instance of the ipv8 struct, sending and receiving over UDP on the tokio runtime it is called from.
+    /// Unlike [new](IPv8::new) this does not start a thread pool of its own.
+    #[cfg(feature = "tokio")]
+    pub fn with_tokio(config: configuration::Config) -> Result<Self, Ipv8Error> {
+        let task_manager = Arc::new(TaskManager::new());
+        let endpoint = Arc::new(TokioEndpoint::new(&config, Arc::clone(&task_manager))?);
+        Self::with_endpoint(config, endpoint, task_manager)
+    }
+
+    /// Creates a new instance of the ipv8 struct which uses the given endpoint. The endpoint is responsible for
+    /// running the tasks of the [TaskManager], like [UdpEndpoint] does.
+    ///
+    /// The endpoint is wrapped in a [StatisticsEndpoint], so the packets communities send with it are counted.
+    /// The node uses the key in the [key_file](Config::key_file) of the config, or gets a new random identity when
+    /// there is none.
+    pub fn with_endpoint(
+        config: configuration::Config,
+        endpoint: Arc<dyn Endpoint>,
+        task_manager: Arc<TaskManager>,
+    ) -> Result<Self, Ipv8Error> {
+        let keypair = match &config.key_file {
+            Some(path) => KeyPair::from_file(path)?,
+            None => KeyPair::new()?,
+        };
+        Self::with_identity(config, endpoint, task_manager, Arc::new(keypair))
+    }
+
+    /// Creates a new instance of the ipv8 struct like [with_endpoint](IPv8::with_endpoint), where the node signs its
+    /// messages with the given key. The built-in communities and the ones [loaded](IPv8::load_community) later use it.
+    pub fn with_identity(
+        config: configuration::Config,
+        endpoint: Arc<dyn Endpoint>,
+        task_manager: Arc<TaskManager>,
+        keypair: Arc<KeyPair>,
+    ) -> Result<Self, Ipv8Error> {
+        let my_peer = MyPeer::new(keypair, endpoint.local_address()?)?;
+        let statistics = Arc::new(Statistics::new());
+        let endpoint: Arc<dyn Endpoint> =
+            Arc::new(StatisticsEndpoint::new(endpoint, Arc::clone(&statistics)));
+
+        // Register the built-in communities
+        let communities = Arc::new(CommunityRegistry::new(
+            Some(my_peer.clone()),
+            RateLimiter::new(&config),
+            Arc::clone(&statistics),
+        ));
+        endpoint.add_listener(Box::new(Arc::clone(&communities)))?;
+        let mut walker = Walker::new(config.walk_interval);
+
+        let discovery = Arc::new(DiscoveryCommunity::with_bootstrap_addresses(
+            my_peer,
+            &endpoint,
+            &task_manager,
+            config.default_hosts.clone(),
+        )?);
+        communities.add_community(Box::new(Arc::clone(&discovery)))?;
+        walker.add_strategy(
+            Box::new(RandomWalk::new(discovery.clone())),
+            config.target_peers,
+        );
+        walker.add_strategy(
+            Box::new(RandomChurn::from_config(discovery, &config)),
+            usize::MAX,
+        );
+
         Ok(IPv8 {
             config,
-            network_receiver,
-            network_sender,
-            communities: CommunityRegistry::default(),
+            endpoint,
+            task_manager,
+            communities,
+            walker,
+            statistics,
+        })
+    }
+
+    /// Constructs a community with the identity of this node and registers it, see
+    /// [CommunityRegistry::load_community]
+    pub fn load_community<C: Community + 'static>(&self) -> Result<Arc<C>, Ipv8Error> {
+        self.communities
+            .load_community(&self.endpoint, &self.task_manager)
+    }
+
+    /// Returns the traffic of every community so far
+    pub fn statistics(&self) -> Result<StatisticsSnapshot, Ipv8Error> {
+        snapshot(&self.statistics, &*self.endpoint)
+    }
+
+    /// Starts ipv8 to actually listen for packets and to start walking the network.
+    /// Returns a handle to stop it again.
+    pub fn start(mut self) -> Result<IPv8Handle, Ipv8Error> {
+        let walker = mem::replace(&mut self.walker, Walker::new(self.config.walk_interval));
+        walker.register(&self.task_manager)?;
+        self.endpoint.open()?;
+
+        Ok(IPv8Handle {
+            config: self.config.clone(),
+            endpoint: Arc::clone(&self.endpoint),
+            task_manager: Arc::clone(&self.task_manager),
+            communities: Arc::clone(&self.communities),
+            statistics: Arc::clone(&self.statistics),
         })
     }
+}
 
-    /// Starts ipv8 to actually listen for packets
-    pub fn start(self) {
-        self.network_receiver.start(&self.config);
+impl Drop for IPv8 {
+    /// Releases the socket of an instance which was never started. The endpoint keeps the registry alive as its
+    /// listener, and the communities and their tasks keep the endpoint alive, so the communities are unloaded and
+    /// the tasks cancelled to let them all go. A started instance is left running for its [IPv8Handle].
+    fn drop(&mut self) {
+        if self.endpoint.is_open() {
+            return;
+        }
+        if let Err(e) = self.communities.unload_all() {
+            warn!("Could not unload the communities of a dropped IPv8: {}", e);
+        }
+        if let Err(e) = self.task_manager.cancel_all_pending_tasks() {
+            warn!("Could not cancel the tasks of a dropped IPv8: {}", e);
+        }
     }
 }
+
+/// A started IPv8 instance, returned by [IPv8::start].
+///
+/// Dropping the handle does not stop IPv8. Once it is [stopped](IPv8Handle::stop) its socket is released, so a new
+/// instance can be created with the same [Config].
+pub struct IPv8Handle {
+    /// The config IPv8 was started with
+    pub config: Config,
+    /// The endpoint packets are sent and received over
+    pub endpoint: Arc<dyn Endpoint>,
+    /// The tasks of all communities and of the walker
+    pub task_manager: Arc<TaskManager>,
+    /// The registry containing all the communities, which receives every packet from the endpoint
+    pub communities: Arc<CommunityRegistry>,
+    /// The traffic of every community
+    pub statistics: Arc<Statistics>,
+}
+
+impl IPv8Handle {
+    /// Returns the traffic of every community so far. Cheap enough to be polled by monitoring.
+    pub fn statistics(&self) -> Result<StatisticsSnapshot, Ipv8Error> {
+        snapshot(&self.statistics, &*self.endpoint)
+    }
+
+    /// Stops IPv8. The walker stops first, then every community is [unloaded](crate::community::Community::unload)
+    /// while the endpoint can still send. Packets are sent as soon as they are handed to the endpoint, so none are
+    /// lost when it is closed afterwards. Returns once the endpoint stopped receiving and all tasks are cancelled.
+    pub fn stop(self) -> Result<(), Ipv8Error> {
+        self.task_manager.cancel_pending_task(WALKER_TASK)?;
+        let unloaded = self.communities.unload_all();
+
+        if self.endpoint.is_open() {
+            self.endpoint.close()?;
+        }
+        self.endpoint.join()?;
+        self.task_manager.cancel_all_pending_tasks()?;
+        unloaded
+    }
+}
+
+/// Takes a snapshot of the statistics, with the queue statistics of the endpoint
+fn snapshot(
+    statistics: &Statistics,
+    endpoint: &dyn Endpoint,
+) -> Result<StatisticsSnapshot, Ipv8Error> {
+    let mut snapshot = statistics.snapshot()?;
+    snapshot.queue = endpoint.receive_stats();
+    Ok(snapshot)
+}
@@ -1,27 +1,70 @@
 //! Module containing structs for working with network addresses
 //!
-//! This also provides the serialization and deserialization of network addresses
+//! This also provides the serialization and deserialization of network addresses.
+//!
+//! py-ipv8 payloads encode an [Address] as 4 address bytes followed by a big-endian port, which leaves no room for
+//! IPv6. IPv6 addresses are encoded with [Ipv6Address] instead: 16 address bytes followed by a big-endian port, the
+//! same as py-ipv8's `ipv6` format.
 use serde::{Deserialize, Serialize, Serializer, Deserializer};
-use std::net::{Ipv4Addr, SocketAddr, IpAddr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, IpAddr};
 use serde::ser::SerializeTuple;
 use serde::de::{Visitor, SeqAccess};
 use std::fmt;
 
 /// Wrapper for a SocketAddr. Had to be wrapped to serialize it properly
-/// Currently this can only be used as an IPV4 address + port
-#[derive(Debug, PartialEq)]
+///
+/// Only IPv4 addresses, or IPv6 addresses which map to one, can be serialized as an [Address].
+/// Use [Ipv6Address] to put any other IPv6 address in a payload.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Address(pub SocketAddr);
 
+impl Address {
+    /// Returns the IPv4 address an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) stands for, or the address itself.
+    ///
+    /// Dual-stack sockets report IPv4 peers with such mapped addresses, normalizing them makes sure a peer is known
+    /// under the same address no matter what kind of socket it was received on.
+    pub fn normalized(&self) -> Self {
+        match self.0.ip() {
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(v4) => Address(SocketAddr::new(IpAddr::V4(v4), self.0.port())),
+                None => *self,
+            },
+            IpAddr::V4(_) => *self,
+        }
+    }
+
+    /// Returns true if this is an IPv6 address which does not map to an IPv4 address.
+    /// These can not be serialized as an [Address], only as an [Ipv6Address].
+    pub fn is_ipv6(&self) -> bool {
+        match self.normalized().0 {
+            SocketAddr::V6(_) => true,
+            SocketAddr::V4(_) => false,
+        }
+    }
+
+    /// Returns this address as an IPv6 address, mapping IPv4 addresses to `::ffff:a.b.c.d`.
+    /// Needed to send to IPv4 peers from a dual-stack socket.
+    pub fn to_ipv6_mapped(&self) -> Self {
+        match self.0.ip() {
+            IpAddr::V4(ip) => Address(SocketAddr::new(
+                IpAddr::V6(ip.to_ipv6_mapped()),
+                self.0.port(),
+            )),
+            IpAddr::V6(_) => *self,
+        }
+    }
+}
+
 impl Serialize for Address {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let v4 = match self.0.ip() {
+        let v4 = match self.normalized().0.ip() {
             IpAddr::V4(a) => a,
             IpAddr::V6(_) => {
                 return Err(serde::ser::Error::custom(
-                    "ip-v8 does not (yet) support ipv6",
+                    "IPv6 addresses can only be serialized as an Ipv6Address",
                 ))
             }
         };
@@ -67,6 +110,64 @@ impl<'de> Deserialize<'de> for Address {
     }
 }
 
+/// An address in py-ipv8's `ipv6` format: 16 address bytes followed by a big-endian port.
+///
+/// IPv4 addresses are serialized as IPv4-mapped IPv6 addresses, and deserialized back into IPv4 addresses.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Ipv6Address(pub Address);
+
+impl Serialize for Ipv6Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let Ipv6Address(Address(address)) = self;
+        let v6 = match address.ip() {
+            IpAddr::V4(a) => a.to_ipv6_mapped(),
+            IpAddr::V6(a) => a,
+        };
+
+        let mut state = serializer.serialize_tuple(2)?;
+        state.serialize_element(&v6)?;
+        state.serialize_element(&address.port())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Ipv6Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[doc(hidden)]
+        struct Ipv6AddressVisitor;
+        impl<'de> Visitor<'de> for Ipv6AddressVisitor {
+            type Value = Ipv6Address;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Ipv6Address")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let addr: Ipv6Addr = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::custom("Could not deserialize the address"))?;
+                let port: u16 = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::custom("Could not deserialize the port"))?;
+
+                Ok(Ipv6Address(
+                    Address(SocketAddr::new(IpAddr::V6(addr), port)).normalized(),
+                ))
+            }
+        }
+
+        deserializer.deserialize_tuple(2, Ipv6AddressVisitor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +196,53 @@ mod tests {
             bincode::deserialize(&bincode::serialize(&i).unwrap()).unwrap()
         );
     }
+
+    #[test]
+    fn test_mapped_serialization() {
+        let mapped = Address(SocketAddr::new(
+            IpAddr::V6(Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped()),
+            8000,
+        ));
+
+        assert_eq!(
+            bincode::serialize(&mapped).unwrap(),
+            vec![127, 0, 0, 1, 64, 31]
+        );
+        assert!(!mapped.is_ipv6());
+    }
+
+    #[test]
+    fn test_ipv6_serialization() {
+        let i = Address(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 8000));
+        assert!(i.is_ipv6());
+        assert!(bincode::serialize(&i).is_err());
+
+        let mut expected = vec![0; 15];
+        expected.extend_from_slice(&[1, 0x1f, 0x40]);
+        assert_eq!(
+            bincode::config()
+                .big_endian()
+                .serialize(&Ipv6Address(i))
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            Ipv6Address(i),
+            bincode::deserialize(&bincode::serialize(&Ipv6Address(i)).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ipv6_of_ipv4() {
+        let i = Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 80));
+        let serialized = bincode::serialize(&Ipv6Address(i)).unwrap();
+        assert_eq!(
+            serialized[..16],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 1, 2, 3, 4]
+        );
+
+        // deserialized back into the IPv4 address
+        let deserialized: Ipv6Address = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized.0, i);
+    }
 }
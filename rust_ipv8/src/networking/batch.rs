@@ -0,0 +1,538 @@
+//! Sends and receives many packets with a single system call.
+//!
+//! On Linux this uses `recvmmsg` and `sendmmsg`. Other platforms fall back to one `recv_from` or `send_to` per packet,
+//! behind the same interface. A [RecvBatch] owns the buffers packets are received in, so they are allocated once
+//! instead of for every packet.
+//!
+//! Where the kernel supports UDP generic segmentation offload (`UDP_SEGMENT`, Linux 4.18 and later), consecutive
+//! packets of the same size to the same address are handed to the kernel as a single message, which splits them up
+//! again. Only the last packet of such a run may be shorter. When the kernel or the network card turns out not to
+//! support it, the packets are sent one message each.
+
+use crate::networking::address::Address;
+use crate::serialization::Packet;
+use mio::net::UdpSocket;
+use std::io;
+use std::net::SocketAddr;
+
+/// Reusable buffers to receive a batch of packets in.
+pub struct RecvBatch {
+    /// One buffer for every packet of a batch
+    buffers: Vec<Vec<u8>>,
+    /// The size and sender of every packet received by the last [recv](RecvBatch::recv)
+    received: Vec<(usize, SocketAddr)>,
+    /// The system call structures, kept to not allocate them for every batch
+    #[cfg(target_os = "linux")]
+    headers: linux::Headers,
+}
+
+impl RecvBatch {
+    /// Creates buffers to receive up to `batchsize` packets of at most `buffersize` bytes at once
+    pub fn new(batchsize: usize, buffersize: usize) -> Self {
+        let batchsize = batchsize.max(1);
+        Self {
+            buffers: vec![vec![0; buffersize]; batchsize],
+            received: Vec::with_capacity(batchsize),
+            #[cfg(target_os = "linux")]
+            headers: linux::Headers::new(batchsize),
+        }
+    }
+
+    /// Receives the packets which are waiting on the socket, as many as fit in the batch.
+    /// Returns the number of packets received, which is 0 when no packets are waiting.
+    pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.received.clear();
+
+        #[cfg(target_os = "linux")]
+        let result = self
+            .headers
+            .recv(socket, &mut self.buffers, &mut self.received);
+        #[cfg(not(target_os = "linux"))]
+        let result = fallback::recv(socket, &mut self.buffers, &mut self.received);
+
+        match result {
+            Ok(()) => Ok(self.received.len()),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the contents and sender of every packet received by the last [recv](RecvBatch::recv)
+    pub fn packets(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.received
+            .iter()
+            .zip(&self.buffers)
+            .map(|((size, address), buffer)| (&buffer[..*size], *address))
+    }
+}
+
+/// Sends every packet to its address. Returns the number of packets sent, which is less than the number of packets
+/// given when the send buffer of the socket filled up. Fails when not a single packet could be sent.
+pub fn send_batch(socket: &UdpSocket, packets: &[(Address, Packet)]) -> io::Result<usize> {
+    #[cfg(target_os = "linux")]
+    return linux::send(socket, packets);
+    #[cfg(not(target_os = "linux"))]
+    return fallback::send(socket, packets);
+}
+
+/// `recvmmsg` and `sendmmsg`
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use libc::{
+        c_int, c_uint, c_void, iovec, mmsghdr, sockaddr_in, sockaddr_in6, sockaddr_storage,
+        socklen_t,
+    };
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+    use std::ops::Range;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    /// The socket option and control message setting the segment size, from `linux/udp.h`
+    const UDP_SEGMENT: c_int = 103;
+    /// The most segments the kernel accepts in a single message
+    const MAX_SEGMENTS: usize = 64;
+    /// The most bytes a single UDP message can carry
+    const MAX_MESSAGE_SIZE: usize = 65507;
+    /// Larger packets are sent on their own. A segment has to fit in a single IP packet, while a packet sent on its
+    /// own may be fragmented. This is what fits in an Ethernet frame over IPv6.
+    const MAX_SEGMENT_SIZE: usize = 1452;
+
+    /// It is not known yet whether the kernel segments packets
+    const GSO_UNKNOWN: u8 = 0;
+    /// The kernel segments packets
+    const GSO_SUPPORTED: u8 = 1;
+    /// The kernel or the network card does not segment packets
+    const GSO_UNSUPPORTED: u8 = 2;
+    /// Whether packets are segmented by the kernel, found out on the first send
+    static GSO: AtomicU8 = AtomicU8::new(GSO_UNKNOWN);
+
+    /// Room for a control message holding the segment size, aligned like a `cmsghdr`
+    type Control = [u64; 4];
+
+    /// The structures `recvmmsg` fills in. They point into the buffers of a [RecvBatch], so they are set up again for
+    /// every call.
+    pub(super) struct Headers {
+        /// The sender of every packet
+        addresses: Vec<sockaddr_storage>,
+        /// The buffer of every packet
+        iovecs: Vec<iovec>,
+        /// The message headers tying the above together
+        messages: Vec<mmsghdr>,
+    }
+
+    // The raw pointers only ever point into the RecvBatch owning the headers, which are moved along with it
+    unsafe impl Send for Headers {}
+
+    impl Headers {
+        /// Allocates the structures for a batch of the given size
+        pub(super) fn new(batchsize: usize) -> Self {
+            // These are plain C structures, for which all zeroes is a valid value
+            Self {
+                addresses: vec![unsafe { mem::zeroed() }; batchsize],
+                iovecs: vec![unsafe { mem::zeroed() }; batchsize],
+                messages: vec![unsafe { mem::zeroed() }; batchsize],
+            }
+        }
+
+        /// Receives a batch into the buffers and stores the size and sender of every packet in `received`
+        pub(super) fn recv(
+            &mut self,
+            socket: &UdpSocket,
+            buffers: &mut [Vec<u8>],
+            received: &mut Vec<(usize, SocketAddr)>,
+        ) -> io::Result<()> {
+            for (i, buffer) in buffers.iter_mut().enumerate() {
+                self.iovecs[i] = iovec {
+                    iov_base: buffer.as_mut_ptr() as *mut c_void,
+                    iov_len: buffer.len(),
+                };
+                let message = &mut self.messages[i];
+                message.msg_hdr.msg_name =
+                    &mut self.addresses[i] as *mut sockaddr_storage as *mut c_void;
+                message.msg_hdr.msg_namelen = mem::size_of::<sockaddr_storage>() as socklen_t;
+                message.msg_hdr.msg_iov = &mut self.iovecs[i];
+                message.msg_hdr.msg_iovlen = 1;
+                message.msg_hdr.msg_control = ptr::null_mut();
+                message.msg_hdr.msg_controllen = 0;
+                message.msg_len = 0;
+            }
+
+            let count = unsafe {
+                libc::recvmmsg(
+                    socket.as_raw_fd(),
+                    self.messages.as_mut_ptr(),
+                    buffers.len() as c_uint,
+                    libc::MSG_DONTWAIT,
+                    ptr::null_mut(),
+                )
+            };
+            if count < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            for i in 0..count as usize {
+                match to_socket_addr(&self.addresses[i]) {
+                    Some(address) => received.push((self.messages[i].msg_len as usize, address)),
+                    None => warn!("Dropped a packet from an unknown address family"),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Sends the packets with as few `sendmmsg` calls as possible, segmenting them in the kernel where it can
+    pub(super) fn send(socket: &UdpSocket, packets: &[(Address, Packet)]) -> io::Result<usize> {
+        send_messages(socket, packets, gso_supported(socket))
+    }
+
+    /// Returns true if the kernel segments packets, asking it the first time
+    fn gso_supported(socket: &UdpSocket) -> bool {
+        match GSO.load(Ordering::Relaxed) {
+            GSO_SUPPORTED => true,
+            GSO_UNSUPPORTED => false,
+            _ => {
+                let mut size: c_int = 0;
+                let mut length = mem::size_of::<c_int>() as socklen_t;
+                let supported = unsafe {
+                    libc::getsockopt(
+                        socket.as_raw_fd(),
+                        libc::SOL_UDP,
+                        UDP_SEGMENT,
+                        &mut size as *mut c_int as *mut c_void,
+                        &mut length,
+                    )
+                } == 0;
+                GSO.store(
+                    if supported {
+                        GSO_SUPPORTED
+                    } else {
+                        GSO_UNSUPPORTED
+                    },
+                    Ordering::Relaxed,
+                );
+                supported
+            }
+        }
+    }
+
+    /// Splits the packets into the runs which are sent as one message each. Without segmentation, every packet is a
+    /// run of its own.
+    pub(super) fn runs(packets: &[(Address, Packet)], gso: bool) -> Vec<Range<usize>> {
+        let mut runs = vec![];
+        let mut start = 0;
+        while start < packets.len() {
+            let (address, first) = &packets[start];
+            let size = first.raw().len();
+            let mut end = start + 1;
+            if gso && size > 0 && size <= MAX_SEGMENT_SIZE {
+                let mut total = size;
+                while end < packets.len() && end - start < MAX_SEGMENTS {
+                    let (next_address, next) = &packets[end];
+                    let next_size = next.raw().len();
+                    if next_address != address
+                        || next_size > size
+                        || next_size == 0
+                        || total + next_size > MAX_MESSAGE_SIZE
+                    {
+                        break;
+                    }
+                    total += next_size;
+                    end += 1;
+                    // only the last segment may be shorter
+                    if next_size < size {
+                        break;
+                    }
+                }
+            }
+            runs.push(start..end);
+            start = end;
+        }
+        runs
+    }
+
+    /// Sends every run of packets as one message
+    fn send_messages(
+        socket: &UdpSocket,
+        packets: &[(Address, Packet)],
+        gso: bool,
+    ) -> io::Result<usize> {
+        let runs = runs(packets, gso);
+        let mut addresses: Vec<sockaddr_storage> = vec![unsafe { mem::zeroed() }; runs.len()];
+        let mut controls: Vec<Control> = vec![[0; 4]; runs.len()];
+        let mut messages: Vec<mmsghdr> = vec![unsafe { mem::zeroed() }; runs.len()];
+        // sendmmsg does not write to the buffers, the pointer is only mutable because iovec is shared with recvmmsg
+        let mut iovecs: Vec<iovec> = packets
+            .iter()
+            .map(|(_, packet)| iovec {
+                iov_base: packet.raw().as_ptr() as *mut c_void,
+                iov_len: packet.raw().len(),
+            })
+            .collect();
+
+        for (i, run) in runs.iter().enumerate() {
+            let header = &mut messages[i].msg_hdr;
+            header.msg_namelen = from_socket_addr(&packets[run.start].0 .0, &mut addresses[i]);
+            header.msg_name = &mut addresses[i] as *mut sockaddr_storage as *mut c_void;
+            header.msg_iov = &mut iovecs[run.start];
+            header.msg_iovlen = run.len() as _;
+            if run.len() > 1 {
+                set_segment_size(header, &mut controls[i], packets[run.start].1.raw().len());
+            }
+        }
+
+        let mut sent_messages = 0;
+        let mut sent = 0;
+        while sent_messages < messages.len() {
+            let count = unsafe {
+                libc::sendmmsg(
+                    socket.as_raw_fd(),
+                    messages[sent_messages..].as_mut_ptr(),
+                    (messages.len() - sent_messages) as c_uint,
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if count < 0 {
+                let error = io::Error::last_os_error();
+                if gso && runs.iter().any(|run| run.len() > 1) {
+                    match error.raw_os_error() {
+                        // The network card can't segment, which only shows when sending
+                        Some(libc::EIO) => {
+                            warn!("Sending without segmentation offload: {}", error);
+                            GSO.store(GSO_UNSUPPORTED, Ordering::Relaxed);
+                            return Ok(sent + send_messages(socket, &packets[sent..], false)?);
+                        }
+                        // The segments don't fit in the packets of the route
+                        Some(libc::EINVAL) => {
+                            return Ok(sent + send_messages(socket, &packets[sent..], false)?);
+                        }
+                        _ => {}
+                    }
+                }
+                if sent > 0 && error.kind() == io::ErrorKind::WouldBlock {
+                    break;
+                }
+                return Err(error);
+            }
+            let count = count as usize;
+            sent += runs[sent_messages..sent_messages + count]
+                .iter()
+                .map(|run| run.len())
+                .sum::<usize>();
+            sent_messages += count;
+        }
+        Ok(sent)
+    }
+
+    /// Adds the control message telling the kernel to split the message into segments of the given size
+    fn set_segment_size(header: &mut libc::msghdr, control: &mut Control, size: usize) {
+        let space = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as c_uint) } as usize;
+        debug_assert!(space <= mem::size_of::<Control>());
+        header.msg_control = control.as_mut_ptr() as *mut c_void;
+        header.msg_controllen = space as _;
+        unsafe {
+            let message = libc::CMSG_FIRSTHDR(header);
+            (*message).cmsg_level = libc::SOL_UDP;
+            (*message).cmsg_type = UDP_SEGMENT;
+            (*message).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as c_uint) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(message) as *mut u16, size as u16);
+        }
+    }
+
+    /// Converts an address filled in by the kernel
+    fn to_socket_addr(storage: &sockaddr_storage) -> Option<SocketAddr> {
+        match i32::from(storage.ss_family) {
+            libc::AF_INET => {
+                let address =
+                    unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)),
+                    u16::from_be(address.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let address =
+                    unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(address.sin6_addr.s6_addr),
+                    u16::from_be(address.sin6_port),
+                    address.sin6_flowinfo,
+                    address.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts an address for the kernel. Returns the length of the address written.
+    fn from_socket_addr(address: &SocketAddr, storage: &mut sockaddr_storage) -> socklen_t {
+        match address {
+            SocketAddr::V4(v4) => {
+                let out = unsafe { &mut *(storage as *mut sockaddr_storage as *mut sockaddr_in) };
+                out.sin_family = libc::AF_INET as libc::sa_family_t;
+                out.sin_port = v4.port().to_be();
+                out.sin_addr.s_addr = u32::from(*v4.ip()).to_be();
+                mem::size_of::<sockaddr_in>() as socklen_t
+            }
+            SocketAddr::V6(v6) => {
+                let out = unsafe { &mut *(storage as *mut sockaddr_storage as *mut sockaddr_in6) };
+                out.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                out.sin6_port = v6.port().to_be();
+                out.sin6_addr.s6_addr = v6.ip().octets();
+                out.sin6_flowinfo = v6.flowinfo();
+                out.sin6_scope_id = v6.scope_id();
+                mem::size_of::<sockaddr_in6>() as socklen_t
+            }
+        }
+    }
+}
+
+/// One system call per packet, for platforms without `recvmmsg` and `sendmmsg`
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use super::*;
+
+    /// Receives packets until the buffers are full or no packets are waiting
+    pub(super) fn recv(
+        socket: &UdpSocket,
+        buffers: &mut [Vec<u8>],
+        received: &mut Vec<(usize, SocketAddr)>,
+    ) -> io::Result<()> {
+        for buffer in buffers.iter_mut() {
+            match socket.recv_from(buffer) {
+                Ok(packet) => received.push(packet),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && !received.is_empty() => {
+                    break
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends packets until all are sent or the send buffer is full
+    pub(super) fn send(socket: &UdpSocket, packets: &[(Address, Packet)]) -> io::Result<usize> {
+        for (sent, (address, packet)) in packets.iter().enumerate() {
+            match socket.send_to(packet.raw(), &address.0) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && sent > 0 => return Ok(sent),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(packets.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::test_helper::{localhost, wait_for};
+
+    fn packet(byte: u8, size: usize) -> Packet {
+        Packet(vec![byte; size])
+    }
+
+    #[test]
+    fn test_send_recv_batch() {
+        let sender = UdpSocket::bind(&localhost().0).unwrap();
+        let receiver = UdpSocket::bind(&localhost().0).unwrap();
+        let to = Address(receiver.local_addr().unwrap());
+
+        let packets: Vec<(Address, Packet)> =
+            (0..10).map(|i| (to, packet(i, 10 + i as usize))).collect();
+        assert_eq!(send_batch(&sender, &packets).unwrap(), 10);
+
+        let mut batch = RecvBatch::new(4, 2048);
+        let mut received = vec![];
+        assert!(wait_for(|| {
+            let n = batch.recv(&receiver).unwrap();
+            assert!(n <= 4);
+            received.extend(
+                batch
+                    .packets()
+                    .map(|(data, address)| (data.to_vec(), address)),
+            );
+            received.len() == 10
+        }));
+        for (i, (data, address)) in received.into_iter().enumerate() {
+            assert_eq!(data, packet(i as u8, 10 + i).raw());
+            assert_eq!(address, sender.local_addr().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_send_recv_segmented() {
+        let sender = UdpSocket::bind(&localhost().0).unwrap();
+        let receiver = UdpSocket::bind(&localhost().0).unwrap();
+        let other = UdpSocket::bind(&localhost().0).unwrap();
+        let to = Address(receiver.local_addr().unwrap());
+
+        // equal packets are segmented by the kernel where it can, they still arrive one by one
+        let mut packets: Vec<(Address, Packet)> = (0..8).map(|i| (to, packet(i, 100))).collect();
+        packets.push((to, packet(8, 50)));
+        packets.push((Address(other.local_addr().unwrap()), packet(9, 100)));
+        assert_eq!(send_batch(&sender, &packets).unwrap(), 10);
+
+        let mut batch = RecvBatch::new(16, 2048);
+        let mut received = vec![];
+        assert!(wait_for(|| {
+            batch.recv(&receiver).unwrap();
+            received.extend(batch.packets().map(|(data, _)| data.to_vec()));
+            received.len() == 9
+        }));
+        for (i, data) in received.into_iter().enumerate() {
+            assert_eq!(&data[..], packets[i].1.raw());
+        }
+        assert!(wait_for(|| batch.recv(&other).unwrap() == 1));
+        assert_eq!(batch.packets().next().unwrap().0, packet(9, 100).raw());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_runs() {
+        let a = localhost();
+        let b = Address("127.0.0.1:1".parse().unwrap());
+        let packets = vec![
+            (a, packet(0, 100)),
+            (a, packet(1, 100)),
+            (a, packet(2, 50)),
+            (a, packet(3, 50)),
+            (b, packet(4, 50)),
+            (b, packet(5, 60)),
+            (b, packet(6, 2000)),
+            (b, packet(7, 2000)),
+        ];
+        assert_eq!(
+            linux::runs(&packets, true),
+            vec![0..3, 3..4, 4..5, 5..6, 6..7, 7..8]
+        );
+        assert_eq!(linux::runs(&packets, false).len(), 8);
+
+        let many: Vec<(Address, Packet)> = (0..100).map(|_| (a, packet(0, 1000))).collect();
+        assert_eq!(linux::runs(&many, true), vec![0..64, 64..100]);
+    }
+
+    #[test]
+    fn test_recv_nothing() {
+        let receiver = UdpSocket::bind(&localhost().0).unwrap();
+        let mut batch = RecvBatch::new(4, 2048);
+        assert_eq!(batch.recv(&receiver).unwrap(), 0);
+        assert_eq!(batch.packets().count(), 0);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let sender = UdpSocket::bind(&localhost().0).unwrap();
+        let receiver = UdpSocket::bind(&localhost().0).unwrap();
+        let to = Address(receiver.local_addr().unwrap());
+
+        send_batch(&sender, &[(to, packet(1, 100))]).unwrap();
+
+        // packets larger than the buffers are cut off, like with recv_from
+        let mut batch = RecvBatch::new(4, 10);
+        assert!(wait_for(|| batch.recv(&receiver).unwrap() == 1));
+        assert_eq!(batch.packets().next().unwrap().0, &[1; 10][..]);
+    }
+}
@@ -0,0 +1,343 @@
+//! The transport packets are sent and received over.
+//!
+//! [Communities](crate::community::Community) only ever see an [Endpoint], so the UDP implementation can be replaced
+//! by another transport, an in-memory network for tests or a wrapper which, for example, counts the packets passing
+//! through it.
+
+use crate::configuration::Config;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::networking::queue::{QueueStats, ReceiveQueue};
+use crate::networking::{NetworkReceiver, NetworkSender, Receiver, StopHandle};
+use crate::serialization::Packet;
+use crate::taskmanager::TaskManager;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Sends packets to and receives packets from other peers.
+///
+/// Packets received by an open endpoint are passed to all of its listeners.
+pub trait Endpoint: Send + Sync {
+    /// Sends a [Packet] to the specified address. Returns the number of bytes sent.
+    fn send(&self, address: &Address, packet: Packet) -> Result<usize, Box<dyn Error>>;
+
+    /// Sends every [Packet] to its address. Returns the number of packets sent.
+    ///
+    /// Sends one packet at a time by default, endpoints which can send many packets at once override this.
+    fn send_batch(&self, packets: &[(Address, Packet)]) -> Result<usize, Box<dyn Error>> {
+        for (address, packet) in packets {
+            self.send(address, packet.clone())?;
+        }
+        Ok(packets.len())
+    }
+
+    /// Adds a listener which is notified of every packet received by this endpoint.
+    fn add_listener(&self, listener: Box<dyn Receiver + Send + Sync>)
+        -> Result<(), Box<dyn Error>>;
+
+    /// Starts receiving packets
+    fn open(&self) -> Result<(), Box<dyn Error>>;
+
+    /// Stops receiving packets
+    fn close(&self) -> Result<(), Box<dyn Error>>;
+
+    /// Blocks until the endpoint stopped receiving after it was closed. Packets which were already received are
+    /// handled by then.
+    fn join(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Returns true if the endpoint is receiving packets
+    fn is_open(&self) -> bool;
+
+    /// Returns the [Address] other peers can send packets for this endpoint to
+    fn local_address(&self) -> Result<Address, Box<dyn Error>>;
+
+    /// Returns how many packets were received and how many of those were dropped because they came in faster than
+    /// they were handled. None when the endpoint does not queue received packets.
+    fn receive_stats(&self) -> Option<QueueStats> {
+        None
+    }
+}
+
+/// An [Endpoint] over UDP, sending with a [NetworkSender] and receiving with a [NetworkReceiver]. Both use the same
+/// socket unless [single_socket](Config::single_socket) is turned off.
+///
+/// The [NetworkReceiver] runs the tasks of the [TaskManager] the endpoint was created with. Listeners can only be added
+/// before the endpoint is opened, and a closed endpoint can not be opened again.
+pub struct UdpEndpoint {
+    /// Sends all outgoing packets
+    sender: NetworkSender,
+    /// The receiver, until it is started by [open](Endpoint::open)
+    receiver: Mutex<Option<NetworkReceiver>>,
+    /// Stops the receiver once it is started
+    stop_handle: StopHandle,
+    /// The thread the receiver runs on once it is started
+    thread: Mutex<Option<JoinHandle<()>>>,
+    /// The queue of the receiver
+    queue: Arc<ReceiveQueue>,
+    /// The address the receiver is bound to, the sending address when they share a socket
+    receiving_address: Address,
+    /// True between [open](Endpoint::open) and [close](Endpoint::close)
+    open: AtomicBool,
+    /// The configuration the receiver is started with
+    config: Config,
+}
+
+impl UdpEndpoint {
+    /// Binds the socket, or the sending and receiving sockets, of the configuration. The tasks of the given
+    /// [TaskManager] are run by the receiver once the endpoint is opened.
+    pub fn new(config: &Config, task_manager: Arc<TaskManager>) -> Result<Self, Ipv8Error> {
+        let sender = NetworkSender::new(&config.sending_address)?;
+        let receiver = if config.single_socket {
+            NetworkReceiver::sharing_socket(&sender, task_manager)?
+        } else {
+            NetworkReceiver::with_task_manager(&config.receiving_address, task_manager)?
+        };
+
+        Ok(Self {
+            sender,
+            stop_handle: receiver.stop_handle(),
+            queue: receiver.queue(),
+            receiving_address: receiver.local_address()?,
+            receiver: Mutex::new(Some(receiver)),
+            thread: Mutex::new(None),
+            open: AtomicBool::new(false),
+            config: config.clone(),
+        })
+    }
+
+    /// Returns the [Address] the sending socket is bound to
+    pub fn sending_address(&self) -> Result<Address, Ipv8Error> {
+        self.sender.local_address()
+    }
+}
+
+impl Endpoint for UdpEndpoint {
+    fn send(&self, address: &Address, packet: Packet) -> Result<usize, Box<dyn Error>> {
+        Ok(self.sender.send(address, packet)?)
+    }
+
+    fn send_batch(&self, packets: &[(Address, Packet)]) -> Result<usize, Box<dyn Error>> {
+        Ok(self.sender.send_batch(packets)?)
+    }
+
+    fn add_listener(
+        &self,
+        listener: Box<dyn Receiver + Send + Sync>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.receiver
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?
+            .as_mut()
+            .ok_or(Ipv8Error::EndpointOpened)?
+            .add_receiver(listener);
+        Ok(())
+    }
+
+    fn open(&self) -> Result<(), Box<dyn Error>> {
+        let receiver = self
+            .receiver
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?
+            .take()
+            .ok_or(Ipv8Error::EndpointOpened)?;
+
+        *self
+            .thread
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))? = Some(receiver.start(&self.config));
+        self.open.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), Box<dyn Error>> {
+        if !self.open.swap(false, Ordering::SeqCst) {
+            return Err(Box::new(Ipv8Error::EndpointClosed));
+        }
+        Ok(self.stop_handle.stop()?)
+    }
+
+    fn join(&self) -> Result<(), Box<dyn Error>> {
+        let thread = self
+            .thread
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?
+            .take();
+        if let Some(thread) = thread {
+            thread
+                .join()
+                .or(Err(Ipv8Error::ThreadPanicked("the endpoint")))?;
+        }
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn local_address(&self) -> Result<Address, Box<dyn Error>> {
+        Ok(self.receiving_address)
+    }
+
+    fn receive_stats(&self) -> Option<QueueStats> {
+        self.queue.stats().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::test_helper::{localhost, wait_for};
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Receiver for Counter {
+        fn on_receive(&self, _packet: Packet, _address: Address) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn udp_endpoint() -> UdpEndpoint {
+        let config = Config {
+            sending_address: localhost(),
+            ..Config::default()
+        };
+        UdpEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap()
+    }
+
+    #[test]
+    fn test_single_socket() {
+        let endpoint = udp_endpoint();
+        let other = udp_endpoint();
+        let count = Arc::new(AtomicUsize::new(0));
+        endpoint
+            .add_listener(Box::new(Counter(Arc::clone(&count))))
+            .unwrap();
+        endpoint.open().unwrap();
+
+        assert_eq!(
+            endpoint.local_address().unwrap(),
+            endpoint.sending_address().unwrap()
+        );
+
+        // a reply to the address our packets come from reaches the listeners
+        let packet = Packet::new(create_test_header!()).unwrap();
+        other
+            .send(&endpoint.sending_address().unwrap(), packet)
+            .unwrap();
+        assert!(wait_for(|| count.load(Ordering::SeqCst) == 1));
+
+        endpoint.close().unwrap();
+        endpoint.join().unwrap();
+    }
+
+    #[test]
+    fn test_burst() {
+        /// Counts packets, but takes its time with the first one
+        struct SlowCounter(Arc<AtomicUsize>);
+
+        impl Receiver for SlowCounter {
+            fn on_receive(&self, _packet: Packet, _address: Address) {
+                if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                    thread::sleep(Duration::from_millis(50));
+                }
+            }
+        }
+
+        let config = Config {
+            sending_address: localhost(),
+            queuesize: 5,
+            batchsize: 4,
+            ..Config::default()
+        };
+        let endpoint = UdpEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap();
+        let other = udp_endpoint();
+        let count = Arc::new(AtomicUsize::new(0));
+        endpoint
+            .add_listener(Box::new(SlowCounter(Arc::clone(&count))))
+            .unwrap();
+        endpoint.open().unwrap();
+
+        let packets: Vec<(Address, Packet)> = (0..50)
+            .map(|_| {
+                (
+                    endpoint.local_address().unwrap(),
+                    Packet::new(create_test_header!()).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(other.send_batch(&packets).unwrap(), 50);
+
+        // the whole burst is read from the socket, but only what fits in the queue is handled
+        assert!(wait_for(|| {
+            let stats = endpoint.receive_stats().unwrap();
+            stats.received == 50 && count.load(Ordering::SeqCst) + stats.dropped == 50
+        }));
+        let stats = endpoint.receive_stats().unwrap();
+        assert!(stats.dropped > 0);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(count.load(Ordering::SeqCst), 50 - stats.dropped);
+
+        endpoint.close().unwrap();
+        endpoint.join().unwrap();
+    }
+
+    #[test]
+    fn test_separate_sockets() {
+        let config = Config {
+            sending_address: localhost(),
+            receiving_address: localhost(),
+            single_socket: false,
+            ..Config::default()
+        };
+        let endpoint = UdpEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap();
+
+        // both sockets are bound to a port of their own
+        let receiving = endpoint.local_address().unwrap();
+        let sending = endpoint.sending_address().unwrap();
+        assert_eq!(receiving.0.ip(), config.receiving_address.0.ip());
+        assert_eq!(sending.0.ip(), config.sending_address.0.ip());
+        assert_ne!(receiving, sending);
+    }
+
+    #[test]
+    fn test_open_close() {
+        let endpoint = udp_endpoint();
+        let other = udp_endpoint();
+        let count = Arc::new(AtomicUsize::new(0));
+        endpoint
+            .add_listener(Box::new(Counter(Arc::clone(&count))))
+            .unwrap();
+
+        assert!(!endpoint.is_open());
+        endpoint.open().unwrap();
+        assert!(endpoint.is_open());
+        assert!(endpoint.open().is_err());
+        assert!(endpoint
+            .add_listener(Box::new(Counter(Arc::clone(&count))))
+            .is_err());
+
+        let packet = Packet::new(create_test_header!()).unwrap();
+        other
+            .send(&endpoint.local_address().unwrap(), packet)
+            .unwrap();
+        assert!(wait_for(|| count.load(Ordering::SeqCst) == 1));
+
+        endpoint.close().unwrap();
+        endpoint.join().unwrap();
+        assert!(!endpoint.is_open());
+        assert!(endpoint.close().is_err());
+    }
+
+    #[test]
+    fn test_as_trait_object() {
+        let endpoint: Arc<dyn Endpoint> = Arc::new(udp_endpoint());
+        assert_eq!(endpoint.local_address().unwrap().0.ip(), localhost().0.ip());
+    }
+}
@@ -1,22 +1,76 @@
 //! This module handles all the network IO and allows senders to send
 //! and notifies receivers of recieved messages.
 
+use crate::error::Ipv8Error;
 use crate::serialization::Packet;
-use std::error::Error;
 use mio::net::UdpSocket;
 use std::thread;
 use std::thread::JoinHandle;
-use mio::{Poll, Token, Events, Ready, PollOpt};
+use mio::{Poll, Token, Events, Ready, PollOpt, Registration, SetReadiness};
 use crate::configuration::Config;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use std::time::Duration;
 use crate::networking::address::Address;
+use crate::networking::batch::{send_batch, RecvBatch};
+use crate::networking::queue::{DropPolicy, ReceiveQueue};
 use rayon::scope_fifo;
+use crate::taskmanager::TaskManager;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{Ipv4Addr, IpAddr, SocketAddr};
+use net2::UdpBuilder;
 
 pub mod address;
+pub mod batch;
+pub mod endpoint;
+pub mod queue;
+pub mod simulation;
+#[cfg(feature = "tokio")]
+pub mod tokioendpoint;
+
+/// The poll token of the socket of a [NetworkReceiver]
+const RECEIVER: Token = Token(0);
+/// The poll token a [StopHandle] wakes a [NetworkReceiver] up with
+const STOP: Token = Token(1);
+
+/// Binds a UDP socket to the address. Sockets bound to an IPv6 address are dual-stack, so they can also send to and
+/// receive from IPv4 peers.
+///
+/// When IPv6 is not available on this machine, binding to the unspecified IPv6 address (`[::]`) falls back to the
+/// unspecified IPv4 address with the same port.
+fn bind(address: &Address) -> Result<UdpSocket, Ipv8Error> {
+    Ok(UdpSocket::from_socket(bind_std(address)?)?)
+}
 
-create_error!(SocketCreationError, "The socket creation failed");
-create_error!(ListenError, "An error occured during the listening");
+/// Binds a blocking standard library UDP socket to the address, like [bind] does.
+fn bind_std(address: &Address) -> Result<std::net::UdpSocket, Ipv8Error> {
+    let bind_error = |source| Ipv8Error::Bind {
+        address: *address,
+        source,
+    };
+    match address.0 {
+        SocketAddr::V4(_) => std::net::UdpSocket::bind(address.0).map_err(bind_error),
+        SocketAddr::V6(v6) => {
+            let socket = UdpBuilder::new_v6().and_then(|builder| {
+                builder.only_v6(false)?;
+                builder.bind(address.0)
+            });
+            match socket {
+                Ok(socket) => Ok(socket),
+                Err(e) if v6.ip().is_unspecified() => {
+                    warn!(
+                        "Could not bind a dual-stack socket ({}), falling back to IPv4",
+                        e
+                    );
+                    let fallback =
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), address.0.port());
+                    std::net::UdpSocket::bind(fallback).map_err(bind_error)
+                }
+                Err(e) => Err(bind_error(e)),
+            }
+        }
+    }
+}
 
 /// Any struct implementing this method can become a receiver of incoming network packets.
 /// under normal operation, only the IPV8 struct should be a receiver of these and it should distribute it
@@ -26,6 +80,14 @@ pub trait Receiver {
     fn on_receive(&self, packet: Packet, address: Address);
 }
 
+/// Allows a receiver to be registered as a listener while it is used elsewhere too, like the
+/// [CommunityRegistry](crate::community::CommunityRegistry) of IPv8.
+impl<R: Receiver + ?Sized> Receiver for Arc<R> {
+    fn on_receive(&self, packet: Packet, address: Address) {
+        (**self).on_receive(packet, address)
+    }
+}
+
 /// A NetworkSender is a wrapper for a sending udp socket.
 ///
 /// Every [Community](crate::community::Community) gets one of these, there called 'endpoint'.
@@ -34,20 +96,63 @@ pub trait Receiver {
 pub struct NetworkSender {
     /// The actual socket which is used for sending
     socket: UdpSocket,
+    /// True if the socket is an IPv6 socket, which has to send to IPv4 peers on their IPv4-mapped address
+    dual_stack: bool,
 }
 
 impl NetworkSender {
     /// Creates a new [NetworkSender] object.
-    pub fn new(sending_address: &Address) -> Result<Self, Box<dyn Error>> {
-        let socket = UdpSocket::bind(&sending_address.0)?;
+    pub fn new(sending_address: &Address) -> Result<Self, Ipv8Error> {
+        let socket = bind(sending_address)?;
+        let dual_stack = socket.local_addr()?.is_ipv6();
         debug!("Starting, sending_address: {:?}", sending_address);
 
-        Ok(Self { socket })
+        Ok(Self { socket, dual_stack })
     }
 
     /// Sends a [Packet](crate::serialization::Packet) to the specified address.
-    pub fn send(&self, address: &Address, packet: Packet) -> Result<usize, Box<dyn Error>> {
-        Ok(self.socket.send_to(packet.raw(), &address.0)?)
+    pub fn send(&self, address: &Address, packet: Packet) -> Result<usize, Ipv8Error> {
+        let to = if self.dual_stack {
+            address.to_ipv6_mapped()
+        } else {
+            *address
+        };
+        self.socket
+            .send_to(packet.raw(), &to.0)
+            .map_err(|source| Ipv8Error::Send {
+                address: *address,
+                source,
+            })
+    }
+
+    /// Sends every [Packet](crate::serialization::Packet) to its address, with as few system calls as possible.
+    /// Returns the number of packets sent, which is less than the number given when the send buffer filled up.
+    pub fn send_batch(&self, packets: &[(Address, Packet)]) -> Result<usize, Ipv8Error> {
+        if self.dual_stack {
+            let packets: Vec<(Address, Packet)> = packets
+                .iter()
+                .map(|(address, packet)| (address.to_ipv6_mapped(), packet.clone()))
+                .collect();
+            Ok(send_batch(&self.socket, &packets)?)
+        } else {
+            Ok(send_batch(&self.socket, packets)?)
+        }
+    }
+
+    /// Creates a new [NetworkSender] sending from the same underlying socket.
+    ///
+    /// Used by [Communities](crate::community::Community) to keep their own handle to the endpoint they were constructed with.
+    pub fn try_clone(&self) -> Result<Self, Ipv8Error> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            dual_stack: self.dual_stack,
+        })
+    }
+
+    /// Returns the [Address] the underlying socket is bound to.
+    /// This is an IPv6 address when the socket is dual-stack.
+    pub fn local_address(&self) -> Result<Address, Ipv8Error> {
+        Ok(Address(self.socket.local_addr()?))
     }
 }
 
@@ -61,85 +166,230 @@ pub struct NetworkReceiver {
     receivers: Vec<Box<dyn Receiver + Send + Sync>>,
     /// The actual UDP socket used for receiving packets
     socket: UdpSocket,
+    /// The tasks which are run in between receiving packets
+    task_manager: Arc<TaskManager>,
+    /// Set to false to stop the listening thread
+    running: Arc<AtomicBool>,
+    /// Wakes up the listening thread when it should stop
+    registration: Registration,
+    /// Used by a [StopHandle] to trigger the [registration](NetworkReceiver::registration)
+    set_readiness: SetReadiness,
+    /// The packets which were received but not handled yet
+    queue: Arc<ReceiveQueue>,
+}
+
+/// Stops a started [NetworkReceiver]. Obtained with [NetworkReceiver::stop_handle].
+#[derive(Clone)]
+pub struct StopHandle {
+    /// The running flag of the receiver
+    running: Arc<AtomicBool>,
+    /// Wakes up the receiver so it notices it should stop
+    set_readiness: SetReadiness,
+}
+
+impl StopHandle {
+    /// Makes the listening thread of the [NetworkReceiver] return. Packets which are already being handled are not
+    /// interrupted.
+    pub fn stop(&self) -> Result<(), Ipv8Error> {
+        self.running.store(false, Ordering::SeqCst);
+        self.set_readiness.set_readiness(Ready::readable())?;
+        Ok(())
+    }
 }
 
 impl NetworkReceiver {
     /// Creates a new [NetworkReceiver]. This creates a receiver socket and builds a new threadpool on which
     /// all messages are distributed.
-    pub fn new(receiving_address: &Address) -> Result<Self, Box<dyn Error>> {
-        let socket = UdpSocket::bind(&receiving_address.0)?;
+    pub fn new(receiving_address: &Address) -> Result<Self, Ipv8Error> {
+        Self::with_task_manager(receiving_address, Arc::new(TaskManager::new()))
+    }
+
+    /// Creates a new [NetworkReceiver] which runs the tasks of the given [TaskManager].
+    pub fn with_task_manager(
+        receiving_address: &Address,
+        task_manager: Arc<TaskManager>,
+    ) -> Result<Self, Ipv8Error> {
+        let socket = bind(receiving_address)?;
 
         debug!("Starting, receiving_address: {:?}", receiving_address);
 
-        let nm = Self {
+        Ok(Self::with_socket(socket, task_manager))
+    }
+
+    /// Creates a new [NetworkReceiver] which receives on the socket of the given [NetworkSender], so peers can reply
+    /// to the address the packets they receive come from.
+    pub fn sharing_socket(
+        sender: &NetworkSender,
+        task_manager: Arc<TaskManager>,
+    ) -> Result<Self, Ipv8Error> {
+        debug!(
+            "Starting, receiving on the sending socket {:?}",
+            sender.local_address()?
+        );
+
+        Ok(Self::with_socket(sender.socket.try_clone()?, task_manager))
+    }
+
+    /// Creates a new [NetworkReceiver] receiving on an already bound socket
+    fn with_socket(socket: UdpSocket, task_manager: Arc<TaskManager>) -> Self {
+        let (registration, set_readiness) = Registration::new2();
+        Self {
             receivers: vec![],
             socket,
-        };
-        Ok(nm)
+            task_manager,
+            running: Arc::new(AtomicBool::new(true)),
+            registration,
+            set_readiness,
+            queue: Arc::new(ReceiveQueue::new(
+                Config::default().queuesize,
+                DropPolicy::default(),
+            )),
+        }
+    }
+
+    /// Returns the [Address] the underlying socket is bound to.
+    pub fn local_address(&self) -> Result<Address, Ipv8Error> {
+        Ok(Address(self.socket.local_addr()?))
     }
 
-    /// Starts the [NetworkReceiver]. This spawns a new thread in which it will listen for incoming messages.
+    /// Returns a handle which can stop this [NetworkReceiver] after it is started.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            running: Arc::clone(&self.running),
+            set_readiness: self.set_readiness.clone(),
+        }
+    }
+
+    /// Returns the [TaskManager] whose tasks are run by this [NetworkReceiver] once it is started.
+    /// Tasks can be registered both before and after starting it.
+    pub fn task_manager(&self) -> Arc<TaskManager> {
+        Arc::clone(&self.task_manager)
+    }
+
+    /// Starts the [NetworkReceiver]. This spawns a new thread in which it will listen for incoming messages
+    /// and run the tasks of its [TaskManager].
     ///
     /// This method consumes self as it is transferred to the new thread. After this no [Receivers](Receiver) can be added to it.
     ///
     /// Returns a [`JoinHandle<()>`](std::thread::JoinHandle) which can be used to block until the [NetworkReceiver] stops listening.
-    /// Under normal operation this never happens so this marks the end of the program.
+    /// This happens when it is stopped with a [StopHandle], or when receiving fails, which is logged.
     pub fn start(self, configuration: &Config) -> JoinHandle<()> {
         let queuesize = configuration.queuesize.to_owned();
         let buffersize = configuration.buffersize.to_owned();
+        let batchsize = configuration.batchsize.to_owned();
         let pollinterval = configuration.pollinterval.to_owned();
+        let drop_policy = configuration.drop_policy.to_owned();
 
         // Start the I/O thread
         thread::spawn(move || {
-            self.listen(queuesize, buffersize, pollinterval)
-                .or_else(|i| {
-                    error!("the listening thread crashed");
-                    Err(i)
-                })
-                .unwrap(); // This only panics the I/O thread not the whole application
+            if let Err(e) = self
+                .queue
+                .set_limits(queuesize, drop_policy)
+                .and_then(|_| self.listen(queuesize, buffersize, batchsize, pollinterval))
+            {
+                error!("the listening thread crashed: {}", e);
+            }
         })
     }
 
+    /// Returns the [ReceiveQueue] received packets wait in until they are handled, to see how many were dropped.
+    pub fn queue(&self) -> Arc<ReceiveQueue> {
+        Arc::clone(&self.queue)
+    }
+
     #[doc(hidden)]
     fn listen(
-        self,
+        mut self,
         queuesize: usize,
         buffersize: usize,
+        batchsize: usize,
         pollinterval: Option<Duration>,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), Ipv8Error> {
         debug!("IPV8 is starting it's listener!");
 
         let poll = Poll::new()?;
 
-        let mut events = Events::with_capacity(queuesize);
+        // There are only two tokens to get events for
+        let mut events = Events::with_capacity(2);
 
-        let mut tmp_buf = vec![0; buffersize];
-        let buffer = tmp_buf.as_mut_slice();
+        let mut batch = RecvBatch::new(batchsize, buffersize);
 
-        const RECEIVER: Token = Token(0);
         poll.register(&self.socket, RECEIVER, Ready::readable(), PollOpt::edge())?;
+        poll.register(&self.registration, STOP, Ready::readable(), PollOpt::edge())?;
+
+        // The receivers are handed the packets on a thread of their own, so a burst of packets doesn't wait for them
+        let receivers = std::mem::take(&mut self.receivers);
+        let dispatcher = {
+            let queue = Arc::clone(&self.queue);
+            thread::spawn(move || dispatch(&queue, &receivers, batchsize))
+        };
+
+        let result = self.receive(&poll, &mut events, &mut batch, queuesize, pollinterval);
+
+        self.queue.close()?;
+        if dispatcher.join().is_err() {
+            error!("the dispatching thread crashed");
+        }
+
+        debug!("IPV8 stopped it's listener");
+        result
+    }
 
-        loop {
-            poll.poll(&mut events, pollinterval)?;
+    /// Receives packets into the queue and runs tasks until stopped
+    fn receive(
+        &self,
+        poll: &Poll,
+        events: &mut Events,
+        batch: &mut RecvBatch,
+        queuesize: usize,
+        pollinterval: Option<Duration>,
+    ) -> Result<(), Ipv8Error> {
+        // True while the socket may still hold packets
+        let mut readable = false;
+        while self.running.load(Ordering::SeqCst) {
+            // Wake up in time for the next task, or right away to continue emptying the socket
+            let timeout = match (pollinterval, self.task_manager.time_until_next_task()?) {
+                _ if readable => Some(Duration::from_millis(0)),
+                (Some(interval), Some(task)) => Some(interval.min(task)),
+                (interval, task) => interval.or(task),
+            };
+
+            poll.poll(events, timeout)?;
             trace!("checking poll");
-            for _ in events.iter() {
+            if events.iter().any(|event| event.token() == RECEIVER) {
                 trace!("handling event");
+                readable = true;
+            }
 
-                let (recv_size, address) = self.socket.recv_from(buffer)?;
+            // The socket is registered edge triggered, so there is no new event until it is emptied. This reads at
+            // most a queue full at a time, so tasks still run when packets keep on coming.
+            if readable {
+                readable = self.drain(batch, queuesize)?;
+            }
+
+            self.task_manager.run_pending_tasks()?;
+        }
+        Ok(())
+    }
 
-                let packet = Packet(buffer[..recv_size].to_vec()).clone();
+    /// Moves packets from the socket to the queue until the socket is empty or `limit` packets were read.
+    /// Returns true when the socket may still hold packets.
+    fn drain(&self, batch: &mut RecvBatch, limit: usize) -> Result<bool, Ipv8Error> {
+        let mut read = 0;
+        while read < limit {
+            let received = batch.recv(&self.socket)?;
+            if received == 0 {
+                return Ok(false);
+            }
+            read += received;
 
-                // We want a FIFO threadpool
-                scope_fifo(|s| {
-                    s.spawn_fifo(|_| {
-                        // iterate over the receivers asynchronously and non blocking
-                        self.receivers.par_iter().for_each(|r| {
-                            r.on_receive(packet.clone(), Address(address));
-                        });
-                    })
-                });
+            for (data, address) in batch.packets() {
+                // IPv4 peers show up with an IPv4-mapped address on a dual-stack socket
+                self.queue
+                    .push(Packet(data.to_vec()), Address(address).normalized())?;
             }
         }
+        Ok(true)
     }
 
     /// Adds a receiver to the networkmanager. Can only happen before the networkmanager is started.
@@ -148,32 +398,78 @@ impl NetworkReceiver {
     }
 }
 
+/// Hands the packets in the queue to the receivers until the queue is closed and empty
+fn dispatch(queue: &ReceiveQueue, receivers: &[Box<dyn Receiver + Send + Sync>], batchsize: usize) {
+    loop {
+        let packets = match queue.pop_batch(batchsize) {
+            Ok(packets) => packets,
+            Err(e) => {
+                error!("the dispatching thread crashed: {}", e);
+                return;
+            }
+        };
+        if packets.is_empty() {
+            return;
+        }
+
+        // We want a FIFO threadpool
+        scope_fifo(|s| {
+            for (packet, address) in &packets {
+                s.spawn_fifo(move |_| {
+                    // iterate over the receivers asynchronously and non blocking
+                    receivers.par_iter().for_each(|r| {
+                        r.on_receive(packet.clone(), *address);
+                    });
+                })
+            }
+        });
+    }
+}
+
 /// Taken and adapted from the [mio testing suite](https://github.com/tokio-rs/mio/blob/master/test/mod.rs#L113)
 #[cfg(test)]
 pub mod test_helper {
     use std::net::{SocketAddr, Ipv4Addr, IpAddr};
-    use std::sync::atomic::Ordering::SeqCst;
-    use std::sync::atomic::{AtomicU16};
     use crate::networking::address::Address;
+    use crate::networking::endpoint::{Endpoint, UdpEndpoint};
+    use crate::configuration::Config;
+    use crate::taskmanager::TaskManager;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
-    // Helper for getting a unique port for the task run
-    // TODO: Reuse ports to not spam the system
-    // If multiple test suites are ran at the same time, this _will_ fail as it will try to bind to the same FIRST_PORT
-    const FIRST_PORT: u16 = 18080;
-    static NEXT_PORT: AtomicU16 = AtomicU16::new(FIRST_PORT);
     pub const LOCALHOST_IP: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
 
-    fn next_port() -> u16 {
-        // Get and increment the port list
-        NEXT_PORT.fetch_add(1, SeqCst)
-    }
-
+    /// A localhost address to bind to. The system picks a free port, so test suites running at the same time don't
+    /// clash. Use the local address of the bound socket to send to it.
     pub fn localhost() -> Address {
         Address(localhost_socket())
     }
 
+    /// See [localhost]
     pub fn localhost_socket() -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(LOCALHOST_IP), next_port())
+        SocketAddr::new(IpAddr::V4(LOCALHOST_IP), 0)
+    }
+
+    /// Polls the condition until it holds, for at most a second. Returns whether it did, so packets sent over a real
+    /// socket can be waited for without sleeping longer than needed.
+    pub fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        for _ in 0..1000 {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        condition()
+    }
+
+    /// An [Endpoint] bound to a fresh localhost port
+    pub fn localhost_endpoint() -> Arc<dyn Endpoint> {
+        let config = Config {
+            sending_address: localhost(),
+            ..Config::default()
+        };
+        Arc::new(UdpEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap())
     }
 }
 
@@ -190,10 +486,9 @@ mod tests {
     use std::sync::Once;
     use std::time::Duration;
     use crate::networking::{Receiver, NetworkSender, NetworkReceiver};
-    use std::thread;
     use std::sync::atomic::{AtomicUsize, Ordering, AtomicU16};
     use crate::networking::address::Address;
-    use crate::networking::test_helper::{localhost, localhost_socket, LOCALHOST_IP};
+    use crate::networking::test_helper::{localhost, localhost_socket, wait_for, LOCALHOST_IP};
 
     static BEFORE: Once = Once::new();
 
@@ -215,13 +510,13 @@ mod tests {
         config.sending_address = localhost();
         config.buffersize = 2048;
 
-        let mut ipv8 = IPv8::new(config).unwrap();
+        let ipv8 = IPv8::new(config).unwrap();
 
         let sender_socket = UdpSocket::bind(&localhost_socket()).unwrap();
 
         static SEND_PORT: AtomicU16 = AtomicU16::new(0);
 
-        let recv_port: u16 = ipv8.network_receiver.socket.local_addr().unwrap().port();
+        let recv_port: u16 = ipv8.endpoint.local_address().unwrap().0.port();
         let send_port: u16 = sender_socket.local_addr().unwrap().port();
 
         SEND_PORT.store(send_port, Ordering::SeqCst);
@@ -244,9 +539,9 @@ mod tests {
             }
         }
 
-        ipv8.network_receiver.add_receiver(Box::new(AReceiver));
+        ipv8.endpoint.add_listener(Box::new(AReceiver)).unwrap();
 
-        ipv8.start();
+        ipv8.start().unwrap();
 
         // now try to send ipv8 a message
         sender_socket
@@ -255,16 +550,49 @@ mod tests {
 
         let a = sender_socket.send(OGPACKET.raw()).unwrap();
         assert_eq!(a, OGPACKET.raw().len());
-
-        thread::sleep(Duration::from_millis(20));
+        assert!(wait_for(|| PACKET_COUNTER.load(Ordering::SeqCst) == 1));
 
         let b = sender_socket.send(OGPACKET.raw()).unwrap();
         assert_eq!(b, OGPACKET.raw().len());
 
-        thread::sleep(Duration::from_millis(20));
-
         // a poor man's `verify(AReceiver, times(2)).on_receiver();`
-        assert_eq!(2, PACKET_COUNTER.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(wait_for(|| PACKET_COUNTER.load(Ordering::SeqCst) == 2));
+    }
+
+    #[test]
+    fn test_tasks_run_by_receiver() {
+        before();
+
+        let config = Config {
+            receiving_address: localhost(),
+            // without a poll interval the receiver only wakes up for tasks
+            pollinterval: None,
+            ..Config::default()
+        };
+
+        let nr = NetworkReceiver::new(&config.receiving_address).unwrap();
+        let task_manager = nr.task_manager();
+
+        static TASK_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        task_manager
+            .register_task(
+                "count",
+                || {
+                    TASK_COUNTER.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                },
+                Some(Duration::from_millis(10)),
+                Some(Duration::from_millis(10)),
+            )
+            .unwrap();
+
+        let stop = nr.stop_handle();
+        let handle = nr.start(&config);
+        assert!(wait_for(|| TASK_COUNTER.load(Ordering::SeqCst) >= 3));
+        task_manager.cancel_pending_task("count").unwrap();
+
+        stop.stop().unwrap();
+        handle.join().unwrap();
     }
 
     #[test]
@@ -314,9 +642,7 @@ mod tests {
 
         ns.send(&addr, Packet(OGPACKET.raw().to_vec())).unwrap();
 
-        thread::sleep(Duration::from_millis(20));
-
-        // a poor man's `verify(AReceiver, times(2)).on_receiver();`
-        assert_eq!(1, PACKET_COUNTER.load(std::sync::atomic::Ordering::SeqCst));
+        // a poor man's `verify(AReceiver, times(1)).on_receiver();`
+        assert!(wait_for(|| PACKET_COUNTER.load(Ordering::SeqCst) == 1));
     }
 }
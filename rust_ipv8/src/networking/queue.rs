@@ -0,0 +1,224 @@
+//! The queue between the thread receiving packets and the threads handling them.
+//!
+//! The [NetworkReceiver](crate::networking::NetworkReceiver) empties the socket as fast as it can into a bounded
+//! [ReceiveQueue]. When packets come in faster than the communities handle them, the queue fills up and packets are
+//! dropped according to the [DropPolicy], instead of piling up in the kernel buffer unseen.
+
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::serialization::Packet;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// Which packet is dropped when a packet arrives at a full [ReceiveQueue]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum DropPolicy {
+    /// The packet which just arrived is dropped, like a full kernel buffer does
+    #[default]
+    DropNewest,
+    /// The packet which waited longest is dropped to make room for the new one
+    DropOldest,
+}
+
+/// What happened to the packets received by an endpoint so far
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct QueueStats {
+    /// The number of packets received from the socket
+    pub received: usize,
+    /// The number of packets dropped because the queue was full
+    pub dropped: usize,
+    /// The number of packets waiting to be handled right now
+    pub queued: usize,
+}
+
+/// The part of the queue behind the lock
+struct State {
+    /// The packets waiting to be handled, oldest first
+    packets: VecDeque<(Packet, Address)>,
+    /// The max number of waiting packets
+    capacity: usize,
+    /// What to drop when the queue is full
+    policy: DropPolicy,
+    /// Set when no packets will be pushed anymore
+    closed: bool,
+}
+
+/// A bounded queue of received packets, see the [module documentation](crate::networking::queue).
+pub struct ReceiveQueue {
+    /// The packets and the limits of the queue
+    state: Mutex<State>,
+    /// Signalled when a packet is pushed or the queue is closed
+    available: Condvar,
+    /// The number of packets pushed
+    received: AtomicUsize,
+    /// The number of packets dropped
+    dropped: AtomicUsize,
+}
+
+impl ReceiveQueue {
+    /// Creates an empty queue holding at most `capacity` packets
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            state: Mutex::new(State {
+                packets: VecDeque::new(),
+                capacity: capacity.max(1),
+                policy,
+                closed: false,
+            }),
+            available: Condvar::new(),
+            received: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Changes the capacity and drop policy. A queue which holds more packets than the new capacity keeps them.
+    pub fn set_limits(&self, capacity: usize, policy: DropPolicy) -> Result<(), Ipv8Error> {
+        let mut state = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the receive queue")))?;
+        state.capacity = capacity.max(1);
+        state.policy = policy;
+        Ok(())
+    }
+
+    /// Adds a received packet, dropping one when the queue is full
+    pub fn push(&self, packet: Packet, address: Address) -> Result<(), Ipv8Error> {
+        self.received.fetch_add(1, Ordering::SeqCst);
+        let mut state = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the receive queue")))?;
+
+        if state.packets.len() >= state.capacity {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+            match state.policy {
+                DropPolicy::DropNewest => {
+                    trace!("Receive queue full, dropped a packet from {:?}", address);
+                    return Ok(());
+                }
+                DropPolicy::DropOldest => {
+                    state.packets.pop_front();
+                }
+            }
+        }
+
+        state.packets.push_back((packet, address));
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Takes up to `max` packets, oldest first. Blocks until there is at least one packet, or returns none when the
+    /// queue is closed and empty.
+    pub fn pop_batch(&self, max: usize) -> Result<Vec<(Packet, Address)>, Ipv8Error> {
+        let mut state = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the receive queue")))?;
+        while state.packets.is_empty() && !state.closed {
+            state = self
+                .available
+                .wait(state)
+                .or(Err(Ipv8Error::LockPoisoned("the receive queue")))?;
+        }
+
+        let count = state.packets.len().min(max.max(1));
+        Ok(state.packets.drain(..count).collect())
+    }
+
+    /// Wakes up everyone waiting in [pop_batch](ReceiveQueue::pop_batch) once the queue is empty. Packets which are
+    /// already queued can still be taken.
+    pub fn close(&self) -> Result<(), Ipv8Error> {
+        self.state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the receive queue")))?
+            .closed = true;
+        self.available.notify_all();
+        Ok(())
+    }
+
+    /// Returns the number of packets received, dropped and waiting
+    pub fn stats(&self) -> Result<QueueStats, Ipv8Error> {
+        let queued = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the receive queue")))?
+            .packets
+            .len();
+        Ok(QueueStats {
+            received: self.received.load(Ordering::SeqCst),
+            dropped: self.dropped.load(Ordering::SeqCst),
+            queued,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::test_helper::localhost;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn packet(byte: u8) -> Packet {
+        Packet(vec![byte])
+    }
+
+    fn contents(packets: Vec<(Packet, Address)>) -> Vec<u8> {
+        packets.iter().map(|(packet, _)| packet.raw()[0]).collect()
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let queue = ReceiveQueue::new(2, DropPolicy::DropNewest);
+        for byte in 0..4 {
+            queue.push(packet(byte), localhost()).unwrap();
+        }
+
+        assert_eq!(
+            queue.stats().unwrap(),
+            QueueStats {
+                received: 4,
+                dropped: 2,
+                queued: 2
+            }
+        );
+        assert_eq!(contents(queue.pop_batch(10).unwrap()), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let queue = ReceiveQueue::new(2, DropPolicy::DropOldest);
+        for byte in 0..4 {
+            queue.push(packet(byte), localhost()).unwrap();
+        }
+
+        assert_eq!(queue.stats().unwrap().dropped, 2);
+        assert_eq!(contents(queue.pop_batch(1).unwrap()), vec![2]);
+        assert_eq!(contents(queue.pop_batch(1).unwrap()), vec![3]);
+    }
+
+    #[test]
+    fn test_close() {
+        let queue = Arc::new(ReceiveQueue::new(10, DropPolicy::default()));
+        let consumer_queue = Arc::clone(&queue);
+        let consumer = thread::spawn(move || {
+            let mut popped = vec![];
+            loop {
+                let packets = consumer_queue.pop_batch(10).unwrap();
+                if packets.is_empty() {
+                    return popped;
+                }
+                popped.extend(contents(packets));
+            }
+        });
+
+        queue.push(packet(1), localhost()).unwrap();
+        queue.push(packet(2), localhost()).unwrap();
+        queue.close().unwrap();
+
+        // everything queued before closing is still handled
+        assert_eq!(consumer.join().unwrap(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,787 @@
+//! An in-memory network for deterministic tests with many peers.
+//!
+//! A [SimulatedNetwork] connects any number of [SimulatedEndpoints](SimulatedEndpoint) with virtual addresses. Nothing
+//! happens until the network is [advanced](SimulatedNetwork::advance): packets are delivered and tasks are run on the
+//! calling thread, in the order of a virtual [Clock]. Latency, packet loss, reordering and NAT behaviour are
+//! simulated with a seeded random number generator, so every run of a test behaves exactly the same.
+//!
+//! ```
+//! use rust_ipv8::networking::address::Address;
+//! use rust_ipv8::networking::endpoint::Endpoint;
+//! use rust_ipv8::networking::simulation::SimulatedNetwork;
+//! use rust_ipv8::serialization::header::Header;
+//! use rust_ipv8::serialization::Packet;
+//! use std::time::Duration;
+//!
+//! let network = SimulatedNetwork::new(42);
+//! let alice = network.add_endpoint(Address("1.0.0.1:8000".parse().unwrap()));
+//! let bob = network.add_endpoint(Address("1.0.0.2:8000".parse().unwrap()));
+//! bob.open().unwrap();
+//!
+//! let packet = Packet::new(Header::py_ipv8_header([0; 20], 1)).unwrap();
+//! alice.send(&bob.local_address().unwrap(), packet).unwrap();
+//! network.advance(Duration::from_millis(100)).unwrap();
+//! assert_eq!(network.delivered_packets().unwrap(), 1);
+//! ```
+
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::networking::endpoint::Endpoint;
+use crate::networking::Receiver;
+use crate::serialization::Packet;
+use crate::taskmanager::{Clock, TaskManager};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// A clock which only moves when it is told to. Shared by a [SimulatedNetwork] and the [TaskManagers](TaskManager) of
+/// its endpoints.
+pub struct VirtualClock {
+    /// The moment virtual time started
+    start: Instant,
+    /// The virtual time passed since the start, in nanoseconds
+    elapsed: AtomicU64,
+}
+
+impl VirtualClock {
+    /// Creates a clock at virtual time zero
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the virtual time passed since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed.load(AtomicOrdering::SeqCst))
+    }
+
+    /// Moves the clock forward by the given duration. Tasks do not run by themselves, a
+    /// [SimulatedNetwork](SimulatedNetwork::advance) or a test has to run them.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed
+            .fetch_add(duration.as_nanos() as u64, AtomicOrdering::SeqCst);
+    }
+
+    /// Moves the clock forward to the given virtual time. The clock never moves back.
+    fn advance_to(&self, elapsed: Duration) {
+        self.elapsed
+            .fetch_max(elapsed.as_nanos() as u64, AtomicOrdering::SeqCst);
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+/// How the packets on a [SimulatedNetwork] are delayed and dropped.
+#[derive(Debug, Clone)]
+pub struct LinkConditions {
+    /// The time every packet takes to arrive
+    pub latency: Duration,
+    /// Every packet is delayed by a random extra time up to this, which reorders packets sent close together
+    pub jitter: Duration,
+    /// The chance, between 0 and 1, that a packet is lost
+    pub loss: f64,
+}
+
+impl Default for LinkConditions {
+    /// A perfect link with 10ms latency
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(10),
+            jitter: Duration::from_millis(0),
+            loss: 0.0,
+        }
+    }
+}
+
+/// The NAT an endpoint is behind
+struct Nat {
+    /// The address other peers see the packets of the endpoint come from
+    public: Address,
+    /// The addresses the endpoint sent to. Only packets from these pass the NAT (a port restricted cone NAT).
+    punched: HashSet<Address>,
+}
+
+/// An endpoint connected to the network
+struct Node {
+    /// The address of the endpoint itself
+    address: Address,
+    /// The NAT the endpoint is behind, if any
+    nat: Option<Nat>,
+    /// The endpoint, to deliver packets to
+    endpoint: Arc<SimulatedEndpoint>,
+}
+
+/// A packet on its way
+struct InFlight {
+    /// The virtual time the packet arrives
+    arrival: Duration,
+    /// Keeps packets arriving at the same time in the order they were sent
+    sequence: u64,
+    /// The node the packet is for
+    to: usize,
+    /// The address the packet appears to come from
+    from: Address,
+    /// The packet itself
+    packet: Packet,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        (self.arrival, self.sequence) == (other.arrival, other.sequence)
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.arrival, self.sequence).cmp(&(other.arrival, other.sequence))
+    }
+}
+
+/// The mutable state of a [SimulatedNetwork]
+struct State {
+    /// All endpoints
+    nodes: Vec<Node>,
+    /// The node at every address, both the addresses of the endpoints and the public addresses of their NATs
+    routes: HashMap<Address, usize>,
+    /// The packets on their way, the first to arrive on top
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    /// The sequence number of the next packet
+    next_sequence: u64,
+    /// Decides which packets are lost and how long they take
+    rng: StdRng,
+    /// The number of packets handed to an endpoint
+    delivered: usize,
+    /// The number of packets lost, blocked by a NAT or sent to nobody
+    dropped: usize,
+}
+
+/// The shared part of a [SimulatedNetwork], which its endpoints hold on to
+struct Shared {
+    /// The virtual time of the network
+    clock: Arc<VirtualClock>,
+    /// The delay and loss of all packets
+    conditions: RwLock<LinkConditions>,
+    /// Everything else
+    state: Mutex<State>,
+}
+
+impl Shared {
+    /// Puts a packet sent by the given node on its way
+    fn send(&self, from: usize, address: &Address, packet: Packet) -> Result<(), Ipv8Error> {
+        let conditions = self
+            .conditions
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?
+            .clone();
+        let mut state = self
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?;
+
+        let sender = &state.nodes[from];
+        let sender_address = sender.address;
+        let sender_public = sender.nat.as_ref().map(|nat| nat.public);
+        if let Some(nat) = state.nodes[from].nat.as_mut() {
+            nat.punched.insert(*address);
+        }
+
+        let to = match state.routes.get(address) {
+            Some(to) => *to,
+            None => {
+                state.dropped += 1;
+                return Ok(());
+            }
+        };
+
+        let target = &state.nodes[to];
+        let from_address = if target.address == *address {
+            match (&target.nat, sender_public) {
+                // A public endpoint
+                (None, Some(public)) => public,
+                (None, None) => sender_address,
+                // Behind a NAT, only reachable from inside the same NAT
+                (Some(nat), Some(public)) if same_host(&nat.public, &public) => sender_address,
+                (Some(_), _) => {
+                    state.dropped += 1;
+                    return Ok(());
+                }
+            }
+        } else {
+            // The public address of a NAT, which only lets through packets from addresses it sent to
+            let from_address = sender_public.unwrap_or(sender_address);
+            let punched = match &target.nat {
+                Some(nat) => nat.punched.contains(&from_address),
+                None => false,
+            };
+            if !punched {
+                state.dropped += 1;
+                return Ok(());
+            }
+            from_address
+        };
+
+        if state.rng.gen::<f64>() < conditions.loss {
+            state.dropped += 1;
+            return Ok(());
+        }
+        let jitter = if conditions.jitter > Duration::from_millis(0) {
+            Duration::from_nanos(state.rng.gen_range(0, conditions.jitter.as_nanos() as u64))
+        } else {
+            Duration::from_millis(0)
+        };
+
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        state.in_flight.push(Reverse(InFlight {
+            arrival: self.clock.elapsed() + conditions.latency + jitter,
+            sequence,
+            to,
+            from: from_address,
+            packet,
+        }));
+        Ok(())
+    }
+}
+
+/// Returns true if both addresses have the same IP address
+fn same_host(a: &Address, b: &Address) -> bool {
+    let ip: IpAddr = a.0.ip();
+    ip == b.0.ip()
+}
+
+/// An in-memory network, see the [module documentation](crate::networking::simulation).
+pub struct SimulatedNetwork {
+    /// The state shared with all endpoints
+    shared: Arc<Shared>,
+}
+
+impl SimulatedNetwork {
+    /// Creates an empty network with [default](LinkConditions::default) link conditions. The seed determines which
+    /// packets are lost and how they are reordered.
+    pub fn new(seed: u64) -> Self {
+        Self::with_conditions(seed, LinkConditions::default())
+    }
+
+    /// Creates an empty network with the given link conditions
+    pub fn with_conditions(seed: u64, conditions: LinkConditions) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                clock: Arc::new(VirtualClock::new()),
+                conditions: RwLock::new(conditions),
+                state: Mutex::new(State {
+                    nodes: vec![],
+                    routes: HashMap::new(),
+                    in_flight: BinaryHeap::new(),
+                    next_sequence: 0,
+                    rng: StdRng::seed_from_u64(seed),
+                    delivered: 0,
+                    dropped: 0,
+                }),
+            }),
+        }
+    }
+
+    /// Returns the virtual clock of the network. [TaskManagers](TaskManager) running on it only run their tasks when
+    /// the network is advanced.
+    pub fn clock(&self) -> Arc<VirtualClock> {
+        Arc::clone(&self.shared.clock)
+    }
+
+    /// Changes the delay and loss of all packets sent from now on
+    pub fn set_conditions(&self, conditions: LinkConditions) -> Result<(), Ipv8Error> {
+        *self
+            .shared
+            .conditions
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))? = conditions;
+        Ok(())
+    }
+
+    /// Adds a publicly reachable endpoint at the given address.
+    ///
+    /// # Panics
+    /// When the address is already in use.
+    pub fn add_endpoint(&self, address: Address) -> Arc<SimulatedEndpoint> {
+        self.try_add_endpoint(address, None).unwrap()
+    }
+
+    /// Adds an endpoint behind a port restricted cone NAT. Other peers see its packets come from the public address,
+    /// and can only reach it there after it sent them a packet. Endpoints behind NATs with the same public IP address
+    /// are on the same LAN and can reach each other directly.
+    ///
+    /// # Panics
+    /// When either address is already in use.
+    pub fn add_endpoint_behind_nat(
+        &self,
+        address: Address,
+        public: Address,
+    ) -> Arc<SimulatedEndpoint> {
+        self.try_add_endpoint(address, Some(public)).unwrap()
+    }
+
+    /// Adds an endpoint, failing when one of its addresses is already in use
+    pub fn try_add_endpoint(
+        &self,
+        address: Address,
+        public: Option<Address>,
+    ) -> Result<Arc<SimulatedEndpoint>, Ipv8Error> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?;
+        let addresses: Vec<Address> = Some(address).into_iter().chain(public).collect();
+        if let Some(address) = addresses
+            .iter()
+            .find(|address| state.routes.contains_key(address))
+        {
+            return Err(Ipv8Error::AddressInUse { address: *address });
+        }
+
+        let index = state.nodes.len();
+        let endpoint = Arc::new(SimulatedEndpoint {
+            network: Arc::clone(&self.shared),
+            index,
+            address,
+            listeners: RwLock::new(vec![]),
+            open: AtomicBool::new(false),
+            task_manager: Arc::new(TaskManager::with_clock(self.clock())),
+        });
+        state.nodes.push(Node {
+            address,
+            nat: public.map(|public| Nat {
+                public,
+                punched: HashSet::new(),
+            }),
+            endpoint: Arc::clone(&endpoint),
+        });
+        for address in addresses {
+            state.routes.insert(address, index);
+        }
+        Ok(endpoint)
+    }
+
+    /// Moves the virtual clock forward, delivering all packets and running all tasks which are due in the meantime.
+    /// Packets sent while doing so are delivered as well when they arrive in time.
+    pub fn advance(&self, duration: Duration) -> Result<(), Ipv8Error> {
+        let clock = &self.shared.clock;
+        let target = clock.elapsed() + duration;
+
+        loop {
+            let (next_packet, endpoints) = {
+                let state = self
+                    .shared
+                    .state
+                    .lock()
+                    .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?;
+                let next_packet = state.in_flight.peek().map(|packet| packet.0.arrival);
+                let endpoints: Vec<Arc<SimulatedEndpoint>> = state
+                    .nodes
+                    .iter()
+                    .map(|node| Arc::clone(&node.endpoint))
+                    .filter(|endpoint| endpoint.is_open())
+                    .collect();
+                (next_packet, endpoints)
+            };
+
+            let mut next_task = None;
+            for endpoint in &endpoints {
+                if let Some(until) = endpoint.task_manager.time_until_next_task()? {
+                    let at = clock.elapsed() + until;
+                    next_task = Some(next_task.map_or(at, |next: Duration| next.min(at)));
+                }
+            }
+
+            let next = match (next_packet, next_task) {
+                (Some(packet), Some(task)) => packet.min(task),
+                (packet, task) => match packet.or(task) {
+                    Some(next) => next,
+                    None => break,
+                },
+            };
+            if next > target {
+                break;
+            }
+            clock.advance_to(next);
+
+            self.deliver_due()?;
+            for endpoint in &endpoints {
+                endpoint.task_manager.run_pending_tasks()?;
+            }
+        }
+
+        clock.advance_to(target);
+        Ok(())
+    }
+
+    /// Delivers all packets which arrived by now
+    fn deliver_due(&self) -> Result<(), Ipv8Error> {
+        let now = self.shared.clock.elapsed();
+        loop {
+            let (packet, endpoint) = {
+                let mut state = self
+                    .shared
+                    .state
+                    .lock()
+                    .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?;
+                match state.in_flight.peek() {
+                    Some(packet) if packet.0.arrival <= now => {}
+                    _ => return Ok(()),
+                }
+                let packet = match state.in_flight.pop() {
+                    Some(Reverse(packet)) => packet,
+                    None => return Ok(()),
+                };
+                let endpoint = Arc::clone(&state.nodes[packet.to].endpoint);
+                if endpoint.is_open() {
+                    state.delivered += 1;
+                } else {
+                    state.dropped += 1;
+                    continue;
+                }
+                (packet, endpoint)
+            };
+
+            // Delivered without holding the lock, so listeners can send packets themselves
+            endpoint.deliver(packet.packet, packet.from)?;
+        }
+    }
+
+    /// Returns the number of packets handed to an endpoint so far
+    pub fn delivered_packets(&self) -> Result<usize, Ipv8Error> {
+        Ok(self
+            .shared
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?
+            .delivered)
+    }
+
+    /// Returns the number of packets which were lost, blocked by a NAT or sent to an address nobody is at
+    pub fn dropped_packets(&self) -> Result<usize, Ipv8Error> {
+        Ok(self
+            .shared
+            .state
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?
+            .dropped)
+    }
+}
+
+/// An [Endpoint] on a [SimulatedNetwork]. Created with [SimulatedNetwork::add_endpoint].
+///
+/// Its [TaskManager] runs on the virtual clock of the network and should be passed to
+/// [IPv8::with_endpoint](crate::IPv8::with_endpoint) together with the endpoint.
+pub struct SimulatedEndpoint {
+    /// The network this endpoint is on
+    network: Arc<Shared>,
+    /// The index of this endpoint in the nodes of the network
+    index: usize,
+    /// The address of this endpoint
+    address: Address,
+    /// Notified of every packet delivered to this endpoint
+    listeners: RwLock<Vec<Box<dyn Receiver + Send + Sync>>>,
+    /// True between [open](Endpoint::open) and [close](Endpoint::close)
+    open: AtomicBool,
+    /// The tasks run by the network while this endpoint is open
+    task_manager: Arc<TaskManager>,
+}
+
+impl SimulatedEndpoint {
+    /// Returns the [TaskManager] whose tasks the network runs while this endpoint is open
+    pub fn task_manager(&self) -> Arc<TaskManager> {
+        Arc::clone(&self.task_manager)
+    }
+
+    /// Hands a packet to all listeners
+    fn deliver(&self, packet: Packet, from: Address) -> Result<(), Ipv8Error> {
+        for listener in self
+            .listeners
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?
+            .iter()
+        {
+            listener.on_receive(packet.clone(), from);
+        }
+        Ok(())
+    }
+}
+
+impl Endpoint for SimulatedEndpoint {
+    /// Puts the packet on the network, it arrives when the network is [advanced](SimulatedNetwork::advance).
+    /// Always returns the size of the packet, even when it is going to be lost.
+    fn send(&self, address: &Address, packet: Packet) -> Result<usize, Box<dyn Error>> {
+        let size = packet.raw().len();
+        self.network.send(self.index, address, packet)?;
+        Ok(size)
+    }
+
+    fn add_listener(
+        &self,
+        listener: Box<dyn Receiver + Send + Sync>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.listeners
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the simulated network")))?
+            .push(listener);
+        Ok(())
+    }
+
+    fn open(&self) -> Result<(), Box<dyn Error>> {
+        if self.open.swap(true, AtomicOrdering::SeqCst) {
+            return Err(Box::new(Ipv8Error::EndpointOpened));
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), Box<dyn Error>> {
+        if !self.open.swap(false, AtomicOrdering::SeqCst) {
+            return Err(Box::new(Ipv8Error::EndpointClosed));
+        }
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(AtomicOrdering::SeqCst)
+    }
+
+    fn local_address(&self) -> Result<Address, Box<dyn Error>> {
+        Ok(self.address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::community::discovery::DISCOVERY_MID;
+    use crate::configuration::Config;
+    use crate::serialization::header::Header;
+    use crate::{IPv8, IPv8Handle};
+
+    /// Records every packet it receives
+    struct Recorder(Arc<Mutex<Vec<(u64, Address)>>>);
+
+    impl Receiver for Recorder {
+        fn on_receive(&self, packet: Packet, address: Address) {
+            let header = packet.start_deserialize().peek_header().unwrap();
+            self.0
+                .lock()
+                .unwrap()
+                .push((header.message_type.unwrap(), address));
+        }
+    }
+
+    fn address(last: u8) -> Address {
+        Address(format!("10.0.0.{}:8000", last).parse().unwrap())
+    }
+
+    fn public(last: u8, port: u16) -> Address {
+        Address(format!("145.0.0.{}:{}", last, port).parse().unwrap())
+    }
+
+    fn packet(message_type: u8) -> Packet {
+        Packet::new(Header::py_ipv8_header([0; 20], message_type)).unwrap()
+    }
+
+    fn recorder(endpoint: &SimulatedEndpoint) -> Arc<Mutex<Vec<(u64, Address)>>> {
+        let received = Arc::new(Mutex::new(vec![]));
+        endpoint
+            .add_listener(Box::new(Recorder(Arc::clone(&received))))
+            .unwrap();
+        endpoint.open().unwrap();
+        received
+    }
+
+    #[test]
+    fn test_latency() {
+        let network = SimulatedNetwork::new(0);
+        let a = network.add_endpoint(address(1));
+        let b = network.add_endpoint(address(2));
+        let received = recorder(&b);
+
+        a.send(&address(2), packet(1)).unwrap();
+        network.advance(Duration::from_millis(9)).unwrap();
+        assert!(received.lock().unwrap().is_empty());
+
+        network.advance(Duration::from_millis(1)).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![(1, address(1))]);
+        assert_eq!(network.clock().elapsed(), Duration::from_millis(10));
+
+        // closed endpoints receive nothing
+        b.close().unwrap();
+        a.send(&address(2), packet(2)).unwrap();
+        network.advance(Duration::from_millis(10)).unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert_eq!(network.dropped_packets().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_loss_and_reordering_are_deterministic() {
+        let run = || {
+            let network = SimulatedNetwork::with_conditions(
+                7,
+                LinkConditions {
+                    latency: Duration::from_millis(10),
+                    jitter: Duration::from_millis(50),
+                    loss: 0.3,
+                },
+            );
+            let a = network.add_endpoint(address(1));
+            let b = network.add_endpoint(address(2));
+            let received = recorder(&b);
+            for message_type in 0..50 {
+                a.send(&address(2), packet(message_type)).unwrap();
+            }
+            network.advance(Duration::from_secs(1)).unwrap();
+            let received = received.lock().unwrap().clone();
+            received
+        };
+
+        let first = run();
+        assert_eq!(first, run());
+        assert!(first.len() < 50);
+        assert!(first.windows(2).any(|pair| pair[0].0 > pair[1].0));
+    }
+
+    #[test]
+    fn test_nat() {
+        let network = SimulatedNetwork::new(0);
+        let public_peer = network.add_endpoint(address(1));
+        let natted = network.add_endpoint_behind_nat(address(2), public(2, 1234));
+        let neighbour = network.add_endpoint_behind_nat(address(3), public(2, 1235));
+        let from_public = recorder(&public_peer);
+        let from_natted = recorder(&natted);
+
+        // unsolicited packets don't pass the NAT
+        public_peer.send(&public(2, 1234), packet(1)).unwrap();
+        public_peer.send(&address(2), packet(2)).unwrap();
+        network.advance(Duration::from_millis(10)).unwrap();
+        assert!(from_natted.lock().unwrap().is_empty());
+
+        // once the natted peer sent a packet, the public peer can answer
+        natted.send(&address(1), packet(3)).unwrap();
+        network.advance(Duration::from_millis(10)).unwrap();
+        assert_eq!(*from_public.lock().unwrap(), vec![(3, public(2, 1234))]);
+        public_peer.send(&public(2, 1234), packet(4)).unwrap();
+        network.advance(Duration::from_millis(10)).unwrap();
+        assert_eq!(*from_natted.lock().unwrap(), vec![(4, address(1))]);
+
+        // peers behind the same NAT reach each other directly
+        neighbour.send(&address(2), packet(5)).unwrap();
+        network.advance(Duration::from_millis(10)).unwrap();
+        assert_eq!(from_natted.lock().unwrap()[1], (5, address(3)));
+    }
+
+    #[test]
+    fn test_tasks_on_virtual_time() {
+        let network = SimulatedNetwork::new(0);
+        let endpoint = network.add_endpoint(address(1));
+        let runs = Arc::new(Mutex::new(vec![]));
+        let task_runs = Arc::clone(&runs);
+        let clock = network.clock();
+        endpoint
+            .task_manager()
+            .register_task(
+                "tick",
+                move || {
+                    task_runs.lock().unwrap().push(clock.elapsed());
+                    Ok(())
+                },
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(1)),
+            )
+            .unwrap();
+
+        // tasks of closed endpoints don't run
+        network.advance(Duration::from_secs(2)).unwrap();
+        assert!(runs.lock().unwrap().is_empty());
+
+        endpoint.open().unwrap();
+        network.advance(Duration::from_secs(3)).unwrap();
+        assert_eq!(
+            *runs.lock().unwrap(),
+            vec![
+                Duration::from_secs(2),
+                Duration::from_secs(3),
+                Duration::from_secs(4),
+                Duration::from_secs(5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ipv8_discovery() {
+        let network = SimulatedNetwork::new(0);
+        let nodes: Vec<(Address, IPv8Handle)> = (1..=5)
+            .map(|last| {
+                let endpoint = network.add_endpoint(address(last));
+                // everyone bootstraps from the first node, which knows nobody
+                let config = Config {
+                    default_hosts: if last == 1 { vec![] } else { vec![address(1)] },
+                    ..Config::default()
+                };
+                let ipv8 =
+                    IPv8::with_endpoint(config, endpoint.clone(), endpoint.task_manager()).unwrap();
+                (address(last), ipv8.start().unwrap())
+            })
+            .collect();
+
+        network.advance(Duration::from_secs(30)).unwrap();
+
+        // every node found every other node, including the ones it was never told about directly
+        for (address, handle) in &nodes {
+            let communities = handle.communities.communities.read().unwrap();
+            let discovery = &communities[&DISCOVERY_MID.to_vec()];
+            for (other, _) in &nodes {
+                if other != address {
+                    assert!(
+                        discovery.known_peer_key(other).is_some(),
+                        "{:?} did not find {:?}",
+                        address,
+                        other
+                    );
+                }
+            }
+        }
+
+        for (_, handle) in nodes {
+            handle.stop().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_address_in_use() {
+        let network = SimulatedNetwork::new(0);
+        network.add_endpoint(address(1));
+        assert!(network.try_add_endpoint(address(1), None).is_err());
+        assert!(network
+            .try_add_endpoint(address(2), Some(address(1)))
+            .is_err());
+    }
+}
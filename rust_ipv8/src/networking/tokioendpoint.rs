@@ -0,0 +1,530 @@
+//! An [Endpoint] running on a [tokio](https://tokio.rs) runtime, for applications which are async already.
+//! Only available with the `tokio` feature.
+//!
+//! The [TokioEndpoint] receives on a task of the runtime instead of on its own thread, and runs the tasks of its
+//! [TaskManager] on tokio timers. Received packets wait in a [ReceiveQueue] for a worker on the blocking threads of the
+//! runtime, which calls the listeners instead of the global rayon pool. Because listeners run within the runtime, a
+//! community can spawn a future for every message it wants to handle asynchronously:
+//!
+//! ```ignore
+//! fn on_receive(&self, header: Header, deserializer: PacketDeserializer, address: Address) -> Result<(), Box<dyn Error>> {
+//!     let endpoint = Arc::clone(&self.endpoint);
+//!     let reply = self.create_reply(deserializer)?;
+//!     tokio::spawn(async move { endpoint.send_async(&address, reply).await });
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Handlers registered with [register_async](crate::community::handlers::MessageHandlers::register_async) do this for
+//! a community, and [register_async_task](TaskManager::register_async_task) does it for its timers. To wait for the
+//! response to a request, see [RequestCache::add_waiting](crate::requestcache::RequestCache::add_waiting).
+
+use crate::configuration::Config;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::networking::endpoint::Endpoint;
+use crate::networking::queue::ReceiveQueue;
+use crate::networking::{bind_std, Receiver};
+use crate::serialization::Packet;
+use crate::taskmanager::TaskManager;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::net::UdpSocket;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// An [Endpoint] over UDP on a tokio runtime, see the [module documentation](crate::networking::tokioendpoint).
+///
+/// Like the [UdpEndpoint](crate::networking::endpoint::UdpEndpoint) it sends and receives on a single socket bound to
+/// the [sending_address](Config::sending_address). Listeners can only be added before the endpoint is opened, and a
+/// closed endpoint can not be opened again.
+pub struct TokioEndpoint {
+    /// The socket packets are sent and received on
+    socket: Arc<UdpSocket>,
+    /// A non-blocking handle to the same socket, for sending outside of the runtime
+    sync_socket: std::net::UdpSocket,
+    /// True if the socket is an IPv6 socket, which has to send to IPv4 peers on their IPv4-mapped address
+    dual_stack: bool,
+    /// The listeners, until they are moved to the receiving task by [open](Endpoint::open)
+    listeners: Mutex<Option<Vec<Box<dyn Receiver + Send + Sync>>>>,
+    /// The tasks run on the runtime while the endpoint is open
+    task_manager: Arc<TaskManager>,
+    /// The runtime the endpoint was created in, which runs the receiving task
+    runtime: Handle,
+    /// Stops the receiving task
+    shutdown: Arc<Notify>,
+    /// The receiving task once it is started
+    task: Mutex<Option<JoinHandle<()>>>,
+    /// True while the receiving task runs, to [join](Endpoint::join) it without a runtime
+    running: Arc<(Mutex<bool>, Condvar)>,
+    /// True between [open](Endpoint::open) and [close](Endpoint::close)
+    open: AtomicBool,
+    /// The configuration the receiving task is started with
+    config: Config,
+}
+
+impl TokioEndpoint {
+    /// Binds the socket of the configuration. Has to be called from within a tokio runtime, which is the runtime the
+    /// endpoint runs on. The tasks of the given [TaskManager] are run there once the endpoint is opened.
+    pub fn new(config: &Config, task_manager: Arc<TaskManager>) -> Result<Self, Ipv8Error> {
+        let runtime = Handle::try_current().or(Err(Ipv8Error::NoRuntime))?;
+        let socket = bind_std(&config.sending_address)?;
+        socket.set_nonblocking(true)?;
+        let sync_socket = socket.try_clone()?;
+        let socket = {
+            let _guard = runtime.enter();
+            UdpSocket::from_std(socket)?
+        };
+        debug!("Starting, tokio endpoint on: {:?}", socket.local_addr()?);
+
+        Ok(Self {
+            dual_stack: socket.local_addr()?.is_ipv6(),
+            socket: Arc::new(socket),
+            sync_socket,
+            listeners: Mutex::new(Some(vec![])),
+            task_manager,
+            runtime,
+            shutdown: Arc::new(Notify::new()),
+            task: Mutex::new(None),
+            running: Arc::new((Mutex::new(false), Condvar::new())),
+            open: AtomicBool::new(false),
+            config: config.clone(),
+        })
+    }
+
+    /// Sends a [Packet] to the specified address, waiting until the socket can take it.
+    /// Returns the number of bytes sent.
+    pub async fn send_async(&self, address: &Address, packet: Packet) -> Result<usize, Ipv8Error> {
+        let address = self.target(address);
+        Ok(self.socket.send_to(packet.raw(), address.0).await?)
+    }
+
+    /// Waits until the receiving task stopped after the endpoint was closed. Packets which were already received are
+    /// handled by then. This is the async version of [join](Endpoint::join), which does not block.
+    pub async fn closed(&self) -> Result<(), Ipv8Error> {
+        let task = self
+            .task
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?
+            .take();
+        if let Some(task) = task {
+            task.await
+                .or(Err(Ipv8Error::ThreadPanicked("the endpoint")))?;
+        }
+        Ok(())
+    }
+
+    /// The address to send to for the given peer address
+    fn target(&self, address: &Address) -> Address {
+        if self.dual_stack {
+            address.to_ipv6_mapped()
+        } else {
+            *address
+        }
+    }
+}
+
+impl Endpoint for TokioEndpoint {
+    /// Sends the packet without waiting. Fails when the send buffer of the socket is full, use
+    /// [send_async](TokioEndpoint::send_async) to wait until it can.
+    fn send(&self, address: &Address, packet: Packet) -> Result<usize, Box<dyn Error>> {
+        let address = self.target(address);
+        Ok(self.sync_socket.send_to(packet.raw(), address.0)?)
+    }
+
+    fn add_listener(
+        &self,
+        listener: Box<dyn Receiver + Send + Sync>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.listeners
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?
+            .as_mut()
+            .ok_or(Ipv8Error::EndpointOpened)?
+            .push(listener);
+        Ok(())
+    }
+
+    fn open(&self) -> Result<(), Box<dyn Error>> {
+        let listeners = self
+            .listeners
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?
+            .take()
+            .ok_or(Ipv8Error::EndpointOpened)?;
+
+        *self
+            .running
+            .0
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))? = true;
+        let running = Running(Arc::clone(&self.running));
+        let task = self.runtime.spawn(receive(
+            Arc::clone(&self.socket),
+            listeners,
+            Arc::clone(&self.task_manager),
+            Arc::clone(&self.shutdown),
+            self.config.clone(),
+            running,
+        ));
+        *self
+            .task
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))? = Some(task);
+        self.open.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), Box<dyn Error>> {
+        if !self.open.swap(false, Ordering::SeqCst) {
+            return Err(Box::new(Ipv8Error::EndpointClosed));
+        }
+        self.shutdown.notify_one();
+        Ok(())
+    }
+
+    /// Blocks until the receiving task stopped. Use [closed](TokioEndpoint::closed) from async code instead.
+    ///
+    /// On a single threaded runtime the receiving task can't run while this is called from the runtime, blocking would
+    /// never end. No packets are received anymore once the endpoint is closed, so this returns right away there, while
+    /// the packets which were already received may still be handled.
+    fn join(&self) -> Result<(), Box<dyn Error>> {
+        if let Ok(runtime) = Handle::try_current() {
+            if runtime.runtime_flavor() == RuntimeFlavor::CurrentThread {
+                return Ok(());
+            }
+        }
+
+        let (running, stopped) = &*self.running;
+        let mut running = running
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?;
+        while *running {
+            running = stopped
+                .wait(running)
+                .or(Err(Ipv8Error::LockPoisoned("the endpoint")))?;
+        }
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn local_address(&self) -> Result<Address, Box<dyn Error>> {
+        Ok(Address(self.socket.local_addr()?))
+    }
+}
+
+/// Marks the receiving task as stopped when it is dropped, also when the runtime shuts down before it finished
+struct Running(Arc<(Mutex<bool>, Condvar)>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let (running, stopped) = &*self.0;
+        if let Ok(mut running) = running.lock() {
+            *running = false;
+        }
+        stopped.notify_all();
+    }
+}
+
+/// Receives packets and runs tasks until notified to shut down, or until receiving fails. Waits for the packets which
+/// were already received to be handled before it returns.
+async fn receive(
+    socket: Arc<UdpSocket>,
+    listeners: Vec<Box<dyn Receiver + Send + Sync>>,
+    task_manager: Arc<TaskManager>,
+    shutdown: Arc<Notify>,
+    config: Config,
+    _running: Running,
+) {
+    debug!("IPV8 is starting it's tokio listener!");
+    let mut buffer = vec![0; config.buffersize];
+    let pollinterval = config.pollinterval;
+
+    // The listeners are handed the packets on a blocking thread, so they never hold up the runtime
+    let queue = Arc::new(ReceiveQueue::new(config.queuesize, config.drop_policy));
+    let worker = {
+        let queue = Arc::clone(&queue);
+        let batchsize = config.batchsize;
+        tokio::task::spawn_blocking(move || dispatch(&queue, &listeners, batchsize))
+    };
+
+    loop {
+        let next_task = match task_manager.time_until_next_task() {
+            Ok(next_task) => next_task,
+            Err(e) => {
+                error!("the tokio listener crashed: {}", e);
+                break;
+            }
+        };
+        // Wake up in time for the next task
+        let timeout = match (pollinterval, next_task) {
+            (Some(interval), Some(task)) => Some(interval.min(task)),
+            (interval, task) => interval.or(task),
+        };
+        let timer = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            // Once closed, no more packets are handled
+            biased;
+            _ = shutdown.notified() => break,
+            received = socket.recv_from(&mut buffer) => match received {
+                Ok((size, address)) => {
+                    // IPv4 peers show up with an IPv4-mapped address on a dual-stack socket
+                    let address = Address(address).normalized();
+                    if let Err(e) = queue.push(Packet(buffer[..size].to_vec()), address) {
+                        error!("the tokio listener crashed: {}", e);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("the tokio listener crashed: {}", e);
+                    break;
+                }
+            },
+            _ = timer => {}
+            _ = task_manager.task_registered() => {}
+        }
+
+        if let Err(e) = task_manager.run_pending_tasks() {
+            error!("the tokio listener crashed: {}", e);
+            break;
+        }
+    }
+
+    if let Err(e) = queue.close() {
+        error!("the tokio listener crashed: {}", e);
+    }
+    if worker.await.is_err() {
+        error!("the dispatching thread crashed");
+    }
+
+    debug!("IPV8 stopped it's tokio listener");
+}
+
+/// Hands the packets in the queue to the listeners, in the order they were received, until the queue is closed and empty
+fn dispatch(queue: &ReceiveQueue, listeners: &[Box<dyn Receiver + Send + Sync>], batchsize: usize) {
+    loop {
+        let packets = match queue.pop_batch(batchsize) {
+            Ok(packets) => packets,
+            Err(e) => {
+                error!("the dispatching thread crashed: {}", e);
+                return;
+            }
+        };
+        if packets.is_empty() {
+            return;
+        }
+
+        for (packet, address) in packets {
+            for listener in listeners {
+                listener.on_receive(packet.clone(), address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::test_helper::localhost;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Receiver for Counter {
+        fn on_receive(&self, _packet: Packet, _address: Address) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Blocks in every call until it is released, to check that the runtime keeps running meanwhile
+    struct Blocking {
+        started: Arc<AtomicUsize>,
+        release: Mutex<std::sync::mpsc::Receiver<()>>,
+        released: Arc<AtomicUsize>,
+    }
+
+    impl Receiver for Blocking {
+        fn on_receive(&self, _packet: Packet, _address: Address) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let release = self.release.lock().unwrap();
+            if release.recv_timeout(Duration::from_secs(1)).is_ok() {
+                self.released.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn tokio_endpoint() -> TokioEndpoint {
+        let config = Config {
+            sending_address: localhost(),
+            ..Config::default()
+        };
+        TokioEndpoint::new(&config, Arc::new(TaskManager::new())).unwrap()
+    }
+
+    /// Polls the condition every millisecond without blocking the runtime, for up to a second
+    async fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+        for _ in 0..1000 {
+            if condition() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        condition()
+    }
+
+    #[test]
+    fn test_no_runtime() {
+        let config = Config {
+            sending_address: localhost(),
+            ..Config::default()
+        };
+        assert!(TokioEndpoint::new(&config, Arc::new(TaskManager::new())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_receive() {
+        let endpoint = tokio_endpoint();
+        let other = tokio_endpoint();
+        let count = Arc::new(AtomicUsize::new(0));
+        endpoint
+            .add_listener(Box::new(Counter(Arc::clone(&count))))
+            .unwrap();
+        endpoint.open().unwrap();
+        assert!(endpoint.open().is_err());
+
+        let address = endpoint.local_address().unwrap();
+        other
+            .send(&address, Packet::new(create_test_header!()).unwrap())
+            .unwrap();
+        other
+            .send_async(&address, Packet::new(create_test_header!()).unwrap())
+            .await
+            .unwrap();
+        assert!(wait_until(|| count.load(Ordering::SeqCst) == 2).await);
+
+        endpoint.close().unwrap();
+        endpoint.closed().await.unwrap();
+        assert!(!endpoint.is_open());
+        assert!(endpoint.close().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blocking_listener() {
+        let endpoint = tokio_endpoint();
+        let other = tokio_endpoint();
+        let started = Arc::new(AtomicUsize::new(0));
+        let released = Arc::new(AtomicUsize::new(0));
+        let (release, wait) = std::sync::mpsc::channel();
+        endpoint
+            .add_listener(Box::new(Blocking {
+                started: Arc::clone(&started),
+                release: Mutex::new(wait),
+                released: Arc::clone(&released),
+            }))
+            .unwrap();
+        endpoint.open().unwrap();
+
+        other
+            .send_async(
+                &endpoint.local_address().unwrap(),
+                Packet::new(create_test_header!()).unwrap(),
+            )
+            .await
+            .unwrap();
+        // the single threaded runtime of the test keeps running while the listener blocks
+        assert!(wait_until(|| started.load(Ordering::SeqCst) == 1).await);
+        release.send(()).unwrap();
+
+        endpoint.close().unwrap();
+        endpoint.closed().await.unwrap();
+        assert_eq!(released.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_join() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let endpoint = runtime.block_on(async { tokio_endpoint() });
+        // returns right away when the endpoint never received
+        endpoint.join().unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        endpoint
+            .add_listener(Box::new(Counter(Arc::clone(&count))))
+            .unwrap();
+        endpoint.open().unwrap();
+
+        // the runtime runs the receiving task on another thread, until the test is done
+        let (done, wait) = tokio::sync::oneshot::channel::<()>();
+        let driver = std::thread::spawn(move || {
+            runtime.block_on(async {
+                let _ = wait.await;
+            })
+        });
+
+        endpoint.close().unwrap();
+        endpoint.join().unwrap();
+        assert!(!*endpoint.running.0.lock().unwrap());
+
+        done.send(()).unwrap();
+        driver.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_registered_task_wakes_up_receiver() {
+        let endpoint = tokio_endpoint();
+        endpoint.open().unwrap();
+        // the receiver waits without a timeout, until the task is registered
+        tokio::task::yield_now().await;
+
+        let sleep = endpoint
+            .task_manager
+            .sleep(Duration::from_millis(10))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), sleep)
+            .await
+            .unwrap()
+            .unwrap();
+
+        endpoint.close().unwrap();
+        endpoint.closed().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tasks_on_tokio_timers() {
+        let endpoint = tokio_endpoint();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let task_runs = Arc::clone(&runs);
+        endpoint
+            .task_manager
+            .register_task(
+                "count",
+                move || {
+                    task_runs.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                },
+                Some(Duration::from_millis(10)),
+                Some(Duration::from_millis(10)),
+            )
+            .unwrap();
+
+        endpoint.open().unwrap();
+        assert!(wait_until(|| runs.load(Ordering::SeqCst) >= 3).await);
+        endpoint.close().unwrap();
+        endpoint.closed().await.unwrap();
+    }
+}
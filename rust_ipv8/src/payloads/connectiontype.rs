@@ -3,7 +3,8 @@
 #[derive(Debug, PartialEq)]
 /// Sent as a member of a number of payloads like the [IntroductionRequestPayload](crate::payloads::introductionrequestpayload::IntroductionRequestPayload), in their flags fields.
 ///
-/// **_NOTE_**: with IPv6 support, another connection type might be added to signify this.
+/// py-ipv8 only uses three of the four combinations of bits. The last one is used to signal [IPV6](ConnectionType::IPV6),
+/// which py-ipv8 decodes as unknown.
 pub enum ConnectionType {
     /// A Public connnection meaning: Easily reachable and no NAT Puncturing needed.
     PUBLIC,
@@ -11,6 +12,9 @@ pub enum ConnectionType {
     SYMMETRICNAT,
     /// Fallback if connectiontype could not be determined or is not known.
     UNKNOWN,
+    /// The sender is reachable over IPv6. Its addresses could not be put in the IPv4 address fields of the payload,
+    /// so they have to be taken from the packet itself or from the extra bytes of the payload.
+    IPV6,
 }
 
 impl ConnectionType {
@@ -21,6 +25,7 @@ impl ConnectionType {
             ConnectionType::UNKNOWN => (false, false),
             ConnectionType::PUBLIC => (true, false),
             ConnectionType::SYMMETRICNAT => (true, true),
+            ConnectionType::IPV6 => (false, true),
         }
     }
 
@@ -29,7 +34,7 @@ impl ConnectionType {
         match bits {
             (false, false) => ConnectionType::UNKNOWN,
             (true, false) => ConnectionType::PUBLIC,
-            (false, true) => ConnectionType::UNKNOWN, // not in py-ipv8 but this case is not specified and thus unknown
+            (false, true) => ConnectionType::IPV6, // not in py-ipv8, which decodes this as unknown
             (true, true) => ConnectionType::SYMMETRICNAT,
         }
     }
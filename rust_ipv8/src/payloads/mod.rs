@@ -6,6 +6,7 @@ pub mod binmemberauthenticationpayload;
 pub mod connectiontype;
 pub mod introductionrequestpayload;
 pub mod introductionresponsepayload;
+pub mod pingpayload;
 pub mod puncturepayload;
 pub mod puncturerequestpayload;
 pub mod timedistributionpayload;
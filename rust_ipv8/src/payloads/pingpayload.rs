@@ -0,0 +1,46 @@
+//! Payload of the ping and pong messages of the DiscoveryCommunity, used to check if a peer is still alive.
+
+use crate::payloads::Ipv8Payload;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Payload sent as a ping, and sent back unchanged as a pong.
+pub struct PingPayload {
+    /// is a number chosen by the sender of the ping. The pong contains the same number
+    /// so the sender can match it to the ping.
+    pub identifier: u16,
+}
+
+impl Ipv8Payload for PingPayload {
+    // doesnt have anything but needed for the default implementation (as of right now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::Packet;
+
+    #[test]
+    fn integration_test_creation() {
+        let i = PingPayload { identifier: 42 };
+
+        let mut packet = Packet::new(create_test_header!()).unwrap();
+        packet.add(&i).unwrap();
+
+        assert_eq!(
+            packet,
+            Packet(vec![
+                0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 42, 0, 42,
+            ])
+        );
+        assert_eq!(
+            i,
+            packet
+                .start_deserialize()
+                .skip_header()
+                .unwrap()
+                .next_payload()
+                .unwrap()
+        );
+    }
+}
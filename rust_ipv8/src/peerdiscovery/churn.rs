@@ -0,0 +1,219 @@
+//! Removes peers which stopped responding from the [Network](crate::peerdiscovery::network::Network) of an [Overlay].
+//!
+//! Ported from py-ipv8's [RandomChurn](https://github.com/Tribler/py-ipv8/blob/master/ipv8/peerdiscovery/churn.py).
+
+use crate::community::peer::Peer;
+use crate::configuration::Config;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::peerdiscovery::discovery::DiscoveryStrategy;
+use crate::peerdiscovery::Overlay;
+use rand::seq::SliceRandom;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Every step, pings a random sample of the verified peers which have been silent for a while.
+/// Peers which stay silent after being pinged are removed once they have been silent for the drop time.
+///
+/// This strategy should always run, so it is added to the [Walker](crate::peerdiscovery::Walker) with a target
+/// of `usize::MAX` peers.
+pub struct RandomChurn {
+    /// The overlay to remove dead peers from
+    overlay: Arc<dyn Overlay>,
+    /// The number of peers checked every step
+    sample_size: usize,
+    /// The minimum time between two pings to the same peer
+    ping_interval: Duration,
+    /// Peers which have been silent for this long are pinged
+    inactive_time: Duration,
+    /// Pinged peers which have been silent for this long are removed
+    drop_time: Duration,
+    /// The time we last pinged each address
+    pinged: HashMap<Address, Instant>,
+}
+
+impl RandomChurn {
+    /// Creates a random churn with the same default parameters as py-ipv8
+    pub fn new(overlay: Arc<dyn Overlay>) -> Self {
+        Self::from_config(overlay, &Config::default())
+    }
+
+    /// Creates a random churn with the churn parameters of the configuration
+    pub fn from_config(overlay: Arc<dyn Overlay>, config: &Config) -> Self {
+        Self::with_parameters(
+            overlay,
+            config.churn_sample_size,
+            config.churn_ping_interval,
+            config.churn_inactive_time,
+            config.churn_drop_time,
+        )
+    }
+
+    /// Creates a random churn with custom parameters
+    pub fn with_parameters(
+        overlay: Arc<dyn Overlay>,
+        sample_size: usize,
+        ping_interval: Duration,
+        inactive_time: Duration,
+        drop_time: Duration,
+    ) -> Self {
+        Self {
+            overlay,
+            sample_size,
+            ping_interval,
+            inactive_time,
+            drop_time,
+            pinged: HashMap::new(),
+        }
+    }
+}
+
+impl DiscoveryStrategy for RandomChurn {
+    fn overlay(&self) -> &dyn Overlay {
+        &*self.overlay
+    }
+
+    fn take_step(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.overlay.now()?;
+
+        let sample: Vec<Peer> = {
+            let network = self
+                .overlay
+                .network()
+                .read()
+                .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+            network
+                .get_verified_peers()
+                .choose_multiple(&mut rand::thread_rng(), self.sample_size)
+                .map(|peer| (*peer).clone())
+                .collect()
+        };
+
+        for peer in sample {
+            let address = *peer.address();
+            let silent = peer.time_since_last_response(now);
+
+            if silent > self.drop_time && self.pinged.contains_key(&address) {
+                debug!("Removing unresponsive peer {:?}", address);
+                self.overlay.remove_peer(&peer)?;
+                self.pinged.remove(&address);
+            } else if silent > self.inactive_time || peer.pings().is_empty() {
+                if let Some(sent) = self.pinged.get(&address).cloned() {
+                    if now.duration_since(sent) > self.ping_interval {
+                        self.pinged.remove(&address);
+                        if peer.last_response() < sent {
+                            let mut network = self
+                                .overlay
+                                .network()
+                                .write()
+                                .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+                            if let Some(peer) = network.get_verified_by_address_mut(&address) {
+                                peer.add_failure();
+                            }
+                        }
+                    }
+                }
+
+                if let Entry::Vacant(entry) = self.pinged.entry(address) {
+                    entry.insert(now);
+                    self.overlay.send_ping(&address)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::simulation::VirtualClock;
+    use crate::peerdiscovery::network::Network;
+    use crate::peerdiscovery::test_helper::{address, TestOverlay};
+    use crate::taskmanager::Clock;
+    use std::sync::RwLock;
+
+    #[test]
+    fn test_ping_new_peer() {
+        let overlay = Arc::new(TestOverlay::default());
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .add_verified_peer(Peer::new([1; 32], address(1), true, Instant::now()));
+
+        let mut churn = RandomChurn::new(overlay.clone());
+        churn.take_step().unwrap();
+        // pinged once to learn the round trip time, but not again within the ping interval
+        churn.take_step().unwrap();
+
+        assert_eq!(*overlay.pings.lock().unwrap(), vec![address(1)]);
+        assert_eq!(overlay.get_peers().unwrap(), vec![address(1)]);
+    }
+
+    #[test]
+    fn test_active_peer_not_pinged() {
+        let overlay = Arc::new(TestOverlay::default());
+        let mut peer = Peer::new([1; 32], address(1), true, Instant::now());
+        peer.add_ping(Duration::from_millis(10));
+        overlay.network.write().unwrap().add_verified_peer(peer);
+
+        let mut churn = RandomChurn::new(overlay.clone());
+        churn.take_step().unwrap();
+
+        assert!(overlay.pings.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_drop_silent_peer() {
+        let clock = Arc::new(VirtualClock::new());
+        let overlay = Arc::new(TestOverlay {
+            network: RwLock::new(Network::with_clock(clock.clone())),
+            ..TestOverlay::default()
+        });
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .add_verified_peer(Peer::new([1; 32], address(1), true, clock.now()));
+
+        let mut churn = RandomChurn::with_parameters(
+            overlay.clone(),
+            8,
+            Duration::from_millis(0),
+            Duration::from_millis(0),
+            Duration::from_millis(50),
+        );
+
+        // pinged after the peer last responded
+        clock.advance(Duration::from_millis(1));
+        churn.take_step().unwrap();
+        clock.advance(Duration::from_millis(5));
+        churn.take_step().unwrap();
+
+        // the peer did not respond to the first ping, it is pinged again but not dropped yet
+        assert_eq!(overlay.pings.lock().unwrap().len(), 2);
+        assert_eq!(
+            overlay
+                .network
+                .read()
+                .unwrap()
+                .get_verified_by_address(&address(1))
+                .unwrap()
+                .failures(),
+            1
+        );
+
+        clock.advance(Duration::from_millis(40));
+        churn.take_step().unwrap();
+        assert_eq!(overlay.get_peers().unwrap(), vec![address(1)]);
+
+        clock.advance(Duration::from_millis(10));
+        churn.take_step().unwrap();
+        assert!(overlay.get_peers().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+//! Strategies to discover new peers for an [Overlay].
+//!
+//! Ported from py-ipv8's [discovery strategies](https://github.com/Tribler/py-ipv8/blob/master/ipv8/peerdiscovery/discovery.py).
+
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::peerdiscovery::Overlay;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A strategy to find new peers for an [Overlay]. Strategies are driven by the [Walker](crate::peerdiscovery::Walker).
+pub trait DiscoveryStrategy: Send {
+    /// The overlay this strategy discovers peers for
+    fn overlay(&self) -> &dyn Overlay;
+
+    /// Called periodically by the [Walker](crate::peerdiscovery::Walker) to try and find a new peer.
+    fn take_step(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+/// Walks to random introduced addresses. Occasionally asks a known peer for a new introduction instead,
+/// so we don't get stuck on a set of unreachable addresses.
+pub struct RandomWalk {
+    /// The overlay to walk
+    overlay: Arc<dyn Overlay>,
+    /// Introduced addresses which don't respond within this time are removed
+    node_timeout: Duration,
+    /// The maximum number of outstanding introduction requests
+    window_size: usize,
+    /// The chance (out of 255) to ask a known peer for an introduction instead of walking to a new address
+    reset_chance: u8,
+    /// The time we walked to each address that has not responded yet
+    intro_timeouts: HashMap<Address, Instant>,
+}
+
+impl RandomWalk {
+    /// Creates a random walk with the same default parameters as py-ipv8
+    pub fn new(overlay: Arc<dyn Overlay>) -> Self {
+        Self::with_parameters(overlay, Duration::from_secs(3), 5, 50)
+    }
+
+    /// Creates a random walk with custom parameters. A `window_size` of zero means there is no limit on the number of
+    /// outstanding introduction requests.
+    pub fn with_parameters(
+        overlay: Arc<dyn Overlay>,
+        node_timeout: Duration,
+        window_size: usize,
+        reset_chance: u8,
+    ) -> Self {
+        Self {
+            overlay,
+            node_timeout,
+            window_size,
+            reset_chance,
+            intro_timeouts: HashMap::new(),
+        }
+    }
+}
+
+impl DiscoveryStrategy for RandomWalk {
+    fn overlay(&self) -> &dyn Overlay {
+        &*self.overlay
+    }
+
+    fn take_step(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.overlay.now()?;
+
+        // Sanitize unreachable nodes. Nodes which did respond are verified and no longer walkable.
+        let walkable = self.overlay.get_walkable_addresses()?;
+        let node_timeout = self.node_timeout;
+        let timed_out: Vec<Address> = self
+            .intro_timeouts
+            .iter()
+            .filter(|(_, sent)| now.duration_since(**sent) > node_timeout)
+            .map(|(address, _)| *address)
+            .collect();
+        {
+            let mut network = self
+                .overlay
+                .network()
+                .write()
+                .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+            for address in timed_out {
+                if walkable.contains(&address) {
+                    network.remove_by_address(&address);
+                }
+                self.intro_timeouts.remove(&address);
+            }
+        }
+
+        // Slow down the walk
+        if self.window_size > 0 && self.intro_timeouts.len() >= self.window_size {
+            return Ok(());
+        }
+
+        let available: Vec<Address> = self
+            .overlay
+            .get_walkable_addresses()?
+            .into_iter()
+            .filter(|address| !self.intro_timeouts.contains_key(address))
+            .collect();
+
+        let mut rng = rand::thread_rng();
+        // We can get stuck in an infinite loop of unreachable peers if we never contact a known peer again
+        if !available.is_empty() && (self.reset_chance == 0 || rng.gen::<u8>() >= self.reset_chance)
+        {
+            let address = *available.choose(&mut rng).expect("available is not empty");
+            self.overlay.walk_to(&address)?;
+            self.intro_timeouts.insert(address, now);
+            Ok(())
+        } else {
+            self.overlay.get_new_introduction(None)
+        }
+    }
+}
+
+/// Walks edges of a fixed length, starting from the peers in our direct neighborhood.
+///
+/// Every peer in the neighborhood is the root of an edge. An edge grows by asking its last peer for an introduction
+/// and waiting for the introduced peer to respond. Walking edges instead of random peers makes it harder for an
+/// attacker to fill our peer table, as it has to be present at every hop of an edge.
+pub struct EdgeWalk {
+    /// The overlay to walk
+    overlay: Arc<dyn Overlay>,
+    /// The number of peers in a complete edge, including its root
+    edge_length: usize,
+    /// The number of roots to build edges from
+    neighborhood_size: usize,
+    /// Edges which don't grow within this time are discarded
+    edge_timeout: Duration,
+    /// The last edge which reached the edge length, for every root
+    complete_edges: Vec<Vec<Address>>,
+    /// The last time each edge (by its root) grew
+    last_edge_responses: HashMap<Address, Instant>,
+    /// The roots of the edges
+    neighborhood: Vec<Address>,
+    /// The edges which are still growing, by their root
+    under_construction: HashMap<Address, Vec<Address>>,
+}
+
+impl EdgeWalk {
+    /// Creates an edge walk with the same default parameters as py-ipv8
+    pub fn new(overlay: Arc<dyn Overlay>) -> Self {
+        Self::with_parameters(overlay, 4, 6, Duration::from_secs(3))
+    }
+
+    /// Creates an edge walk with custom parameters
+    pub fn with_parameters(
+        overlay: Arc<dyn Overlay>,
+        edge_length: usize,
+        neighborhood_size: usize,
+        edge_timeout: Duration,
+    ) -> Self {
+        Self {
+            overlay,
+            edge_length,
+            neighborhood_size,
+            edge_timeout,
+            complete_edges: vec![],
+            last_edge_responses: HashMap::new(),
+            neighborhood: vec![],
+            under_construction: HashMap::new(),
+        }
+    }
+
+    /// Returns the last edge which reached the full edge length, for every root which completed one
+    pub fn complete_edges(&self) -> &[Vec<Address>] {
+        &self.complete_edges
+    }
+
+    /// Tries to add a hop to the given edge. Returns true if the edge grew.
+    fn grow_edge(&self, edge: &mut Vec<Address>) -> Result<bool, Box<dyn Error>> {
+        let last = *edge.last().expect("an edge always contains its root");
+
+        let (introductions, verified) = {
+            let network = self
+                .overlay
+                .network()
+                .read()
+                .or(Err(Ipv8Error::LockPoisoned("the peer table")))?;
+            let introductions: Vec<Address> = network
+                .get_introductions_from(&last)
+                .into_iter()
+                .filter(|address| !edge.contains(address))
+                .collect();
+            let verified: Vec<Address> = introductions
+                .iter()
+                .filter(|address| network.get_verified_by_address(address).is_some())
+                .cloned()
+                .collect();
+            (introductions, verified)
+        };
+
+        let mut rng = rand::thread_rng();
+        if let Some(next) = verified.choose(&mut rng) {
+            edge.push(*next);
+            Ok(true)
+        } else if let Some(next) = introductions.choose(&mut rng) {
+            self.overlay.walk_to(next)?;
+            Ok(false)
+        } else {
+            self.overlay.get_new_introduction(Some(&last))?;
+            Ok(false)
+        }
+    }
+}
+
+impl DiscoveryStrategy for EdgeWalk {
+    fn overlay(&self) -> &dyn Overlay {
+        &*self.overlay
+    }
+
+    fn take_step(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.neighborhood.len() < self.neighborhood_size {
+            // Wait for our immediate neighborhood to be discovered
+            self.neighborhood = self.overlay.get_peers()?;
+            self.neighborhood.truncate(self.neighborhood_size);
+            if self.neighborhood.len() < self.neighborhood_size {
+                return self.overlay.get_new_introduction(None);
+            }
+        }
+
+        let now = self.overlay.now()?;
+
+        // Discard edges which stopped growing
+        let edge_timeout = self.edge_timeout;
+        let last_edge_responses = &self.last_edge_responses;
+        self.under_construction
+            .retain(|root, _| match last_edge_responses.get(root) {
+                Some(last) => now.duration_since(*last) <= edge_timeout,
+                None => false,
+            });
+
+        // Start new edges from every root which is not building one
+        for root in &self.neighborhood {
+            if !self.under_construction.contains_key(root) {
+                self.under_construction.insert(*root, vec![*root]);
+                self.last_edge_responses.insert(*root, now);
+            }
+        }
+
+        // Grow the edges
+        let roots: Vec<Address> = self.under_construction.keys().cloned().collect();
+        for root in roots {
+            let mut edge = self.under_construction.remove(&root).unwrap_or_default();
+            if self.grow_edge(&mut edge)? {
+                self.last_edge_responses.insert(root, now);
+            }
+
+            if edge.len() >= self.edge_length {
+                // A new edge from the same root replaces the old one, so the edges don't pile up
+                match self
+                    .complete_edges
+                    .iter_mut()
+                    .find(|complete| complete[0] == root)
+                {
+                    Some(complete) => *complete = edge,
+                    None => self.complete_edges.push(edge),
+                }
+            } else {
+                self.under_construction.insert(root, edge);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::community::peer::Peer;
+    use crate::networking::simulation::VirtualClock;
+    use crate::peerdiscovery::network::Network;
+    use crate::peerdiscovery::test_helper::{address, TestOverlay};
+    use std::sync::RwLock;
+
+    #[test]
+    fn test_random_walk_bootstrap() {
+        let overlay = Arc::new(TestOverlay {
+            bootstrap: vec![address(1)],
+            ..TestOverlay::default()
+        });
+        let mut walk = RandomWalk::new(overlay.clone());
+        walk.take_step().unwrap();
+
+        assert_eq!(*overlay.walks.lock().unwrap(), vec![address(1)]);
+    }
+
+    #[test]
+    fn test_random_walk_introduced() {
+        let overlay = Arc::new(TestOverlay::default());
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .discover_address(address(1), address(2), None);
+
+        let mut walk = RandomWalk::with_parameters(overlay.clone(), Duration::from_secs(3), 5, 0);
+        walk.take_step().unwrap();
+        assert_eq!(*overlay.walks.lock().unwrap(), vec![address(2)]);
+    }
+
+    #[test]
+    fn test_random_walk_timeout() {
+        let clock = Arc::new(VirtualClock::new());
+        let overlay = Arc::new(TestOverlay {
+            network: RwLock::new(Network::with_clock(clock.clone())),
+            ..TestOverlay::default()
+        });
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .discover_address(address(1), address(2), None);
+
+        let mut walk = RandomWalk::with_parameters(overlay.clone(), Duration::from_secs(3), 1, 0);
+        walk.take_step().unwrap();
+        clock.advance(Duration::from_secs(3));
+        walk.take_step().unwrap();
+        assert_eq!(overlay.get_walkable_addresses().unwrap(), vec![address(2)]);
+
+        clock.advance(Duration::from_millis(1));
+        walk.take_step().unwrap();
+
+        // the address did not respond in time, so it's forgotten
+        assert!(overlay.get_walkable_addresses().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_random_walk_window() {
+        let overlay = Arc::new(TestOverlay::default());
+        {
+            let mut network = overlay.network.write().unwrap();
+            network.discover_address(address(1), address(2), None);
+            network.discover_address(address(1), address(3), None);
+        }
+
+        let mut walk = RandomWalk::with_parameters(overlay.clone(), Duration::from_secs(3), 1, 0);
+        walk.take_step().unwrap();
+        walk.take_step().unwrap();
+
+        // the second step is blocked as one request is still outstanding
+        assert_eq!(overlay.walks.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_edge_walk() {
+        let overlay = Arc::new(TestOverlay::default());
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .add_verified_peer(Peer::new([1; 32], address(1), true, Instant::now()));
+
+        let mut walk = EdgeWalk::with_parameters(overlay.clone(), 3, 1, Duration::from_secs(3));
+
+        // the root has not introduced anyone yet, so it is asked for an introduction
+        walk.take_step().unwrap();
+        assert_eq!(*overlay.walks.lock().unwrap(), vec![address(1)]);
+
+        // the root introduced a new peer, which we walk to
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .discover_address(address(1), address(2), None);
+        walk.take_step().unwrap();
+        assert_eq!(overlay.walks.lock().unwrap().last(), Some(&address(2)));
+
+        // once it responded, the edge grows to it and the next hop is introduced by it
+        {
+            let mut network = overlay.network.write().unwrap();
+            network.add_verified_peer(Peer::new([2; 32], address(2), true, Instant::now()));
+            network.discover_address(address(2), address(3), None);
+            network.add_verified_peer(Peer::new([3; 32], address(3), true, Instant::now()));
+        }
+        walk.take_step().unwrap();
+        walk.take_step().unwrap();
+
+        assert_eq!(
+            walk.complete_edges(),
+            &[vec![address(1), address(2), address(3)]]
+        );
+
+        // the next edge from the same root replaces the completed one
+        for _ in 0..4 {
+            walk.take_step().unwrap();
+        }
+        assert_eq!(walk.complete_edges().len(), 1);
+    }
+}
@@ -0,0 +1,273 @@
+//! This module contains everything needed to find other peers in the network.
+//!
+//! An [Overlay] is a community which keeps a [Network] of peers. [DiscoveryStrategies](discovery::DiscoveryStrategy)
+//! like the [RandomWalk](discovery::RandomWalk) periodically send introduction requests on behalf of an overlay
+//! to grow its [Network]. The [Walker] drives all strategies, like the `on_tick` loop of py-ipv8 does.
+//! It runs as a task of the [TaskManager].
+
+use crate::community::peer::Peer;
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::peerdiscovery::discovery::DiscoveryStrategy;
+use crate::peerdiscovery::network::Network;
+use rand::seq::SliceRandom;
+use std::error::Error;
+use crate::taskmanager::TaskManager;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+pub mod churn;
+pub mod discovery;
+pub mod network;
+
+/// The name of the task the [Walker] registers in the [TaskManager]
+pub const WALKER_TASK: &str = "discovery_walker";
+
+/// An overlay is a community which can be walked by a [DiscoveryStrategy].
+///
+/// Implementors only have to provide their peer table, a way to send introduction requests and a list of bootstrap
+/// addresses. Everything else a [DiscoveryStrategy] needs is derived from those.
+pub trait Overlay: Send + Sync {
+    /// Returns the table of peers this overlay knows about
+    fn network(&self) -> &RwLock<Network>;
+
+    /// Sends an introduction request to the given address
+    fn walk_to(&self, address: &Address) -> Result<(), Box<dyn Error>>;
+
+    /// Sends a ping to the given address, to check if the peer there is still alive
+    fn send_ping(&self, address: &Address) -> Result<(), Box<dyn Error>>;
+
+    /// The addresses to contact when no other peers are known
+    fn bootstrap_addresses(&self) -> Vec<Address>;
+
+    /// Removes a verified peer which stopped responding from the network. Overlays which keep more state per peer
+    /// should forget it here as well.
+    fn remove_peer(&self, peer: &Peer) -> Result<(), Box<dyn Error>> {
+        self.network()
+            .write()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .remove_by_address(peer.address());
+        Ok(())
+    }
+
+    /// Returns the current time on the clock of the network, which strategies time their requests by
+    fn now(&self) -> Result<Instant, Box<dyn Error>> {
+        Ok(self
+            .network()
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .now())
+    }
+
+    /// Sends an introduction request to all bootstrap addresses
+    fn bootstrap(&self) -> Result<(), Box<dyn Error>> {
+        for address in self.bootstrap_addresses() {
+            self.walk_to(&address)?;
+        }
+        Ok(())
+    }
+
+    /// Asks a peer for an introduction to a new peer. If no peer is given, a random verified peer is asked.
+    /// When no peers are known at all, the bootstrap addresses are contacted instead.
+    fn get_new_introduction(&self, from_peer: Option<&Address>) -> Result<(), Box<dyn Error>> {
+        let peer = match from_peer {
+            Some(peer) => Some(*peer),
+            None => self.get_peers()?.choose(&mut rand::thread_rng()).cloned(),
+        };
+
+        match peer {
+            Some(peer) => self.walk_to(&peer),
+            None => self.bootstrap(),
+        }
+    }
+
+    /// Returns the addresses of all verified peers of this overlay
+    fn get_peers(&self) -> Result<Vec<Address>, Box<dyn Error>> {
+        Ok(self
+            .network()
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .get_verified_addresses())
+    }
+
+    /// Returns the addresses which were introduced to this overlay but were not contacted successfully yet
+    fn get_walkable_addresses(&self) -> Result<Vec<Address>, Box<dyn Error>> {
+        Ok(self
+            .network()
+            .read()
+            .or(Err(Ipv8Error::LockPoisoned("the peer table")))?
+            .get_walkable_addresses(None))
+    }
+}
+
+/// Periodically lets every registered [DiscoveryStrategy] take a step, as long as its overlay has less peers than
+/// the target peer count of that strategy.
+pub struct Walker {
+    /// All strategies with their target peer count
+    strategies: Vec<(Box<dyn DiscoveryStrategy>, usize)>,
+    /// The time between two steps
+    interval: Duration,
+}
+
+impl Walker {
+    /// Creates a walker without any strategies
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            strategies: vec![],
+            interval,
+        }
+    }
+
+    /// Adds a strategy. It only takes steps while its overlay has less than `target_peers` verified peers.
+    /// Strategies which should always run, like the [RandomChurn](churn::RandomChurn), use `usize::MAX`.
+    pub fn add_strategy(&mut self, strategy: Box<dyn DiscoveryStrategy>, target_peers: usize) {
+        self.strategies.push((strategy, target_peers))
+    }
+
+    /// Lets every strategy which did not reach its target peer count yet take a single step
+    pub fn tick(&mut self) {
+        for (strategy, target_peers) in self.strategies.iter_mut() {
+            let peer_count = match strategy.overlay().get_peers() {
+                Ok(peers) => peers.len(),
+                Err(e) => {
+                    warn!("Could not get the peers of an overlay: {}", e);
+                    continue;
+                }
+            };
+
+            if peer_count < *target_peers {
+                if let Err(e) = strategy.take_step() {
+                    warn!("A discovery strategy failed to take a step: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Registers a repeating task which calls [tick](Walker::tick) every interval.
+    /// The walker is moved into the task, so strategies have to be added before.
+    pub fn register(mut self, task_manager: &TaskManager) -> Result<(), Ipv8Error> {
+        let interval = self.interval;
+        task_manager.register_task(
+            WALKER_TASK,
+            move || {
+                self.tick();
+                Ok(())
+            },
+            None,
+            Some(interval),
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod test_helper {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::Mutex;
+
+    /// An overlay which only records where it walked to and who it pinged
+    #[derive(Default)]
+    pub struct TestOverlay {
+        pub network: RwLock<Network>,
+        pub walks: Mutex<Vec<Address>>,
+        pub pings: Mutex<Vec<Address>>,
+        pub bootstrap: Vec<Address>,
+    }
+
+    impl Overlay for TestOverlay {
+        fn network(&self) -> &RwLock<Network> {
+            &self.network
+        }
+
+        fn walk_to(&self, address: &Address) -> Result<(), Box<dyn Error>> {
+            self.walks.lock().unwrap().push(*address);
+            Ok(())
+        }
+
+        fn send_ping(&self, address: &Address) -> Result<(), Box<dyn Error>> {
+            self.pings.lock().unwrap().push(*address);
+            Ok(())
+        }
+
+        fn bootstrap_addresses(&self) -> Vec<Address> {
+            self.bootstrap.clone()
+        }
+    }
+
+    pub fn address(port: u16) -> Address {
+        Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_helper::*;
+    use super::*;
+    use crate::community::peer::Peer;
+    use crate::peerdiscovery::discovery::RandomWalk;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_get_new_introduction_bootstrap() {
+        let overlay = TestOverlay {
+            bootstrap: vec![address(1), address(2)],
+            ..TestOverlay::default()
+        };
+        overlay.get_new_introduction(None).unwrap();
+        assert_eq!(*overlay.walks.lock().unwrap(), vec![address(1), address(2)]);
+    }
+
+    #[test]
+    fn test_get_new_introduction_peer() {
+        let overlay = TestOverlay {
+            bootstrap: vec![address(1)],
+            ..TestOverlay::default()
+        };
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .add_verified_peer(Peer::new([0; 32], address(2), true, Instant::now()));
+        overlay.get_new_introduction(None).unwrap();
+        assert_eq!(*overlay.walks.lock().unwrap(), vec![address(2)]);
+    }
+
+    #[test]
+    fn test_walker_target_peers() {
+        let overlay = Arc::new(TestOverlay {
+            bootstrap: vec![address(1)],
+            ..TestOverlay::default()
+        });
+        let mut walker = Walker::new(Duration::from_millis(500));
+        walker.add_strategy(Box::new(RandomWalk::new(overlay.clone())), 1);
+
+        walker.tick();
+        assert_eq!(overlay.walks.lock().unwrap().len(), 1);
+
+        // once the target is reached, no more steps are taken
+        overlay
+            .network
+            .write()
+            .unwrap()
+            .add_verified_peer(Peer::new([0; 32], address(2), true, Instant::now()));
+        walker.tick();
+        assert_eq!(overlay.walks.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_walker_task() {
+        let overlay = Arc::new(TestOverlay {
+            bootstrap: vec![address(1)],
+            ..TestOverlay::default()
+        });
+        let mut walker = Walker::new(Duration::from_secs(10));
+        walker.add_strategy(Box::new(RandomWalk::new(overlay.clone())), 1);
+
+        let task_manager = TaskManager::new();
+        walker.register(&task_manager).unwrap();
+        assert!(task_manager.is_pending_task_active(WALKER_TASK).unwrap());
+
+        task_manager.run_pending_tasks().unwrap();
+        assert_eq!(overlay.walks.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,498 @@
+//! The peer table of an [Overlay](crate::peerdiscovery::Overlay), keeping track of which peers we know and how we learned about them.
+//!
+//! Modelled after py-ipv8's [Network](https://github.com/Tribler/py-ipv8/blob/master/ipv8/peerdiscovery/network.py).
+
+use crate::community::peer::Peer;
+use crate::crypto::signature::Ed25519PublicKey;
+use crate::networking::address::Address;
+use crate::taskmanager::{Clock, SystemClock};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The state an address is in, from the point of view of a [Network].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PeerState {
+    /// A peer at this address sent us a validly signed message
+    Verified,
+    /// This address was introduced to us and can be walked to
+    Walkable,
+    /// This address was introduced to us, but the peer which introduced it has since been removed.
+    /// It is kept in the introduction graph, but it is no longer walked to.
+    IntroductionOnly,
+}
+
+/// An address some peer told us about
+struct Introduction {
+    /// The address of the peer which introduced this address
+    introducer: Address,
+    /// The community (by its mid) this address was introduced in, if known
+    service: Option<Vec<u8>>,
+    /// False once the introducer was removed from the network
+    walkable: bool,
+}
+
+/// Keeps track of the peers an overlay knows about.
+///
+/// Peers are verified once they sent us a validly signed message. Before that, they are only known by the address
+/// some other peer introduced them with. Introductions are kept after verification, so the network doubles as a graph
+/// of who introduced whom.
+///
+/// The liveness of peers is tracked on the [Clock] of the network, so a
+/// [SimulatedNetwork](crate::networking::simulation::SimulatedNetwork) can run discovery on virtual time.
+pub struct Network {
+    /// All verified peers by their public key
+    verified_peers: HashMap<Ed25519PublicKey, Peer>,
+    /// The public key of the verified peer at every address
+    verified_addresses: HashMap<Address, Ed25519PublicKey>,
+    /// All introduced addresses
+    introductions: HashMap<Address, Introduction>,
+    /// The communities (by their mid) every verified peer is known to be part of
+    services_per_peer: HashMap<Ed25519PublicKey, HashSet<Vec<u8>>>,
+    /// The peers (by their address) which see us at an address, for every address
+    wan_address_votes: HashMap<Address, HashSet<Address>>,
+    /// The address most peers see us at
+    wan_address: Option<Address>,
+    /// The source of the time peers last responded
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Network {
+    /// Creates a new, empty, peer table on the [SystemClock]
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a new, empty, peer table on the given clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            verified_peers: HashMap::new(),
+            verified_addresses: HashMap::new(),
+            introductions: HashMap::new(),
+            services_per_peer: HashMap::new(),
+            wan_address_votes: HashMap::new(),
+            wan_address: None,
+            clock,
+        }
+    }
+
+    /// Returns the current time on the clock of the network
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Adds a peer which sent us a validly signed message.
+    ///
+    /// If the peer is already known, its address and last response time are updated. Any other peer we knew at the
+    /// same address is removed, as it can't be reached there anymore.
+    pub fn add_verified_peer(&mut self, peer: Peer) {
+        let key = *peer.key();
+        let address = *peer.address();
+
+        if let Some(other_key) = self.verified_addresses.insert(address, key) {
+            if other_key != key {
+                self.verified_peers.remove(&other_key);
+                self.services_per_peer.remove(&other_key);
+            }
+        }
+
+        match self.verified_peers.get_mut(&key) {
+            Some(known) => {
+                if *known.address() != address {
+                    self.verified_addresses.remove(known.address());
+                }
+                known.update(address, peer.last_response());
+            }
+            None => {
+                self.verified_peers.insert(key, peer);
+            }
+        }
+    }
+
+    /// Registers that the peer at `introducer` told us about a peer at `address`, optionally in the community
+    /// identified by `service`.
+    ///
+    /// Only the first introduction of an address is remembered.
+    pub fn discover_address(
+        &mut self,
+        introducer: Address,
+        address: Address,
+        service: Option<&[u8]>,
+    ) {
+        if introducer == address {
+            return;
+        }
+        self.introductions
+            .entry(address)
+            .or_insert_with(|| Introduction {
+                introducer,
+                service: service.map(|s| s.to_vec()),
+                walkable: true,
+            });
+    }
+
+    /// Registers that the peer with the given public key is part of the given communities.
+    /// Does nothing when the peer is not verified.
+    pub fn discover_services(&mut self, key: &Ed25519PublicKey, services: &[Vec<u8>]) {
+        if !self.verified_peers.contains_key(key) {
+            return;
+        }
+        self.services_per_peer
+            .entry(*key)
+            .or_default()
+            .extend(services.iter().cloned());
+    }
+
+    /// Returns the communities the peer with the given public key is known to be part of
+    pub fn get_services_for_peer(&self, key: &Ed25519PublicKey) -> Vec<Vec<u8>> {
+        self.services_per_peer
+            .get(key)
+            .map(|services| services.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the peer with the given public key is known to be part of the given community
+    fn has_service(&self, key: &Ed25519PublicKey, service: &[u8]) -> bool {
+        match self.services_per_peer.get(key) {
+            Some(services) => services.contains(service),
+            None => false,
+        }
+    }
+
+    /// Returns all verified peers which are part of the given community
+    pub fn get_peers_for_service(&self, service: &[u8]) -> Vec<&Peer> {
+        self.verified_peers
+            .values()
+            .filter(|peer| self.has_service(peer.key(), service))
+            .collect()
+    }
+
+    /// Returns all verified peers
+    pub fn get_verified_peers(&self) -> Vec<&Peer> {
+        self.verified_peers.values().collect()
+    }
+
+    /// Returns the addresses of all verified peers
+    pub fn get_verified_addresses(&self) -> Vec<Address> {
+        self.verified_peers
+            .values()
+            .map(|peer| *peer.address())
+            .collect()
+    }
+
+    /// Returns the verified peer at the given address
+    pub fn get_verified_by_address(&self, address: &Address) -> Option<&Peer> {
+        self.verified_addresses
+            .get(address)
+            .and_then(|key| self.verified_peers.get(key))
+    }
+
+    /// Returns the verified peer with the given public key
+    pub fn get_verified_by_public_key(&self, key: &Ed25519PublicKey) -> Option<&Peer> {
+        self.verified_peers.get(key)
+    }
+
+    /// Returns the verified peer at the given address, to update its liveness information. A new address has to be
+    /// set with [add_verified_peer](Network::add_verified_peer) instead.
+    pub fn get_verified_by_address_mut(&mut self, address: &Address) -> Option<&mut Peer> {
+        let verified_peers = &mut self.verified_peers;
+        self.verified_addresses
+            .get(address)
+            .and_then(move |key| verified_peers.get_mut(key))
+    }
+
+    /// Returns the verified peer with the given public key, to update its liveness information. A new address has to
+    /// be set with [add_verified_peer](Network::add_verified_peer) instead.
+    pub fn get_verified_by_public_key_mut(&mut self, key: &Ed25519PublicKey) -> Option<&mut Peer> {
+        self.verified_peers.get_mut(key)
+    }
+
+    /// Returns what we know about the given address, or None if we never heard of it
+    pub fn get_state(&self, address: &Address) -> Option<PeerState> {
+        if self.get_verified_by_address(address).is_some() {
+            Some(PeerState::Verified)
+        } else {
+            self.introductions.get(address).map(|introduction| {
+                if introduction.walkable {
+                    PeerState::Walkable
+                } else {
+                    PeerState::IntroductionOnly
+                }
+            })
+        }
+    }
+
+    /// Returns all addresses that were introduced to us but did not respond yet.
+    ///
+    /// When a service is given, only addresses introduced in that community, or introduced by peers which are part
+    /// of that community, are returned.
+    pub fn get_walkable_addresses(&self, service: Option<&[u8]>) -> Vec<Address> {
+        self.introductions
+            .iter()
+            .filter(|(address, introduction)| {
+                introduction.walkable && self.get_verified_by_address(address).is_none()
+            })
+            .filter(|(_, introduction)| match service {
+                None => true,
+                Some(service) => {
+                    introduction.service.as_deref() == Some(service)
+                        || match self.get_verified_by_address(&introduction.introducer) {
+                            Some(introducer) => self.has_service(introducer.key(), service),
+                            None => false,
+                        }
+                }
+            })
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    /// Returns the address of the peer which introduced the given address to us
+    pub fn get_introducer(&self, address: &Address) -> Option<Address> {
+        self.introductions
+            .get(address)
+            .map(|introduction| introduction.introducer)
+    }
+
+    /// Returns all addresses the peer at `introducer` introduced to us
+    pub fn get_introductions_from(&self, introducer: &Address) -> Vec<Address> {
+        self.introductions
+            .iter()
+            .filter(|(_, introduction)| introduction.introducer == *introducer)
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    /// Registers that the peer at `voter` sees us at `address`, like py-ipv8's `wan_address_vote`.
+    ///
+    /// Every peer has a single vote, a new vote replaces its old one. The [wan_address](Network::wan_address) is the
+    /// address with the most votes, so a single peer can't make us advertise the wrong address.
+    pub fn wan_address_vote(&mut self, address: Address, voter: Address) {
+        self.remove_wan_address_vote(&voter);
+        self.wan_address_votes
+            .entry(address)
+            .or_default()
+            .insert(voter);
+        self.count_wan_address_votes();
+    }
+
+    /// Returns the address most peers see us at, if any peer told us
+    pub fn wan_address(&self) -> Option<Address> {
+        self.wan_address
+    }
+
+    /// Takes back the vote of the peer at `voter`
+    fn remove_wan_address_vote(&mut self, voter: &Address) {
+        self.wan_address_votes.retain(|_, voters| {
+            voters.remove(voter);
+            !voters.is_empty()
+        });
+    }
+
+    /// Picks the address with the most votes. On a tie, the current address is kept.
+    fn count_wan_address_votes(&mut self) {
+        let votes = |address: &Address| self.wan_address_votes.get(address).map_or(0, HashSet::len);
+        let current = self.wan_address.map_or(0, |address| votes(&address));
+        if let Some((address, voters)) = self
+            .wan_address_votes
+            .iter()
+            .max_by_key(|(_, voters)| voters.len())
+        {
+            if voters.len() > current {
+                self.wan_address = Some(*address);
+            }
+        }
+    }
+
+    /// Forgets everything about the peer at the given address, including its vote for our WAN address.
+    ///
+    /// Addresses it introduced are kept in the introduction graph, but are no longer walkable.
+    pub fn remove_by_address(&mut self, address: &Address) {
+        if let Some(key) = self.verified_addresses.remove(address) {
+            self.verified_peers.remove(&key);
+            self.services_per_peer.remove(&key);
+        }
+        self.remove_wan_address_vote(address);
+        self.count_wan_address_votes();
+
+        self.introductions.remove(address);
+        for introduction in self.introductions.values_mut() {
+            if introduction.introducer == *address {
+                introduction.walkable = false;
+            }
+        }
+    }
+
+    /// Forgets everything about the peer with the given public key
+    pub fn remove_peer(&mut self, key: &Ed25519PublicKey) {
+        if let Some(peer) = self.verified_peers.get(key) {
+            let address = *peer.address();
+            self.remove_by_address(&address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    fn address(port: u16) -> Address {
+        Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), port))
+    }
+
+    fn peer(port: u16) -> Peer {
+        Peer::new([port as u8; 32], address(port), true, Instant::now())
+    }
+
+    #[test]
+    fn test_discover_address() {
+        let mut network = Network::new();
+        network.add_verified_peer(peer(1));
+        network.discover_address(address(1), address(2), None);
+        network.discover_address(address(1), address(3), None);
+        network.discover_address(address(3), address(1), None);
+
+        let mut walkable = network.get_walkable_addresses(None);
+        walkable.sort_by_key(|a| a.0.port());
+        assert_eq!(walkable, vec![address(2), address(3)]);
+        assert_eq!(
+            network.get_introductions_from(&address(3)),
+            vec![address(1)]
+        );
+        assert_eq!(network.get_verified_addresses(), vec![address(1)]);
+    }
+
+    #[test]
+    fn test_verify_introduced() {
+        let mut network = Network::new();
+        network.discover_address(address(1), address(2), None);
+        network.add_verified_peer(peer(2));
+
+        assert!(network.get_walkable_addresses(None).is_empty());
+        assert_eq!(
+            network.get_introductions_from(&address(1)),
+            vec![address(2)]
+        );
+        assert_eq!(network.get_introducer(&address(2)), Some(address(1)));
+        assert_eq!(
+            network.get_verified_by_address(&address(2)).map(Peer::key),
+            Some(&[2; 32])
+        );
+        assert_eq!(network.get_state(&address(2)), Some(PeerState::Verified));
+    }
+
+    #[test]
+    fn test_remove_by_address() {
+        let mut network = Network::new();
+        network.add_verified_peer(peer(1));
+        network.discover_address(address(1), address(2), None);
+        network.remove_by_address(&address(1));
+        network.remove_by_address(&address(2));
+
+        assert!(network.get_verified_addresses().is_empty());
+        assert!(network.get_walkable_addresses(None).is_empty());
+    }
+
+    #[test]
+    fn test_introducer_removed() {
+        let mut network = Network::new();
+        network.add_verified_peer(peer(1));
+        network.discover_address(address(1), address(2), None);
+        assert_eq!(network.get_state(&address(2)), Some(PeerState::Walkable));
+
+        network.remove_peer(&[1; 32]);
+
+        assert_eq!(network.get_state(&address(1)), None);
+        assert_eq!(
+            network.get_state(&address(2)),
+            Some(PeerState::IntroductionOnly)
+        );
+        assert!(network.get_walkable_addresses(None).is_empty());
+        assert_eq!(network.get_introducer(&address(2)), Some(address(1)));
+    }
+
+    #[test]
+    fn test_peer_changes_address() {
+        let mut network = Network::new();
+        network.add_verified_peer(peer(1));
+        network.add_verified_peer(Peer::new([1; 32], address(2), true, Instant::now()));
+
+        assert_eq!(network.get_verified_addresses(), vec![address(2)]);
+        assert!(network.get_verified_by_address(&address(1)).is_none());
+
+        // another peer taking over the address replaces the old one
+        network.add_verified_peer(Peer::new([3; 32], address(2), true, Instant::now()));
+        assert!(network.get_verified_by_public_key(&[1; 32]).is_none());
+        assert_eq!(network.get_verified_peers().len(), 1);
+        assert_eq!(
+            network
+                .get_verified_by_address_mut(&address(2))
+                .map(|peer| *peer.key()),
+            Some([3; 32])
+        );
+
+        network.remove_by_address(&address(2));
+        assert!(network.get_verified_by_public_key(&[3; 32]).is_none());
+        assert!(network.get_verified_by_address(&address(2)).is_none());
+    }
+
+    #[test]
+    fn test_wan_address_vote() {
+        let mut network = Network::new();
+        assert_eq!(network.wan_address(), None);
+
+        network.wan_address_vote(address(100), address(1));
+        assert_eq!(network.wan_address(), Some(address(100)));
+
+        // a single peer can't outvote the others
+        network.wan_address_vote(address(100), address(2));
+        network.wan_address_vote(address(200), address(3));
+        assert_eq!(network.wan_address(), Some(address(100)));
+
+        // but peers can change their vote
+        network.wan_address_vote(address(200), address(1));
+        network.wan_address_vote(address(200), address(2));
+        assert_eq!(network.wan_address(), Some(address(200)));
+
+        // and the votes of removed peers are forgotten
+        network.remove_by_address(&address(1));
+        network.remove_by_address(&address(2));
+        network.wan_address_vote(address(100), address(4));
+        network.wan_address_vote(address(100), address(5));
+        assert_eq!(network.wan_address(), Some(address(100)));
+    }
+
+    #[test]
+    fn test_services() {
+        let mut network = Network::new();
+        network.add_verified_peer(peer(1));
+        network.add_verified_peer(peer(2));
+        network.discover_services(&[1; 32], &[vec![42]]);
+        // unverified peers can't have services
+        network.discover_services(&[3; 32], &[vec![42]]);
+
+        let peers: Vec<&Ed25519PublicKey> = network
+            .get_peers_for_service(&[42])
+            .into_iter()
+            .map(Peer::key)
+            .collect();
+        assert_eq!(peers, vec![&[1; 32]]);
+        assert_eq!(network.get_services_for_peer(&[1; 32]), vec![vec![42]]);
+        assert!(network.get_services_for_peer(&[3; 32]).is_empty());
+
+        // addresses introduced by a member of the community, or in the community itself, are walkable for it
+        network.discover_address(address(1), address(4), None);
+        network.discover_address(address(2), address(5), Some(&[42]));
+        network.discover_address(address(2), address(6), None);
+
+        let mut walkable = network.get_walkable_addresses(Some(&[42]));
+        walkable.sort_by_key(|a| a.0.port());
+        assert_eq!(walkable, vec![address(4), address(5)]);
+    }
+}
@@ -0,0 +1,443 @@
+//! Correlates responses to the requests they answer, like py-ipv8's
+//! [RequestCache](https://github.com/Tribler/py-ipv8/blob/master/ipv8/requestcache.py).
+//!
+//! A community adds a [NumberCache] when it sends a request and puts the number it gets back in the `identifier` of
+//! the request payload. When the response arrives, the cache is popped using the identifier of the response. When no
+//! response arrives in time, the cache is removed and its [on_timeout](NumberCache::on_timeout) is called by the
+//! [TaskManager].
+//!
+//! With the `tokio` feature, the response to a request can also be awaited, see [add_waiting](RequestCache::add_waiting).
+
+use crate::error::Ipv8Error;
+use crate::taskmanager::TaskManager;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "tokio")]
+use tokio::sync::oneshot;
+
+/// Used to give every [RequestCache] its own task names
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The state of an outstanding request.
+pub trait NumberCache: Send + 'static {
+    /// Identifies the kind of request. Numbers only have to be unique per prefix.
+    fn prefix(&self) -> &'static str;
+
+    /// The time to wait for a response before [on_timeout](NumberCache::on_timeout) is called
+    fn timeout_delay(&self) -> Duration {
+        Duration::from_secs(10)
+    }
+
+    /// Called when no response arrived within the [timeout_delay](NumberCache::timeout_delay).
+    /// The cache is already removed from the [RequestCache] at that point.
+    fn on_timeout(&mut self);
+}
+
+/// Yields the response to a request stored by [RequestCache::add_waiting], or None when it timed out
+#[cfg(feature = "tokio")]
+pub type WaitingResponse<T> = oneshot::Receiver<Option<T>>;
+
+/// A [NumberCache] which hands the response to the task awaiting it. Created by [RequestCache::add_waiting].
+#[cfg(feature = "tokio")]
+pub struct WaitingCache<T: Send + 'static> {
+    /// The prefix the cache is stored under
+    prefix: &'static str,
+    /// The time to wait for the response
+    timeout_delay: Duration,
+    /// Hands over the response, or None on a timeout
+    sender: Option<oneshot::Sender<Option<T>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: Send + 'static> NumberCache for WaitingCache<T> {
+    fn prefix(&self) -> &'static str {
+        self.prefix
+    }
+
+    fn timeout_delay(&self) -> Duration {
+        self.timeout_delay
+    }
+
+    fn on_timeout(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            // Nobody may be waiting anymore, which is fine
+            let _ = sender.send(None);
+        }
+    }
+}
+
+/// A stored cache with a way to call its timeout handler without knowing its type
+struct Entry {
+    /// Tells apart caches which were stored under the same prefix and number one after the other
+    id: u64,
+    /// The cache itself
+    cache: Box<dyn Any + Send>,
+    /// Calls [on_timeout](NumberCache::on_timeout) on the cache
+    on_timeout: fn(&mut (dyn Any + Send)),
+}
+
+/// Calls [on_timeout](NumberCache::on_timeout) on a cache which is known to be a `T`
+fn timeout<T: NumberCache>(cache: &mut (dyn Any + Send)) {
+    if let Some(cache) = cache.downcast_mut::<T>() {
+        cache.on_timeout();
+    }
+}
+
+/// All outstanding requests of a community, by prefix and number.
+pub struct RequestCache {
+    /// Runs the timeouts of the caches
+    task_manager: Arc<TaskManager>,
+    /// Makes the timeout task names of this cache unique within the [TaskManager]
+    id: u64,
+    /// The id of the next stored cache
+    next_entry: AtomicU64,
+    /// All stored caches
+    entries: Arc<Mutex<HashMap<(&'static str, u16), Entry>>>,
+}
+
+impl RequestCache {
+    /// Creates an empty request cache whose timeouts are run by the given [TaskManager]
+    pub fn new(task_manager: Arc<TaskManager>) -> Self {
+        Self {
+            task_manager,
+            id: NEXT_CACHE_ID.fetch_add(1, Ordering::SeqCst),
+            next_entry: AtomicU64::new(0),
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Stores the cache under a random number which is not in use for its prefix yet. Returns that number.
+    pub fn add<T: NumberCache>(&self, cache: T) -> Result<u16, Ipv8Error> {
+        let prefix = cache.prefix();
+        let number = {
+            let entries = self
+                .entries
+                .lock()
+                .or(Err(Ipv8Error::LockPoisoned("the request cache")))?;
+            if entries.keys().filter(|(p, _)| *p == prefix).count() >= usize::from(u16::MAX) {
+                return Err(Ipv8Error::CacheFull { prefix });
+            }
+            // py-ipv8 never uses 0 as a number
+            loop {
+                let number = rand::random::<u16>();
+                if number != 0 && !entries.contains_key(&(prefix, number)) {
+                    break number;
+                }
+            }
+        };
+        self.add_with_number(cache, number)?;
+        Ok(number)
+    }
+
+    /// Stores the cache under the given number. Fails when the number is already in use for the prefix of the cache.
+    pub fn add_with_number<T: NumberCache>(&self, cache: T, number: u16) -> Result<(), Ipv8Error> {
+        let prefix = cache.prefix();
+        let delay = cache.timeout_delay();
+        let id = self.next_entry.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut entries = self
+                .entries
+                .lock()
+                .or(Err(Ipv8Error::LockPoisoned("the request cache")))?;
+            if entries.contains_key(&(prefix, number)) {
+                return Err(Ipv8Error::CacheExists { prefix, number });
+            }
+            entries.insert(
+                (prefix, number),
+                Entry {
+                    id,
+                    cache: Box::new(cache),
+                    on_timeout: timeout::<T>,
+                },
+            );
+        }
+
+        let entries = Arc::clone(&self.entries);
+        let registered = self.task_manager.register_task(
+            &self.task_name(id),
+            move || {
+                // The cache may have been popped, and another one stored under the same number since
+                let entry = take(&entries, prefix, number, id)?;
+                if let Some(mut entry) = entry {
+                    debug!("Request cache {}:{} timed out", prefix, number);
+                    (entry.on_timeout)(&mut *entry.cache);
+                }
+                Ok(())
+            },
+            Some(delay),
+            None,
+        );
+        if registered.is_err() {
+            take(&self.entries, prefix, number, id)?;
+        }
+        registered
+    }
+
+    /// Returns true if a cache is stored under the given prefix and number
+    pub fn has(&self, prefix: &'static str, number: u16) -> Result<bool, Ipv8Error> {
+        Ok(self
+            .entries
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the request cache")))?
+            .contains_key(&(prefix, number)))
+    }
+
+    /// Calls `f` with the cache stored under the given prefix and number and returns its result.
+    /// Returns None when there is no such cache, or when it is not a `T`.
+    pub fn get<T, F, R>(
+        &self,
+        prefix: &'static str,
+        number: u16,
+        f: F,
+    ) -> Result<Option<R>, Ipv8Error>
+    where
+        T: NumberCache,
+        F: FnOnce(&mut T) -> R,
+    {
+        Ok(self
+            .entries
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the request cache")))?
+            .get_mut(&(prefix, number))
+            .and_then(|entry| entry.cache.downcast_mut::<T>())
+            .map(f))
+    }
+
+    /// Removes the cache stored under the given prefix and number, cancelling its timeout.
+    /// Returns None when there is no such cache, or when it is not a `T`. In the last case the cache is kept.
+    pub fn pop<T: NumberCache>(
+        &self,
+        prefix: &'static str,
+        number: u16,
+    ) -> Result<Option<T>, Ipv8Error> {
+        let cache = {
+            let mut entries = self
+                .entries
+                .lock()
+                .or(Err(Ipv8Error::LockPoisoned("the request cache")))?;
+            match entries.get(&(prefix, number)) {
+                Some(entry) if entry.cache.is::<T>() => entries.remove(&(prefix, number)),
+                _ => None,
+            }
+        };
+
+        match cache {
+            Some(entry) => {
+                self.task_manager
+                    .cancel_pending_task(&self.task_name(entry.id))?;
+                Ok(entry.cache.downcast::<T>().ok().map(|cache| *cache))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Stores a cache for a request whose response is awaited instead of handled by a [NumberCache] of its own. Returns
+    /// the number of the cache and a receiver which yields the response passed to [resolve](RequestCache::resolve),
+    /// or None when no response arrived within the timeout. The receiver fails when the cache is [cleared](RequestCache::clear).
+    #[cfg(feature = "tokio")]
+    pub fn add_waiting<T: Send + 'static>(
+        &self,
+        prefix: &'static str,
+        timeout_delay: Duration,
+    ) -> Result<(u16, WaitingResponse<T>), Ipv8Error> {
+        let (sender, receiver) = oneshot::channel();
+        let number = self.add(WaitingCache {
+            prefix,
+            timeout_delay,
+            sender: Some(sender),
+        })?;
+        Ok((number, receiver))
+    }
+
+    /// Hands the response to the request stored by [add_waiting](RequestCache::add_waiting) under the given prefix and
+    /// number. Returns false when no request waiting for a `T` is stored there, or when nobody awaits it anymore.
+    #[cfg(feature = "tokio")]
+    pub fn resolve<T: Send + 'static>(
+        &self,
+        prefix: &'static str,
+        number: u16,
+        response: T,
+    ) -> Result<bool, Ipv8Error> {
+        Ok(match self.pop::<WaitingCache<T>>(prefix, number)? {
+            Some(mut cache) => match cache.sender.take() {
+                Some(sender) => sender.send(Some(response)).is_ok(),
+                None => false,
+            },
+            None => false,
+        })
+    }
+
+    /// Removes all caches without calling their timeouts
+    pub fn clear(&self) -> Result<(), Ipv8Error> {
+        let ids: Vec<u64> = {
+            let mut entries = self
+                .entries
+                .lock()
+                .or(Err(Ipv8Error::LockPoisoned("the request cache")))?;
+            entries.drain().map(|(_, entry)| entry.id).collect()
+        };
+        for id in ids {
+            self.task_manager.cancel_pending_task(&self.task_name(id))?;
+        }
+        Ok(())
+    }
+
+    /// The name of the task which times out the cache with the given id
+    fn task_name(&self, id: u64) -> String {
+        format!("request-cache-{}:{}", self.id, id)
+    }
+}
+
+/// Removes the cache stored under the given prefix and number if it is the one with the given id
+fn take(
+    entries: &Mutex<HashMap<(&'static str, u16), Entry>>,
+    prefix: &'static str,
+    number: u16,
+    id: u64,
+) -> Result<Option<Entry>, Ipv8Error> {
+    let mut entries = entries
+        .lock()
+        .or(Err(Ipv8Error::LockPoisoned("the request cache")))?;
+    match entries.get(&(prefix, number)) {
+        Some(entry) if entry.id == id => Ok(entries.remove(&(prefix, number))),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::simulation::VirtualClock;
+    use std::sync::atomic::AtomicUsize;
+
+    static TIMEOUTS: AtomicUsize = AtomicUsize::new(0);
+
+    fn virtual_task_manager() -> (Arc<VirtualClock>, Arc<TaskManager>) {
+        let clock = Arc::new(VirtualClock::new());
+        let task_manager = Arc::new(TaskManager::with_clock(clock.clone()));
+        (clock, task_manager)
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct PingCache {
+        sent_to: u16,
+    }
+
+    impl NumberCache for PingCache {
+        fn prefix(&self) -> &'static str {
+            "ping"
+        }
+
+        fn timeout_delay(&self) -> Duration {
+            Duration::from_millis(10)
+        }
+
+        fn on_timeout(&mut self) {
+            TIMEOUTS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct OtherCache;
+
+    impl NumberCache for OtherCache {
+        fn prefix(&self) -> &'static str {
+            "ping"
+        }
+
+        fn on_timeout(&mut self) {}
+    }
+
+    #[test]
+    fn test_add_pop() {
+        let task_manager = Arc::new(TaskManager::new());
+        let cache = RequestCache::new(Arc::clone(&task_manager));
+
+        let number = cache.add(PingCache { sent_to: 42 }).unwrap();
+        assert!(cache.has("ping", number).unwrap());
+        assert_eq!(
+            cache
+                .get("ping", number, |c: &mut PingCache| c.sent_to)
+                .unwrap(),
+            Some(42)
+        );
+
+        // the wrong type can't take the cache
+        assert!(cache.pop::<OtherCache>("ping", number).unwrap().is_none());
+        assert_eq!(
+            cache.pop::<PingCache>("ping", number).unwrap(),
+            Some(PingCache { sent_to: 42 })
+        );
+        assert!(!cache.has("ping", number).unwrap());
+        assert_eq!(task_manager.pending_task_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_duplicate_number() {
+        let cache = RequestCache::new(Arc::new(TaskManager::new()));
+        cache.add_with_number(PingCache { sent_to: 1 }, 7).unwrap();
+        assert!(cache.add_with_number(PingCache { sent_to: 2 }, 7).is_err());
+
+        cache.clear().unwrap();
+        cache.add_with_number(PingCache { sent_to: 2 }, 7).unwrap();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (clock, task_manager) = virtual_task_manager();
+        let cache = RequestCache::new(Arc::clone(&task_manager));
+        let number = cache.add(PingCache { sent_to: 1 }).unwrap();
+
+        task_manager.run_pending_tasks().unwrap();
+        assert!(cache.has("ping", number).unwrap());
+
+        clock.advance(Duration::from_millis(10));
+        task_manager.run_pending_tasks().unwrap();
+        assert!(!cache.has("ping", number).unwrap());
+        assert_eq!(TIMEOUTS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_number_reused() {
+        let (clock, task_manager) = virtual_task_manager();
+        let cache = RequestCache::new(Arc::clone(&task_manager));
+        cache.add_with_number(PingCache { sent_to: 1 }, 7).unwrap();
+
+        // popped, but its timeout is not cancelled yet when the number is used again
+        cache.entries.lock().unwrap().remove(&("ping", 7));
+        cache.add_with_number(OtherCache, 7).unwrap();
+
+        // the old timeout leaves the new cache alone
+        clock.advance(Duration::from_millis(10));
+        task_manager.run_pending_tasks().unwrap();
+        assert!(cache.has("ping", 7).unwrap());
+        assert!(cache.pop::<OtherCache>("ping", 7).unwrap().is_some());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_waiting() {
+        let (clock, task_manager) = virtual_task_manager();
+        let cache = RequestCache::new(Arc::clone(&task_manager));
+
+        let (number, response) = cache
+            .add_waiting::<u32>("request", Duration::from_secs(10))
+            .unwrap();
+        assert!(!cache
+            .resolve("request", number, String::from("wrong type"))
+            .unwrap());
+        assert!(cache.resolve("request", number, 42u32).unwrap());
+        assert_eq!(response.await.unwrap(), Some(42));
+        assert_eq!(task_manager.pending_task_count().unwrap(), 0);
+
+        let (number, response) = cache
+            .add_waiting::<u32>("request", Duration::from_millis(10))
+            .unwrap();
+        clock.advance(Duration::from_millis(10));
+        task_manager.run_pending_tasks().unwrap();
+        assert_eq!(response.await.unwrap(), None);
+        assert!(!cache.resolve("request", number, 42u32).unwrap());
+    }
+}
@@ -10,12 +10,9 @@ use crate::crypto::signature::{Signature, KeyPair, sign_packet, Ed25519PublicKey
 use crate::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
 use crate::payloads::Ipv8Payload;
 use crate::serialization::header::Header;
+use crate::error::Ipv8Error;
 use bincode;
-use bincode::ErrorKind;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
-
-create_error!(HeaderError, "The supplied header was invalid");
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 /// The packet struct containing the bytes of a packet in a `Vec<u8>`.
@@ -36,19 +33,23 @@ pub struct PacketDeserializer {
     pub pntr: Packet,
     /// The index in the vector keeping track how far along we are deserializing.
     pub index: usize,
+    /// The key the signature was verified with, once it is verified. The signature itself is removed at that point.
+    verified_with: Option<Ed25519PublicKey>,
 }
 
 /// iterates over a packet to extract it's possibly multiple payloads
 impl PacketDeserializer {
     /// Deserializes a stream of bytes into an ipv8 payload. Which payload is inferred by the type of T which is generic.
     /// T has to be deserializable and implement the Ipv8Payload trait.
-    pub fn next_payload<T>(&mut self) -> Result<T, Box<ErrorKind>>
+    pub fn next_payload<T>(&mut self) -> Result<T, Ipv8Error>
     where
         for<'de> T: Deserialize<'de> + Ipv8Payload + Serialize,
     {
+        let offset = self.index;
         let res: T = bincode::config()
             .big_endian()
-            .deserialize(&self.pntr.0[self.index..])?;
+            .deserialize(&self.pntr.0[offset..])
+            .map_err(|source| Ipv8Error::Deserialize { offset, source })?;
 
         // the old solution was: self.index += size_of::<T>();
         // this doesnt work as it is not uncommon to return less bytes than was actually in the bytecode (lengths etc)
@@ -69,22 +70,24 @@ impl PacketDeserializer {
     }
 
     /// Returns the header of a packet without removing it
-    pub fn peek_header(&self) -> Result<Header, Box<ErrorKind>> {
+    pub fn peek_header(&self) -> Result<Header, Ipv8Error> {
+        let offset = self.index;
         let res: Header = bincode::config()
             .big_endian()
-            .deserialize(&self.pntr.0[self.index..])?;
+            .deserialize(&self.pntr.0[offset..])
+            .map_err(|source| Ipv8Error::Deserialize { offset, source })?;
         Ok(res)
     }
 
     /// Returns the header of a packet and removes it
-    pub fn pop_header(&mut self) -> Result<Header, Box<ErrorKind>> {
+    pub fn pop_header(&mut self) -> Result<Header, Ipv8Error> {
         let res = self.peek_header()?;
         self.index += res.size;
         Ok(res)
     }
 
     /// Just skips over the header returning self
-    pub fn skip_header(mut self) -> Result<Self, Box<ErrorKind>> {
+    pub fn skip_header(mut self) -> Result<Self, Ipv8Error> {
         self.pop_header()?;
         Ok(self)
     }
@@ -110,30 +113,43 @@ impl PacketDeserializer {
 
     /// Does the same thing as the Packet. verify method. Takes a public key as second argument instead of extracting it from the packet itself
     /// through a BinMemberAuthenticationPayload
+    ///
+    /// Once a signature is verified, verifying again only checks if the same key is used.
+    /// This way the [CommunityRegistry](crate::community::CommunityRegistry) and a community can both verify a packet.
     pub fn verify_with(&mut self, pkey: Ed25519PublicKey) -> bool {
+        if let Some(verified) = self.verified_with {
+            return verified == pkey;
+        }
+
         let keylength = Signature::ED25519_SIGNATURE_BYTES;
 
         let datalen = self.len();
+        // too short to even contain a signature
+        if datalen < keylength {
+            return false;
+        }
 
         let (packet, signature) = self.pntr.0.split_at(datalen - keylength);
 
         let status = verify_raw(&pkey, packet, signature);
         self.pntr.0.truncate(datalen - keylength);
 
+        if status {
+            self.verified_with = Some(pkey);
+        }
         status
     }
+
+    /// Returns the key the signature of this packet was verified with, or None if it is not verified (yet)
+    pub fn verified_with(&self) -> Option<Ed25519PublicKey> {
+        self.verified_with
+    }
 }
 
 impl Packet {
     /// Creates a new packet with a given header.
-    pub fn new(header: Header) -> Result<Self, Box<dyn Error>> {
-        let mut res = Self(vec![]);
-        res.0
-            .extend(match bincode::config().big_endian().serialize(&header) {
-                Ok(i) => i,
-                Err(_) => return Err(Box::new(HeaderError)),
-            });
-        Ok(res)
+    pub fn new(header: Header) -> Result<Self, Ipv8Error> {
+        Ok(Self(bincode::config().big_endian().serialize(&header)?))
     }
 
     /// Extracts the raw byte contents from a packet.
@@ -141,6 +157,15 @@ impl Packet {
         &*self.0
     }
 
+    /// Returns the header of the packet without deserializing the rest of it
+    pub fn peek_header(&self) -> Result<Header, Ipv8Error> {
+        let res: Header = bincode::config()
+            .big_endian()
+            .deserialize(&self.0)
+            .map_err(|source| Ipv8Error::Deserialize { offset: 0, source })?;
+        Ok(res)
+    }
+
     /// Signs a packet. After this, new payloads must under no circumstances be added as this will
     /// break the verification process on the receiving end. There is no check for this by design for a speed boost
     /// (though this may or may not be revisited later). Sign deliberately consumes self and returns it again so it can
@@ -151,7 +176,7 @@ impl Packet {
     ///
     /// To verify signatures first transform the Packet into a PacketIterator with Packet.deserialize_multiple and then use the PacketIterator.verify() or
     /// PacketIterator.verify_with() method.
-    pub fn sign(mut self, keypair: &KeyPair) -> Result<Self, Box<dyn Error>> {
+    pub fn sign(mut self, keypair: &KeyPair) -> Result<Self, Ipv8Error> {
         //        let signature = Signature::from_bytes(&*self.0, skey)?;
         let signature = sign_packet(&keypair, &self)?;
         self.add(&signature)?;
@@ -165,11 +190,12 @@ impl Packet {
         PacketDeserializer {
             pntr: self,
             index: 0,
+            verified_with: None,
         }
     }
 
     /// Adds a payload to a packet. This serializes payloads.
-    pub fn add<T>(&mut self, obj: &T) -> Result<(), Box<ErrorKind>>
+    pub fn add<T>(&mut self, obj: &T) -> Result<(), Ipv8Error>
     where
         T: Ipv8Payload + Serialize,
     {
@@ -290,6 +316,17 @@ mod tests {
         assert!(valid);
     }
 
+    #[test]
+    fn test_verify_without_signature() {
+        let pk = KeyPair::new().unwrap();
+        let packet = Packet::new(create_test_header!()).unwrap();
+        assert_eq!(packet.raw().len(), 23);
+
+        let mut deser_iterator = packet.start_deserialize();
+        assert!(!deser_iterator.verify_with(pk.public_key().unwrap()));
+        assert_eq!(deser_iterator.verified_with(), None);
+    }
+
     #[test]
     fn test_serialize_multiple() {
         let a = TestPayload1 { test: 42 };
@@ -338,16 +375,18 @@ mod tests {
         ser_tmp.add(&a).unwrap();
         ser_tmp.add(&b).unwrap();
         ser_tmp.add(&c).unwrap();
+        let ser_tmp_len = ser_tmp.len();
 
         let mut deser_iterator = ser_tmp.start_deserialize().skip_header().unwrap();
         assert_eq!(a, deser_iterator.next_payload().unwrap());
         assert_eq!(b, deser_iterator.next_payload().unwrap());
         assert_eq!(c, deser_iterator.next_payload().unwrap());
 
-        let last: Result<TestPayload1, Box<ErrorKind>> = deser_iterator.next_payload();
+        let last: Result<TestPayload1, Ipv8Error> = deser_iterator.next_payload();
         match last {
             Ok(_) => assert!(false, "this should throw an error as there is no next"),
-            Err(_) => assert!(true),
+            Err(Ipv8Error::Deserialize { offset, .. }) => assert_eq!(offset, ser_tmp_len),
+            Err(_) => assert!(false, "this should be a deserialization error"),
         };
     }
 }
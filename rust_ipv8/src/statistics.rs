@@ -0,0 +1,341 @@
+//! Counts the traffic of every community per message type, like py-ipv8's
+//! [StatisticsEndpoint](https://github.com/Tribler/py-ipv8/blob/master/ipv8/messaging/interfaces/statistics_endpoint.py).
+//!
+//! Received packets are counted by the [CommunityRegistry](crate::community::CommunityRegistry) as it forwards them,
+//! sent packets by a [StatisticsEndpoint] wrapped around the endpoint the communities send with. A
+//! [snapshot](crate::IPv8Handle::statistics) of everything counted so far can be taken at any time.
+
+use crate::error::Ipv8Error;
+use crate::networking::address::Address;
+use crate::networking::endpoint::Endpoint;
+use crate::networking::queue::QueueStats;
+use crate::networking::Receiver;
+use crate::serialization::Packet;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// The traffic of a single message type, or the sum of several
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct MessageStatistics {
+    /// The number of packets sent
+    pub packets_sent: usize,
+    /// The number of bytes sent
+    pub bytes_sent: usize,
+    /// The number of packets received, including the ones which turned out to be invalid
+    pub packets_received: usize,
+    /// The number of bytes received
+    pub bytes_received: usize,
+    /// The number of received packets the community failed to handle, mostly because they could not be decoded
+    pub decode_errors: usize,
+    /// The number of received packets dropped because they did not meet the
+    /// [SignaturePolicy](crate::community::SignaturePolicy) of their message type
+    pub signature_failures: usize,
+}
+
+impl MessageStatistics {
+    /// Adds the counts of other to these
+    fn add(&mut self, other: &MessageStatistics) {
+        self.packets_sent += other.packets_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.packets_received += other.packets_received;
+        self.bytes_received += other.bytes_received;
+        self.decode_errors += other.decode_errors;
+        self.signature_failures += other.signature_failures;
+    }
+}
+
+/// Everything counted by [Statistics] at one moment
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct StatisticsSnapshot {
+    /// The traffic per community mid, per message type
+    pub messages: HashMap<Vec<u8>, HashMap<u8, MessageStatistics>>,
+    /// The number of received packets dropped because they were for a community which is not loaded. These are not
+    /// counted per mid, anyone can make up mids.
+    pub unknown_community: usize,
+    /// The number of received packets dropped because their header could not be decoded
+    pub undecodable: usize,
+    /// The number of received packets dropped by the [RateLimiter](crate::community::ratelimit::RateLimiter)
+    pub rate_limited: usize,
+    /// The packets received and dropped by the endpoint before they reached any community, if it queues them
+    pub queue: Option<QueueStats>,
+}
+
+impl StatisticsSnapshot {
+    /// Returns the traffic of a community over all of its message types
+    pub fn community(&self, mid: &[u8]) -> MessageStatistics {
+        let mut total = MessageStatistics::default();
+        for statistics in self.messages.get(mid).into_iter().flat_map(HashMap::values) {
+            total.add(statistics);
+        }
+        total
+    }
+
+    /// Returns the traffic of all communities together
+    pub fn total(&self) -> MessageStatistics {
+        let mut total = MessageStatistics::default();
+        for statistics in self.messages.values().flat_map(HashMap::values) {
+            total.add(statistics);
+        }
+        total
+    }
+}
+
+/// The traffic counters shared by the [CommunityRegistry](crate::community::CommunityRegistry) and a
+/// [StatisticsEndpoint], see the [module documentation](crate::statistics).
+#[derive(Default)]
+pub struct Statistics {
+    /// The counts so far
+    counts: Mutex<StatisticsSnapshot>,
+}
+
+impl Statistics {
+    /// Creates statistics with nothing counted yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a sent packet under the community and message type in its header. Packets without a header are not
+    /// counted.
+    pub fn record_sent(&self, packet: &Packet) -> Result<(), Ipv8Error> {
+        match message_of(packet) {
+            Some((mid, message_type)) => self.count_sent(&mid, message_type, packet.raw().len()),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a sent packet of the given size
+    fn count_sent(&self, mid: &[u8], message_type: u8, bytes: usize) -> Result<(), Ipv8Error> {
+        self.update(mid, message_type, |statistics| {
+            statistics.packets_sent += 1;
+            statistics.bytes_sent += bytes;
+        })
+    }
+
+    /// Counts a packet received for a loaded community
+    pub fn record_received(
+        &self,
+        mid: &[u8],
+        message_type: u8,
+        bytes: usize,
+    ) -> Result<(), Ipv8Error> {
+        self.update(mid, message_type, |statistics| {
+            statistics.packets_received += 1;
+            statistics.bytes_received += bytes;
+        })
+    }
+
+    /// Counts a received packet the community failed to handle
+    pub fn record_decode_error(&self, mid: &[u8], message_type: u8) -> Result<(), Ipv8Error> {
+        self.update(mid, message_type, |statistics| {
+            statistics.decode_errors += 1
+        })
+    }
+
+    /// Counts a received packet which did not meet its signature policy
+    pub fn record_signature_failure(&self, mid: &[u8], message_type: u8) -> Result<(), Ipv8Error> {
+        self.update(mid, message_type, |statistics| {
+            statistics.signature_failures += 1
+        })
+    }
+
+    /// Counts a received packet for a community which is not loaded
+    pub fn record_unknown_community(&self) -> Result<(), Ipv8Error> {
+        self.counts
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the statistics")))?
+            .unknown_community += 1;
+        Ok(())
+    }
+
+    /// Counts a received packet without a valid header
+    pub fn record_undecodable(&self) -> Result<(), Ipv8Error> {
+        self.counts
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the statistics")))?
+            .undecodable += 1;
+        Ok(())
+    }
+
+    /// Counts a received packet which exceeded a rate limit
+    pub fn record_rate_limited(&self) -> Result<(), Ipv8Error> {
+        self.counts
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the statistics")))?
+            .rate_limited += 1;
+        Ok(())
+    }
+
+    /// Returns a copy of everything counted so far
+    pub fn snapshot(&self) -> Result<StatisticsSnapshot, Ipv8Error> {
+        Ok(self
+            .counts
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the statistics")))?
+            .clone())
+    }
+
+    /// Applies f to the counts of a message type
+    fn update(
+        &self,
+        mid: &[u8],
+        message_type: u8,
+        f: impl FnOnce(&mut MessageStatistics),
+    ) -> Result<(), Ipv8Error> {
+        let mut counts = self
+            .counts
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the statistics")))?;
+        let community = counts.messages.entry(mid.to_vec()).or_default();
+        f(community.entry(message_type).or_default());
+        Ok(())
+    }
+}
+
+/// Returns the community and message type in the header of the packet, if it has a complete one
+fn message_of(packet: &Packet) -> Option<(Vec<u8>, u8)> {
+    let header = packet.peek_header().ok()?;
+    Some((header.mid_hash?, header.message_type? as u8))
+}
+
+/// An [Endpoint] which counts the packets sent through it in [Statistics] and passes everything on to the endpoint
+/// it wraps.
+pub struct StatisticsEndpoint {
+    /// The endpoint doing the actual work
+    endpoint: Arc<dyn Endpoint>,
+    /// Where sent packets are counted
+    statistics: Arc<Statistics>,
+}
+
+impl StatisticsEndpoint {
+    /// Wraps the endpoint, counting its sent packets in the given statistics
+    pub fn new(endpoint: Arc<dyn Endpoint>, statistics: Arc<Statistics>) -> Self {
+        Self {
+            endpoint,
+            statistics,
+        }
+    }
+}
+
+impl Endpoint for StatisticsEndpoint {
+    fn send(&self, address: &Address, packet: Packet) -> Result<usize, Box<dyn Error>> {
+        // The packet is moved into the endpoint, so what to count is taken from it up front
+        let message = message_of(&packet);
+        let bytes = packet.raw().len();
+        let sent = self.endpoint.send(address, packet)?;
+        if let Some((mid, message_type)) = message {
+            self.statistics.count_sent(&mid, message_type, bytes)?;
+        }
+        Ok(sent)
+    }
+
+    fn send_batch(&self, packets: &[(Address, Packet)]) -> Result<usize, Box<dyn Error>> {
+        let sent = self.endpoint.send_batch(packets)?;
+        for (_, packet) in &packets[..sent] {
+            self.statistics.record_sent(packet)?;
+        }
+        Ok(sent)
+    }
+
+    fn add_listener(
+        &self,
+        listener: Box<dyn Receiver + Send + Sync>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.endpoint.add_listener(listener)
+    }
+
+    fn open(&self) -> Result<(), Box<dyn Error>> {
+        self.endpoint.open()
+    }
+
+    fn close(&self) -> Result<(), Box<dyn Error>> {
+        self.endpoint.close()
+    }
+
+    fn join(&self) -> Result<(), Box<dyn Error>> {
+        self.endpoint.join()
+    }
+
+    fn is_open(&self) -> bool {
+        self.endpoint.is_open()
+    }
+
+    fn local_address(&self) -> Result<Address, Box<dyn Error>> {
+        self.endpoint.local_address()
+    }
+
+    fn receive_stats(&self) -> Option<QueueStats> {
+        self.endpoint.receive_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::test_helper::localhost_endpoint;
+    use crate::serialization::header::Header;
+
+    fn packet(mid: u8, message_type: u8, size: usize) -> Packet {
+        let mut packet = Packet::new(Header::py_ipv8_header([mid; 20], message_type)).unwrap();
+        packet.0.resize(size, 0);
+        packet
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let statistics = Statistics::new();
+        statistics.record_received(&[1; 20], 1, 100).unwrap();
+        statistics.record_received(&[1; 20], 1, 50).unwrap();
+        statistics.record_received(&[1; 20], 2, 30).unwrap();
+        statistics.record_decode_error(&[1; 20], 2).unwrap();
+        statistics.record_signature_failure(&[2; 20], 1).unwrap();
+        statistics.record_unknown_community().unwrap();
+        statistics.record_sent(&packet(1, 3, 40)).unwrap();
+        statistics.record_sent(&Packet(vec![])).unwrap();
+
+        let snapshot = statistics.snapshot().unwrap();
+        assert_eq!(
+            snapshot.messages[&vec![1; 20]][&1],
+            MessageStatistics {
+                packets_received: 2,
+                bytes_received: 150,
+                ..MessageStatistics::default()
+            }
+        );
+        assert_eq!(
+            snapshot.community(&[1; 20]),
+            MessageStatistics {
+                packets_sent: 1,
+                bytes_sent: 40,
+                packets_received: 3,
+                bytes_received: 180,
+                decode_errors: 1,
+                signature_failures: 0,
+            }
+        );
+        assert_eq!(snapshot.total().signature_failures, 1);
+        assert_eq!(snapshot.community(&[3; 20]), MessageStatistics::default());
+        assert_eq!(snapshot.unknown_community, 1);
+        assert_eq!(snapshot.undecodable, 0);
+    }
+
+    #[test]
+    fn test_statistics_endpoint() {
+        let statistics = Arc::new(Statistics::new());
+        let endpoint = StatisticsEndpoint::new(localhost_endpoint(), Arc::clone(&statistics));
+        let to = localhost_endpoint().local_address().unwrap();
+
+        endpoint.send(&to, packet(1, 1, 60)).unwrap();
+        endpoint
+            .send_batch(&[(to, packet(1, 1, 60)), (to, packet(1, 2, 80))])
+            .unwrap();
+        // an IPv4 socket can't send to an IPv6 address, packets which were not sent are not counted
+        let ipv6 = Address("[::1]:1".parse().unwrap());
+        assert!(endpoint.send(&ipv6, packet(1, 1, 60)).is_err());
+
+        let snapshot = statistics.snapshot().unwrap();
+        assert_eq!(snapshot.messages[&vec![1; 20]][&1].packets_sent, 2);
+        assert_eq!(snapshot.messages[&vec![1; 20]][&1].bytes_sent, 120);
+        assert_eq!(snapshot.community(&[1; 20]).bytes_sent, 200);
+    }
+}
@@ -0,0 +1,635 @@
+//! Lets communities run code on a timer, like py-ipv8's
+//! [TaskManager](https://github.com/Tribler/py-ipv8/blob/master/ipv8/taskmanager.py).
+//!
+//! Tasks are run by the I/O thread of the [NetworkReceiver](crate::networking::NetworkReceiver) in between polling
+//! for packets, so a task should never block for long.
+//!
+//! The time tasks are scheduled by comes from a [Clock], so a
+//! [SimulatedNetwork](crate::networking::simulation::SimulatedNetwork) can run them on virtual time.
+//!
+//! With the `tokio` feature, tasks can also be futures, see [register_async_task](TaskManager::register_async_task),
+//! and a future can wait for a delay on the clock of the task manager with [sleep](TaskManager::sleep).
+
+use crate::error::Ipv8Error;
+use std::collections::HashMap;
+use std::error::Error;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle;
+#[cfg(feature = "tokio")]
+use tokio::sync::{oneshot, Notify};
+
+/// The number of the next [sleep](TaskManager::sleep), which makes the names of their tasks unique
+#[cfg(feature = "tokio")]
+static NEXT_SLEEP: AtomicU64 = AtomicU64::new(0);
+
+/// The source of the current time for a [TaskManager]
+pub trait Clock: Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> Instant;
+}
+
+/// The real time, as given by [Instant::now]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The function called when a task fires. Errors are logged, a failing repeating task keeps running.
+type Callback = Arc<Mutex<dyn FnMut() -> Result<(), Box<dyn Error>> + Send>>;
+
+/// A registered task
+struct Task {
+    /// Used to tell apart tasks which were registered again under the same name
+    id: u64,
+    /// The function to run
+    callback: Callback,
+    /// The next time this task should run
+    next_run: Instant,
+    /// The time between two runs, or None for a one-shot task
+    interval: Option<Duration>,
+}
+
+/// The registered tasks with the id the next task gets
+#[derive(Default)]
+struct Tasks {
+    /// All pending tasks by name
+    pending: HashMap<String, Task>,
+    /// The ids of the one-shot tasks which are due in the current round but did not run yet, by name. They are no
+    /// longer pending, but cancelling them still stops them from running.
+    due_once: HashMap<String, u64>,
+    /// The id of the next registered task
+    next_id: u64,
+}
+
+/// Keeps track of named tasks and runs them when they are due.
+///
+/// A [TaskManager] is shared between the [NetworkReceiver](crate::networking::NetworkReceiver), which runs the tasks,
+/// and everyone who registers them. It can be obtained with
+/// [NetworkReceiver::task_manager](crate::networking::NetworkReceiver::task_manager).
+pub struct TaskManager {
+    /// The registered tasks
+    tasks: Mutex<Tasks>,
+    /// Tells the time tasks are scheduled by
+    clock: Arc<dyn Clock>,
+    /// Wakes up the [TokioEndpoint](crate::networking::tokioendpoint::TokioEndpoint) running the tasks when a task
+    /// is registered, as it may be due before the task it is waiting for
+    #[cfg(feature = "tokio")]
+    registered: Notify,
+}
+
+impl Default for TaskManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskManager {
+    /// Creates a task manager without any tasks, running on the [SystemClock]
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a task manager without any tasks, running on the given clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            tasks: Mutex::new(Tasks::default()),
+            clock,
+            #[cfg(feature = "tokio")]
+            registered: Notify::new(),
+        }
+    }
+
+    /// Returns the clock the tasks are scheduled by
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    /// Registers a task under a unique name.
+    ///
+    /// - Without a `delay` or `interval` the task runs once, as soon as possible.
+    /// - With only a `delay` the task runs once, after the delay.
+    /// - With an `interval` the task runs every interval until it is cancelled. The first run happens after the
+    ///   `delay`, or immediately when there is none.
+    ///
+    /// Fails with a [TaskExists](Ipv8Error::TaskExists) error when a task with the same name is still pending, and with
+    /// a [ZeroInterval](Ipv8Error::ZeroInterval) error when the `interval` is zero.
+    pub fn register_task<F>(
+        &self,
+        name: &str,
+        task: F,
+        delay: Option<Duration>,
+        interval: Option<Duration>,
+    ) -> Result<(), Ipv8Error>
+    where
+        F: FnMut() -> Result<(), Box<dyn Error>> + Send + 'static,
+    {
+        if interval == Some(Duration::from_millis(0)) {
+            return Err(Ipv8Error::ZeroInterval {
+                name: name.to_owned(),
+            });
+        }
+
+        let mut tasks = self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?;
+        if tasks.pending.contains_key(name) {
+            return Err(Ipv8Error::TaskExists {
+                name: name.to_owned(),
+            });
+        }
+
+        let id = tasks.next_id;
+        tasks.next_id += 1;
+        tasks.pending.insert(
+            name.to_owned(),
+            Task {
+                id,
+                callback: Arc::new(Mutex::new(task)),
+                next_run: self.clock.now() + delay.unwrap_or_default(),
+                interval,
+            },
+        );
+        #[cfg(feature = "tokio")]
+        self.registered.notify_one();
+        Ok(())
+    }
+
+    /// Registers a task like [register_task](TaskManager::register_task), which returns a future every time it is
+    /// due. The future is spawned on the tokio runtime the task was registered from, so it can wait for responses
+    /// without holding up the other tasks. A failing future is logged.
+    ///
+    /// Fails with a [NoRuntime](Ipv8Error::NoRuntime) error outside of a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn register_async_task<F, T>(
+        &self,
+        name: &str,
+        mut task: F,
+        delay: Option<Duration>,
+        interval: Option<Duration>,
+    ) -> Result<(), Ipv8Error>
+    where
+        F: FnMut() -> T + Send + 'static,
+        T: Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'static,
+    {
+        let runtime = Handle::try_current().or(Err(Ipv8Error::NoRuntime))?;
+        let task_name = name.to_owned();
+        self.register_task(
+            name,
+            move || {
+                let future = task();
+                let name = task_name.clone();
+                runtime.spawn(async move {
+                    if let Err(e) = future.await {
+                        warn!("The async task {} failed: {}", name, e);
+                    }
+                });
+                Ok(())
+            },
+            delay,
+            interval,
+        )
+    }
+
+    /// Returns a future which completes once the delay passed on the clock of this task manager, so it follows the
+    /// virtual time of a [SimulatedNetwork](crate::networking::simulation::SimulatedNetwork) as well. It is driven
+    /// by whoever runs the tasks.
+    ///
+    /// The future fails with a [TaskCancelled](Ipv8Error::TaskCancelled) error when all tasks are cancelled before,
+    /// like when IPv8 is stopped.
+    #[cfg(feature = "tokio")]
+    pub fn sleep(
+        &self,
+        delay: Duration,
+    ) -> Result<impl Future<Output = Result<(), Ipv8Error>>, Ipv8Error> {
+        let name = format!("sleep-{}", NEXT_SLEEP.fetch_add(1, Ordering::SeqCst));
+        let (sender, receiver) = oneshot::channel();
+        let mut sender = Some(sender);
+        self.register_task(
+            &name,
+            move || {
+                if let Some(sender) = sender.take() {
+                    // Nobody waits anymore when the future was dropped
+                    let _ = sender.send(());
+                }
+                Ok(())
+            },
+            Some(delay),
+            None,
+        )?;
+        Ok(async move { receiver.await.or(Err(Ipv8Error::TaskCancelled { name })) })
+    }
+
+    /// Completes when a task is registered after the last time this completed
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn task_registered(&self) {
+        self.registered.notified().await
+    }
+
+    /// Cancels the task with the given name, if it is pending, and registers the new task in its place.
+    pub fn replace_task<F>(
+        &self,
+        name: &str,
+        task: F,
+        delay: Option<Duration>,
+        interval: Option<Duration>,
+    ) -> Result<(), Ipv8Error>
+    where
+        F: FnMut() -> Result<(), Box<dyn Error>> + Send + 'static,
+    {
+        self.cancel_pending_task(name)?;
+        self.register_task(name, task, delay, interval)
+    }
+
+    /// Returns true if a task with the given name is registered and will run again
+    pub fn is_pending_task_active(&self, name: &str) -> Result<bool, Ipv8Error> {
+        Ok(self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?
+            .pending
+            .contains_key(name))
+    }
+
+    /// Cancels the task with the given name. Returns true if such a task was pending.
+    ///
+    /// A repeating task may cancel itself while it is running. A task may also cancel a task which is due in the same
+    /// round but did not run yet, which then does not run.
+    pub fn cancel_pending_task(&self, name: &str) -> Result<bool, Ipv8Error> {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?;
+        let due = tasks.due_once.remove(name).is_some();
+        Ok(tasks.pending.remove(name).is_some() || due)
+    }
+
+    /// Cancels all pending tasks
+    pub fn cancel_all_pending_tasks(&self) -> Result<(), Ipv8Error> {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?;
+        tasks.pending.clear();
+        tasks.due_once.clear();
+        Ok(())
+    }
+
+    /// Returns the number of pending tasks
+    pub fn pending_task_count(&self) -> Result<usize, Ipv8Error> {
+        Ok(self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?
+            .pending
+            .len())
+    }
+
+    /// Returns the time until the next task is due, or None when there are no tasks.
+    /// Used to limit how long the [NetworkReceiver](crate::networking::NetworkReceiver) waits for packets.
+    pub fn time_until_next_task(&self) -> Result<Option<Duration>, Ipv8Error> {
+        let now = self.clock.now();
+        Ok(self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?
+            .pending
+            .values()
+            .map(|task| task.next_run.saturating_duration_since(now))
+            .min())
+    }
+
+    /// Runs all tasks which are due and returns how many ran.
+    ///
+    /// Tasks run without holding the lock on the task list, so they can register and cancel tasks themselves.
+    pub fn run_pending_tasks(&self) -> Result<usize, Ipv8Error> {
+        let now = self.clock.now();
+
+        let due: Vec<(String, u64, bool, Callback)> = {
+            let mut tasks = self
+                .tasks
+                .lock()
+                .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?;
+            let due: Vec<(String, u64, bool, Callback)> = tasks
+                .pending
+                .iter()
+                .filter(|(_, task)| task.next_run <= now)
+                .map(|(name, task)| {
+                    let repeating = task.interval.is_some();
+                    (name.clone(), task.id, repeating, Arc::clone(&task.callback))
+                })
+                .collect();
+
+            for (name, _, _, _) in &due {
+                let interval = tasks.pending.get(name).and_then(|task| task.interval);
+                match interval {
+                    Some(interval) => {
+                        if let Some(task) = tasks.pending.get_mut(name) {
+                            task.next_run = now + interval;
+                        }
+                    }
+                    None => {
+                        if let Some(task) = tasks.pending.remove(name) {
+                            tasks.due_once.insert(name.clone(), task.id);
+                        }
+                    }
+                }
+            }
+            due
+        };
+
+        let mut ran = 0;
+        for (name, id, repeating, callback) in due {
+            // A task which ran earlier in this round may have cancelled or replaced this one
+            let still_due = if repeating {
+                self.pending_id(&name)? == Some(id)
+            } else {
+                self.take_due_once(&name, id)?
+            };
+            if !still_due {
+                continue;
+            }
+
+            let result = match callback.lock() {
+                Ok(mut callback) => (*callback)(),
+                Err(_) => Err(Box::new(Ipv8Error::LockPoisoned("the task")) as Box<dyn Error>),
+            };
+            if let Err(e) = result {
+                warn!("Task {} failed: {}", name, e);
+            }
+            ran += 1;
+        }
+        Ok(ran)
+    }
+
+    /// Forgets the one-shot task with the given name and id which is due in this round. Returns false if it was
+    /// cancelled in the meantime.
+    fn take_due_once(&self, name: &str, id: u64) -> Result<bool, Ipv8Error> {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?;
+        if tasks.due_once.get(name) == Some(&id) {
+            tasks.due_once.remove(name);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Returns the id of the pending task with the given name
+    fn pending_id(&self, name: &str) -> Result<Option<u64>, Ipv8Error> {
+        Ok(self
+            .tasks
+            .lock()
+            .or(Err(Ipv8Error::LockPoisoned("the registered tasks")))?
+            .pending
+            .get(name)
+            .map(|task| task.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::simulation::VirtualClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn virtual_manager() -> (Arc<VirtualClock>, TaskManager) {
+        let clock = Arc::new(VirtualClock::new());
+        let manager = TaskManager::with_clock(Arc::clone(&clock) as Arc<dyn Clock>);
+        (clock, manager)
+    }
+
+    fn counter() -> (
+        Arc<AtomicUsize>,
+        impl FnMut() -> Result<(), Box<dyn Error>> + Send,
+    ) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let task_count = Arc::clone(&count);
+        (count, move || {
+            task_count.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_one_shot() {
+        let (clock, manager) = virtual_manager();
+        let (count, task) = counter();
+        manager
+            .register_task("once", task, Some(Duration::from_millis(10)), None)
+            .unwrap();
+
+        assert_eq!(manager.run_pending_tasks().unwrap(), 0);
+        assert!(manager.is_pending_task_active("once").unwrap());
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(manager.run_pending_tasks().unwrap(), 1);
+        assert_eq!(manager.run_pending_tasks().unwrap(), 0);
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(!manager.is_pending_task_active("once").unwrap());
+    }
+
+    #[test]
+    fn test_repeating() {
+        let (clock, manager) = virtual_manager();
+        let (count, task) = counter();
+        manager
+            .register_task("repeat", task, None, Some(Duration::from_millis(10)))
+            .unwrap();
+
+        // runs immediately, then every interval
+        manager.run_pending_tasks().unwrap();
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        clock.advance(Duration::from_millis(9));
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        clock.advance(Duration::from_millis(1));
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        assert!(manager.cancel_pending_task("repeat").unwrap());
+        clock.advance(Duration::from_millis(10));
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(!manager.cancel_pending_task("repeat").unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_name() {
+        let manager = TaskManager::new();
+        manager
+            .register_task("task", || Ok(()), None, None)
+            .unwrap();
+        assert!(manager
+            .register_task("task", || Ok(()), None, None)
+            .is_err());
+
+        let (count, task) = counter();
+        manager.replace_task("task", task, None, None).unwrap();
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // once a one-shot task ran, its name can be used again
+        manager
+            .register_task("task", || Ok(()), None, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_zero_interval() {
+        let manager = TaskManager::new();
+        assert!(manager
+            .register_task("busy", || Ok(()), None, Some(Duration::from_millis(0)))
+            .is_err());
+        assert_eq!(manager.pending_task_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cancel_from_task() {
+        let (clock, manager) = virtual_manager();
+        let manager = Arc::new(manager);
+        let (count, mut task) = counter();
+        let task_manager = Arc::clone(&manager);
+        manager
+            .register_task(
+                "self-cancelling",
+                move || {
+                    task()?;
+                    task_manager.cancel_pending_task("self-cancelling")?;
+                    Ok(())
+                },
+                None,
+                Some(Duration::from_millis(1)),
+            )
+            .unwrap();
+
+        manager.run_pending_tasks().unwrap();
+        clock.advance(Duration::from_millis(1));
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(manager.pending_task_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cancel_due_task() {
+        let manager = Arc::new(TaskManager::new());
+        let (count, task) = counter();
+        let task = Arc::new(Mutex::new(task));
+        for (name, other) in &[("a", "b"), ("b", "a")] {
+            let task_manager = Arc::clone(&manager);
+            let task = Arc::clone(&task);
+            manager
+                .register_task(
+                    name,
+                    move || {
+                        (*task.lock().unwrap())()?;
+                        assert!(task_manager.cancel_pending_task(other)?);
+                        Ok(())
+                    },
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // whichever one-shot task runs first, the other is cancelled before it runs
+        assert_eq!(manager.run_pending_tasks().unwrap(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(manager.run_pending_tasks().unwrap(), 0);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_sleep() {
+        let (clock, manager) = virtual_manager();
+        let woken = manager.sleep(Duration::from_secs(10)).unwrap();
+        let cancelled = manager.sleep(Duration::from_secs(20)).unwrap();
+
+        clock.advance(Duration::from_secs(10));
+        manager.run_pending_tasks().unwrap();
+        woken.await.unwrap();
+
+        // stopping IPv8 cancels all tasks, which wakes up everyone still waiting
+        manager.cancel_all_pending_tasks().unwrap();
+        assert!(cancelled.await.is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_task() {
+        let (clock, manager) = virtual_manager();
+        let (sender, mut runs) = tokio::sync::mpsc::unbounded_channel();
+        manager
+            .register_async_task(
+                "async",
+                move || {
+                    let sender = sender.clone();
+                    async move {
+                        tokio::task::yield_now().await;
+                        sender.send(())?;
+                        Ok(())
+                    }
+                },
+                None,
+                Some(Duration::from_secs(1)),
+            )
+            .unwrap();
+
+        manager.run_pending_tasks().unwrap();
+        clock.advance(Duration::from_secs(1));
+        manager.run_pending_tasks().unwrap();
+        assert_eq!(runs.recv().await, Some(()));
+        assert_eq!(runs.recv().await, Some(()));
+
+        manager.cancel_all_pending_tasks().unwrap();
+        assert_eq!(runs.recv().await, None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_task_without_runtime() {
+        let manager = TaskManager::new();
+        assert!(manager
+            .register_async_task("async", || async { Ok(()) }, None, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_time_until_next_task() {
+        let (clock, manager) = virtual_manager();
+        assert_eq!(manager.time_until_next_task().unwrap(), None);
+
+        manager
+            .register_task("later", || Ok(()), Some(Duration::from_secs(10)), None)
+            .unwrap();
+        manager
+            .register_task("sooner", || Ok(()), Some(Duration::from_secs(1)), None)
+            .unwrap();
+        assert_eq!(
+            manager.time_until_next_task().unwrap(),
+            Some(Duration::from_secs(1))
+        );
+        clock.advance(Duration::from_millis(400));
+        assert_eq!(
+            manager.time_until_next_task().unwrap(),
+            Some(Duration::from_millis(600))
+        );
+
+        manager.cancel_all_pending_tasks().unwrap();
+        assert_eq!(manager.time_until_next_task().unwrap(), None);
+    }
+}
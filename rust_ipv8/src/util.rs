@@ -1,18 +1,19 @@
 //! Various utility functions to be used in conjunction with ipv8
 
-use std::error::Error;
-
-create_error!(ConversionError, "Converting to a fixed size array failed.");
+use crate::error::Ipv8Error;
 
 /// Helper function types which have the [FromBytes](zerocopy::FromBytes) trait to be converted to some fixed size variant.
 /// Doesn't copy.
-pub fn as_fixed_size<T>(data: &[u8]) -> Result<&T, Box<dyn Error>>
+pub fn as_fixed_size<T>(data: &[u8]) -> Result<&T, Ipv8Error>
 where
     T: zerocopy::FromBytes,
 {
     Ok(
-        (zerocopy::LayoutVerified::<_, T>::new(data).ok_or_else(|| Box::new(ConversionError))?)
-            .into_ref(),
+        (zerocopy::LayoutVerified::<_, T>::new(data).ok_or(Ipv8Error::Conversion {
+            expected: std::mem::size_of::<T>(),
+            actual: data.len(),
+        })?)
+        .into_ref(),
     )
 }
 
@@ -30,18 +31,21 @@ mod tests {
     #[test]
     fn test_as_fixed_size_invalid_too_large() {
         let data = &[0u8, 1u8, 2u8];
-        let fixed: Result<&[u8; 4], Box<dyn Error>> = as_fixed_size(data);
+        let fixed: Result<&[u8; 4], Ipv8Error> = as_fixed_size(data);
 
         match fixed {
             Ok(_) => assert!(false),
-            Err(_) => assert!(true),
+            Err(Ipv8Error::Conversion { expected, actual }) => {
+                assert_eq!((expected, actual), (4, 3))
+            }
+            Err(_) => assert!(false),
         };
     }
 
     #[test]
     fn test_as_fixed_size_invalid_too_small() {
         let data = &[0u8, 1u8, 2u8];
-        let fixed: Result<&[u8; 2], Box<dyn Error>> = as_fixed_size(data);
+        let fixed: Result<&[u8; 2], Ipv8Error> = as_fixed_size(data);
 
         match fixed {
             Ok(_) => assert!(false),
@@ -12,7 +12,10 @@ fn community_integration_test() {
     use rust_ipv8::IPv8;
     use rust_ipv8::configuration::Config;
     use rust_ipv8::serialization::header::HeaderVersion::PyIPV8Header;
-    use rust_ipv8::networking::NetworkSender;
+    use rust_ipv8::networking::endpoint::Endpoint;
+    use rust_ipv8::taskmanager::TaskManager;
+    use std::sync::Arc;
+    use rust_ipv8::payloads::binmemberauthenticationpayload::BinMemberAuthenticationPayload;
 
     pub struct TestCommunity {
         peer: Peer,
@@ -21,7 +24,10 @@ fn community_integration_test() {
     impl TestCommunity {}
 
     impl Community for TestCommunity {
-        fn new(_endpoint: &NetworkSender) -> Result<Self, Box<dyn Error>> {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
             let pk: KeyPair = KeyPair::from_seed_unchecked(&[
                 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
                 23, 24, 25, 26, 27, 28, 29, 30, 31,
@@ -34,6 +40,7 @@ fn community_integration_test() {
                     pk.public_key().unwrap(),
                     Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 42)),
                     true,
+                    task_manager.clock().now(),
                 ),
             })
         }
@@ -60,16 +67,16 @@ fn community_integration_test() {
     }
 
     let config = Config::default();
-    let mut ipv8 = IPv8::new(config).unwrap();
+    let ipv8 = IPv8::new(config).unwrap();
 
-    let community = TestCommunity::new(&ipv8.network_sender).unwrap();
+    let community = TestCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap();
     let mid = community.get_mid();
     ipv8.communities.add_community(Box::new(community)).unwrap();
 
     // now simulate a packet coming in
 
     // Create a packet to test the community with
-    let packet = Packet::new(Header {
+    let mut packet = Packet::new(Header {
         size: 23,
         version: PyIPV8Header,
         mid_hash: Some(mid),
@@ -77,7 +84,15 @@ fn community_integration_test() {
     })
     .unwrap();
 
-    // Normally you would want to sign the packet here
+    // Sign the packet, communities only receive signed messages by default
+    let keypair = KeyPair::new().unwrap();
+    packet
+        .add(&BinMemberAuthenticationPayload {
+            public_key_bin: keypair.public_key().unwrap(),
+            encryption_key_bin: [0; 32],
+        })
+        .unwrap();
+    let packet = packet.sign(&keypair).unwrap();
 
     // Send the packet
     ipv8.communities
@@ -0,0 +1,92 @@
+#[test]
+fn stop_and_restart_test() {
+    use rust_ipv8::community::Community;
+    use rust_ipv8::configuration::Config;
+    use rust_ipv8::networking::address::Address;
+    use rust_ipv8::networking::endpoint::Endpoint;
+    use rust_ipv8::taskmanager::TaskManager;
+    use rust_ipv8::serialization::header::Header;
+    use rust_ipv8::serialization::PacketDeserializer;
+    use rust_ipv8::IPv8;
+    use std::error::Error;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    static UNLOADED: AtomicUsize = AtomicUsize::new(0);
+
+    struct TestCommunity;
+
+    impl Community for TestCommunity {
+        fn new(
+            _endpoint: &Arc<dyn Endpoint>,
+            _task_manager: &Arc<TaskManager>,
+        ) -> Result<Self, Box<dyn Error>> {
+            Ok(TestCommunity)
+        }
+
+        fn get_mid(&self) -> Vec<u8> {
+            vec![42; 20]
+        }
+
+        fn unload(&self) -> Result<(), Box<dyn Error>> {
+            UNLOADED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_receive(
+            &self,
+            _header: Header,
+            _deserializer: PacketDeserializer,
+            _address: Address,
+        ) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+    }
+
+    let mut config = Config {
+        sending_address: Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+        // Don't walk to the bootstrap servers from a test
+        default_hosts: vec![],
+        ..Config::default()
+    };
+
+    let ipv8 = IPv8::new(config.clone()).unwrap();
+    let community = TestCommunity::new(&ipv8.endpoint, &ipv8.task_manager).unwrap();
+    ipv8.communities.add_community(Box::new(community)).unwrap();
+    let address = ipv8.endpoint.local_address().unwrap();
+
+    let handle = ipv8.start().unwrap();
+    let endpoint = Arc::clone(&handle.endpoint);
+    handle.stop().unwrap();
+    assert!(!endpoint.is_open());
+    assert_eq!(UNLOADED.load(Ordering::SeqCst), 1);
+    drop(endpoint);
+
+    // The socket is released, so IPv8 can start again on the same port
+    config.sending_address = address;
+    let ipv8 = IPv8::new(config).unwrap();
+    ipv8.start().unwrap().stop().unwrap();
+}
+
+#[test]
+fn drop_without_start_test() {
+    use rust_ipv8::configuration::Config;
+    use rust_ipv8::networking::address::Address;
+    use rust_ipv8::IPv8;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    let mut config = Config {
+        sending_address: Address(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+        default_hosts: vec![],
+        ..Config::default()
+    };
+
+    let ipv8 = IPv8::new(config.clone()).unwrap();
+    config.sending_address = ipv8.endpoint.local_address().unwrap();
+    drop(ipv8);
+
+    // The socket of an instance which never started is released as well
+    let ipv8 = IPv8::new(config).unwrap();
+    ipv8.start().unwrap().stop().unwrap();
+}